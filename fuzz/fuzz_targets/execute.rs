@@ -0,0 +1,8 @@
+// Loads and runs arbitrary bytes under every platform and layout; see
+// src/fuzz.rs for what the input means and what is checked.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| chip8::fuzz::execute(data));
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::symbols::Symbols;
+use crate::{parse_number, Chip8};
+
+// Candidates listed after a search; the rest are only counted.
+const SHOWN: usize = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    Memory(usize),
+    Register(usize),
+}
+
+impl Target {
+    // "V3", or an address as a number or symbol like "lives+1".
+    fn parse(text: &str, symbols: &Symbols) -> Result<Target, String> {
+        if let Some(n) = text.strip_prefix(['V', 'v']) {
+            if let Ok(n) = u8::from_str_radix(n, 16) {
+                if n < 16 && text.len() == 2 {
+                    return Ok(Target::Register(n as usize));
+                }
+            }
+        }
+        symbols
+            .resolve(text)
+            .map(Target::Memory)
+            .ok_or_else(|| format!("\"{}\" is not a register, address or symbol", text))
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Target::Memory(address) => format!("0x{:03X}", address),
+            Target::Register(n) => format!("V{:X}", n),
+        }
+    }
+
+    fn get(&self, chip8: &Chip8) -> Option<u8> {
+        match *self {
+            Target::Memory(address) => chip8.memory.get(address).copied(),
+            Target::Register(n) => Some(chip8.registry[n]),
+        }
+    }
+}
+
+// A register or memory byte held at a value.
+#[derive(Clone)]
+pub struct Cheat {
+    pub name: String,
+    pub target: Target,
+    pub value: u8,
+}
+
+impl Cheat {
+    // "0x3F0=5", "lives=5" or "V3=9".
+    fn parse(name: &str, text: &str, symbols: &Symbols) -> Result<Cheat, String> {
+        let (target, value) = text
+            .split_once('=')
+            .ok_or_else(|| format!("\"{}\" should look like 0x3F0=5 or V3=9", text))?;
+        let value = parse_number(value.trim())
+            .ok()
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| format!("\"{}\" is not a byte value", value.trim()))?;
+        Ok(Cheat {
+            name: name.to_string(),
+            target: Target::parse(target.trim(), symbols)?,
+            value,
+        })
+    }
+
+    fn text(&self) -> String {
+        format!("{}={}", self.target.name(), self.value)
+    }
+}
+
+// Addresses narrowed down across snapshots of memory.
+struct Search {
+    snapshot: Vec<u8>,
+    candidates: Vec<usize>,
+}
+
+// Cheat files have a table per ROM, by SHA-1, of names and cheats:
+//
+//   [3f1a...]
+//   lives = "0x3F0=5"
+//   level = "V3=9"
+type CheatFile = BTreeMap<String, BTreeMap<String, String>>;
+
+pub struct Cheats {
+    pub enabled: bool,
+    cheats: Vec<Cheat>,
+    search: Option<Search>,
+    // The file and the ROM's SHA-1 they are saved under.
+    file: Option<(PathBuf, String)>,
+}
+
+impl Default for Cheats {
+    fn default() -> Cheats {
+        Cheats {
+            enabled: true,
+            cheats: Vec::new(),
+            search: None,
+            file: None,
+        }
+    }
+}
+
+impl Cheats {
+    // A ROM's cheats from the file, which need not exist yet.
+    pub fn load(path: &Path, sha1: &str, symbols: &Symbols) -> Result<Cheats, String> {
+        let file = read_file(path)?;
+        let cheats = file
+            .get(sha1)
+            .into_iter()
+            .flatten()
+            .map(|(name, text)| Cheat::parse(name, text, symbols))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("{}: [{}]: {}", path.display(), sha1, e))?;
+        Ok(Cheats {
+            cheats,
+            file: Some((path.to_path_buf(), sha1.to_string())),
+            ..Cheats::default()
+        })
+    }
+
+    // Writes the ROM's table, keeping the other ROMs'.
+    fn save(&self) -> Result<String, String> {
+        let (path, sha1) = self.file.as_ref().ok_or("no cheat file for this ROM")?;
+        let mut file = read_file(path)?;
+        let table = self
+            .cheats
+            .iter()
+            .map(|cheat| (cheat.name.clone(), cheat.text()))
+            .collect();
+        file.insert(sha1.clone(), table);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let text = toml::to_string(&file).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(format!(
+            "saved {} cheats to {}",
+            self.cheats.len(),
+            path.display()
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    // Puts every frozen value back; called once a frame.
+    pub fn apply(&self, memory: &mut [u8], registers: &mut [u8; 16]) {
+        if !self.enabled {
+            return;
+        }
+        for cheat in &self.cheats {
+            match cheat.target {
+                Target::Memory(address) => {
+                    if let Some(byte) = memory.get_mut(address) {
+                        *byte = cheat.value;
+                    }
+                }
+                Target::Register(n) => registers[n] = cheat.value,
+            }
+        }
+    }
+
+    fn list(&self) -> String {
+        if self.cheats.is_empty() {
+            return "no cheats".to_string();
+        }
+        let state = if self.enabled { "on" } else { "off" };
+        let lines: Vec<String> = self
+            .cheats
+            .iter()
+            .map(|cheat| format!("{}  {}", cheat.name, cheat.text()))
+            .collect();
+        format!("cheats {}:\n{}", state, lines.join("\n"))
+    }
+}
+
+fn read_file(path: &Path) -> Result<CheatFile, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(_) if !path.exists() => Ok(CheatFile::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+// The help for the commands below.
+pub const HELP: &str = "\
+search                 start a memory search
+search equal [N]       keep bytes unchanged since the last search, or equal to N
+search changed         keep bytes that changed
+search increased       keep bytes that went up
+search decreased       keep bytes that went down
+freeze [NAME] T[=N]    hold T (an address, symbol or V0-VF) at N, or its value now
+unfreeze NAME|all      drop a cheat, or all of them
+cheats [on|off]        list cheats, or turn them all on or off
+cheats save            save the ROM's cheats to the cheat file";
+
+// Runs a cheat command typed in a debugger, or None if the text is not
+// one, so it can be taken as something else.
+pub fn command(chip8: &mut Chip8, text: &str) -> Option<Result<String, String>> {
+    let mut words = text.split_whitespace();
+    let command = words.next()?;
+    let arguments: Vec<&str> = words.collect();
+    Some(match (command, arguments.as_slice()) {
+        ("search", filter) => search(chip8, filter),
+        ("freeze", arguments) => freeze(chip8, arguments),
+        ("unfreeze", ["all"]) => {
+            chip8.cheats.cheats.clear();
+            Ok("removed all cheats".to_string())
+        }
+        ("unfreeze", [name]) => {
+            let before = chip8.cheats.cheats.len();
+            chip8.cheats.cheats.retain(|cheat| cheat.name != *name);
+            if chip8.cheats.cheats.len() < before {
+                Ok(format!("removed {}", name))
+            } else {
+                Err(format!("no cheat named \"{}\"", name))
+            }
+        }
+        ("cheats", []) => Ok(chip8.cheats.list()),
+        ("cheats", ["on" | "off"]) => {
+            chip8.cheats.enabled = arguments[0] == "on";
+            Ok(format!("cheats {}", arguments[0]))
+        }
+        ("cheats", ["save"]) => chip8.cheats.save(),
+        ("unfreeze" | "cheats", _) => Err(format!("usage:\n{}", HELP)),
+        _ => return None,
+    })
+}
+
+fn search(chip8: &mut Chip8, filter: &[&str]) -> Result<String, String> {
+    let keep: Box<dyn Fn(u8, u8) -> bool> = match filter {
+        [] => {
+            chip8.cheats.search = Some(Search {
+                snapshot: chip8.memory.clone(),
+                candidates: (0..chip8.memory.len()).collect(),
+            });
+            return Ok(format!(
+                "searching {} bytes; change something and narrow it down",
+                chip8.memory.len()
+            ));
+        }
+        ["equal"] => Box::new(|old, new| new == old),
+        ["equal", value] => {
+            let value = parse_number(value)
+                .ok()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| format!("\"{}\" is not a byte value", value))?;
+            Box::new(move |_, new| new == value)
+        }
+        ["changed"] => Box::new(|old, new| new != old),
+        ["increased"] => Box::new(|old, new| new > old),
+        ["decreased"] => Box::new(|old, new| new < old),
+        _ => return Err(format!("usage:\n{}", HELP)),
+    };
+    let search = chip8
+        .cheats
+        .search
+        .as_mut()
+        .ok_or("no search started; run \"search\" first")?;
+    let memory = &chip8.memory;
+    search
+        .candidates
+        .retain(|address| keep(search.snapshot[*address], memory[*address]));
+    search.snapshot.clone_from(memory);
+
+    let shown: Vec<String> = search
+        .candidates
+        .iter()
+        .take(SHOWN)
+        .map(|address| format!("{}={}", chip8.symbols.describe(*address), memory[*address]))
+        .collect();
+    let mut text = format!("{} candidates", search.candidates.len());
+    if !shown.is_empty() {
+        text += &format!(":\n{}", shown.join("\n"));
+    }
+    if search.candidates.len() > SHOWN {
+        text += "\n...";
+    }
+    Ok(text)
+}
+
+fn freeze(chip8: &mut Chip8, arguments: &[&str]) -> Result<String, String> {
+    let (name, text) = match arguments {
+        [text] => (None, *text),
+        [name, text] => (Some(*name), *text),
+        _ => return Err(format!("usage:\n{}", HELP)),
+    };
+    // Without a value the target is held at what it is now.
+    let cheat = match text.split_once('=') {
+        Some(_) => Cheat::parse(name.unwrap_or_default(), text, &chip8.symbols)?,
+        None => {
+            let target = Target::parse(text, &chip8.symbols)?;
+            let value = target
+                .get(chip8)
+                .ok_or_else(|| format!("{} is outside memory", target.name()))?;
+            Cheat {
+                name: String::new(),
+                target,
+                value,
+            }
+        }
+    };
+    if let Target::Memory(address) = cheat.target {
+        if address >= chip8.memory.len() {
+            return Err(format!("0x{:03X} is outside memory", address));
+        }
+    }
+    let cheat = Cheat {
+        name: name.map_or_else(|| cheat.target.name(), str::to_string),
+        ..cheat
+    };
+    let text = format!("froze {} at {}", cheat.target.name(), cheat.value);
+    let cheats = &mut chip8.cheats.cheats;
+    match cheats.iter_mut().find(|old| old.name == cheat.name) {
+        Some(old) => *old = cheat,
+        None => cheats.push(cheat),
+    }
+    Ok(text)
+}
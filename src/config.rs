@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::font::FontPreset;
+use crate::keymap::KeyMap;
+use crate::quirks::{LayoutOverrides, Platform, QuirkOverrides};
+use crate::romdb::parse_color;
+
+pub const SCALE: u32 = 15;
+pub const FRAME_RATE: f32 = 60.0;
+pub const FAST_FORWARD: f32 = 4.0;
+
+// One layer of settings. Unset fields fall through to the layer below:
+// built-in defaults, the global file, the ROM database and any options
+// stored with the ROM, the file's per-ROM section and finally the command
+// line.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    // Window pixels per chip8 pixel.
+    pub scale: Option<u32>,
+    // Frames per second, which also drives the delay and sound timers.
+    pub frame_rate: Option<f32>,
+    pub fast_forward: Option<f32>,
+    pub wait_key_press: Option<bool>,
+    pub platform: Option<Platform>,
+    pub ipf: Option<u32>,
+    // Background and foreground as "#RRGGBB".
+    pub palette: Option<[String; 2]>,
+    pub romdb: Option<PathBuf>,
+    pub keymap: Option<PathBuf>,
+    pub font: Option<FontPreset>,
+    // A font binary, used over the preset's glyphs.
+    pub font_file: Option<PathBuf>,
+    // Names for addresses, added to any an assembled ROM brings.
+    pub symbols: Option<PathBuf>,
+    // Frozen values for each ROM; see cheat.rs.
+    pub cheats: Option<PathBuf>,
+    pub quirks: Option<QuirkOverrides>,
+    // Program start, font address, memory size and 64x64 screen.
+    pub layout: Option<LayoutOverrides>,
+    // Host keys for each chip8 key, as in a --keymap file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Vec<String>>,
+    // Per-ROM sections, keyed by file name without extension or by SHA-1.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rom: BTreeMap<String, Settings>,
+}
+
+impl Settings {
+    // The file given, or chip8/config.toml in the user's config directory.
+    // A missing default file is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Settings, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match dirs::config_dir() {
+                Some(dir) => (dir.join("chip8").join("config.toml"), false),
+                None => return Ok(Settings::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) if !required && !path.exists() => return Ok(Settings::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let settings: Settings =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        settings
+            .validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.check_values()?;
+        for (name, section) in &self.rom {
+            let field = if section.scale.is_some() {
+                Some("scale")
+            } else if section.romdb.is_some() {
+                Some("romdb")
+            } else if !section.rom.is_empty() {
+                Some("rom")
+            } else {
+                None
+            };
+            if let Some(field) = field {
+                return Err(format!(
+                    "[rom.\"{}\"]: `{}` can only be set globally",
+                    name, field
+                ));
+            }
+            section
+                .check_values()
+                .map_err(|e| format!("[rom.\"{}\"]: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn check_values(&self) -> Result<(), String> {
+        if let Some(scale) = self.scale {
+            if !(1..=64).contains(&scale) {
+                return Err(format!("scale must be from 1 to 64, not {}", scale));
+            }
+        }
+        if let Some(frame_rate) = self.frame_rate {
+            if !(1.0..=1000.0).contains(&frame_rate) {
+                return Err(format!(
+                    "frame_rate must be from 1 to 1000, not {}",
+                    frame_rate
+                ));
+            }
+        }
+        if let Some(fast_forward) = self.fast_forward {
+            if !(fast_forward > 0.0 && fast_forward <= 100.0) {
+                return Err(format!(
+                    "fast_forward must be above 0 and at most 100, not {}",
+                    fast_forward
+                ));
+            }
+        }
+        if self.ipf == Some(0) {
+            return Err("ipf must be at least 1".to_string());
+        }
+        if let Some(layout) = &self.layout {
+            layout.check().map_err(|e| format!("layout: {}", e))?;
+        }
+        if self.palette().is_none() && self.palette.is_some() {
+            return Err("palette colors must look like #RRGGBB".to_string());
+        }
+        KeyMap::default()
+            .apply(&self.keys)
+            .map_err(|e| format!("keys: {}", e))
+    }
+
+    pub fn palette(&self) -> Option<[(u8, u8, u8); 2]> {
+        let [background, foreground] = self.palette.as_ref()?;
+        Some([parse_color(background)?, parse_color(foreground)?])
+    }
+
+    // Layers other on top of self.
+    pub fn merge(&mut self, other: &Settings) {
+        macro_rules! take {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            };
+        }
+        take!(
+            scale,
+            frame_rate,
+            fast_forward,
+            wait_key_press,
+            platform,
+            ipf,
+            palette,
+            romdb,
+            keymap,
+            font,
+            font_file,
+            symbols,
+            cheats
+        );
+
+        if let Some(other) = &other.quirks {
+            let quirks = self.quirks.get_or_insert_with(QuirkOverrides::default);
+            quirks.merge(other);
+        }
+        if let Some(other) = &other.layout {
+            let layout = self.layout.get_or_insert_with(LayoutOverrides::default);
+            layout.merge(other);
+        }
+        for (key, names) in &other.keys {
+            self.keys.insert(key.clone(), names.clone());
+        }
+    }
+
+    // The first section matching one of a ROM's names.
+    pub fn section(&self, names: &[String]) -> Option<&Settings> {
+        names.iter().find_map(|name| self.rom.get(name))
+    }
+
+    // The global settings with the options stored with a ROM, its section
+    // and the command line on top.
+    pub fn for_rom(&self, names: &[String], options: &Settings, cli: &Settings) -> Settings {
+        let mut settings = Settings {
+            rom: BTreeMap::new(),
+            ..self.clone()
+        };
+        settings.merge(options);
+        if let Some(section) = self.section(names) {
+            settings.merge(section);
+        }
+        settings.merge(cli);
+        settings
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale.unwrap_or(SCALE)
+    }
+
+    pub fn frame_rate(&self) -> f32 {
+        self.frame_rate.unwrap_or(FRAME_RATE)
+    }
+
+    pub fn fast_forward(&self) -> f32 {
+        self.fast_forward.unwrap_or(FAST_FORWARD)
+    }
+
+    // The cheat file given, or chip8/cheats.toml in the user's config
+    // directory.
+    pub fn cheat_file(&self) -> Option<PathBuf> {
+        self.cheats
+            .clone()
+            .or_else(|| Some(dirs::config_dir()?.join("chip8").join("cheats.toml")))
+    }
+
+    pub fn wait_key_press(&self) -> bool {
+        self.wait_key_press.unwrap_or(false)
+    }
+
+    // Everything with a built-in default filled in, for --print-config.
+    pub fn effective(&self) -> Settings {
+        Settings {
+            scale: Some(self.scale()),
+            frame_rate: Some(self.frame_rate()),
+            fast_forward: Some(self.fast_forward()),
+            wait_key_press: Some(self.wait_key_press()),
+            ..self.clone()
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipf(ipf: u32) -> Settings {
+        Settings {
+            ipf: Some(ipf),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn rom_options_sit_between_the_global_settings_and_the_rom_section() {
+        let mut config = ipf(10);
+        let names = ["game".to_string()];
+        assert_eq!(
+            config.for_rom(&names, &ipf(20), &Settings::default()).ipf,
+            Some(20)
+        );
+
+        config.rom.insert("game".to_string(), ipf(30));
+        assert_eq!(
+            config.for_rom(&names, &ipf(20), &Settings::default()).ipf,
+            Some(30)
+        );
+        assert_eq!(config.for_rom(&names, &ipf(20), &ipf(40)).ipf, Some(40));
+    }
+}
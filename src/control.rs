@@ -0,0 +1,108 @@
+const SLOW_MOTION: [f32; 2] = [0.5, 0.25];
+
+pub struct Control {
+    pub paused: bool,
+    advance: bool,
+    fast_forward: bool,
+    pub fast_forward_speed: f32,
+    slow_motion: Option<usize>,
+    // Instructions run in the last frame at normal speed.
+    normal_ipf: Option<u32>,
+}
+
+impl Control {
+    pub fn new(fast_forward_speed: f32) -> Control {
+        Control {
+            paused: false,
+            advance: false,
+            fast_forward: false,
+            fast_forward_speed,
+            slow_motion: None,
+            normal_ipf: None,
+        }
+    }
+
+    // Multiplier applied to the 60Hz frame rate, and so to timers and instructions alike.
+    pub fn speed(&self) -> f32 {
+        if self.fast_forward {
+            self.fast_forward_speed
+        } else {
+            self.slow_motion.map_or(1.0, |n| SLOW_MOTION[n])
+        }
+    }
+
+    // Instructions allowed in a frame. Without a set IPF the CPU runs flat
+    // out, so a speed change holds it to what it ran per frame at normal
+    // speed; stretching or squeezing the frames then scales it with the
+    // timers.
+    pub fn ipf(&self, ipf: Option<u32>) -> Option<u32> {
+        if self.speed() == 1.0 {
+            ipf
+        } else {
+            ipf.or(self.normal_ipf)
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        !self.paused || self.advance
+    }
+
+    pub fn toggle_pause(&mut self) -> String {
+        self.paused = !self.paused;
+        self.advance = false;
+        if self.paused { "Paused" } else { "Resumed" }.to_string()
+    }
+
+    // Stops where the program is, as at a breakpoint.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.advance = false;
+    }
+
+    // Runs until the end of the next frame, then pauses again.
+    pub fn advance(&mut self) {
+        self.paused = true;
+        self.advance = true;
+    }
+
+    pub fn frame_done(&mut self, instructions: u32) {
+        if self.speed() == 1.0 && !self.paused {
+            self.normal_ipf = Some(instructions.max(1));
+        }
+        self.advance = false;
+    }
+
+    pub fn set_fast_forward(&mut self, on: bool) -> Option<String> {
+        if self.fast_forward == on {
+            return None;
+        }
+        self.fast_forward = on;
+        Some(self.speed_message())
+    }
+
+    pub fn cycle_slow_motion(&mut self) -> String {
+        self.slow_motion = match self.slow_motion {
+            None => Some(0),
+            Some(n) if n + 1 < SLOW_MOTION.len() => Some(n + 1),
+            Some(_) => None,
+        };
+        self.speed_message()
+    }
+
+    fn speed_message(&self) -> String {
+        format!("Speed {}%", (self.speed() * 100.0).round())
+    }
+
+    // Shown in the corner of the screen while not running at normal speed.
+    pub fn status(&self) -> Option<String> {
+        if self.paused {
+            Some("PAUSED".to_string())
+        } else if self.fast_forward {
+            Some(format!(">> {}%", (self.speed() * 100.0).round()))
+        } else if self.slow_motion.is_some() {
+            Some(format!("SLOW {}%", (self.speed() * 100.0).round()))
+        } else {
+            None
+        }
+    }
+}
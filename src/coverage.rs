@@ -0,0 +1,178 @@
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::MemoryAccess;
+use crate::{Chip8, RawOpCode};
+
+// Heatmap rows hold this many addresses, each drawn as a square of CELL pixels.
+const ROW: usize = 64;
+const CELL: usize = 8;
+
+// How many times each address was run as code, read through I by Fx65 or
+// Dxyn, and written by Fx55 or Fx33.
+pub struct Coverage {
+    executed: Vec<u32>,
+    read: Vec<u32>,
+    written: Vec<u32>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![0; 0x10000],
+            read: vec![0; 0x10000],
+            written: vec![0; 0x10000],
+        }
+    }
+
+    // Notes an instruction that ran at pc and the memory it touched.
+    pub fn record(&mut self, pc: usize, access: Option<MemoryAccess>) {
+        for address in pc..pc + 2 {
+            bump(&mut self.executed, address);
+        }
+        if let Some(access) = access {
+            let counts = if access.write {
+                &mut self.written
+            } else {
+                &mut self.read
+            };
+            for address in access.start..access.start + access.len {
+                bump(counts, address);
+            }
+        }
+    }
+
+    // Bytes of the ROM that were run, read or written at all.
+    pub fn summary(&self, chip8: &Chip8) -> String {
+        let rom = chip8.layout.start..chip8.end;
+        let count = |counts: &[u32]| rom.clone().filter(|a| counts[*a] > 0).count();
+        let used = rom.clone().filter(|a| self.touched(*a)).count();
+        let size = rom.len().max(1);
+        format!(
+            "ROM coverage: {} of {} bytes used ({:.1}%): {} executed, {} read as data, {} written",
+            used,
+            rom.len(),
+            used as f64 * 100.0 / size as f64,
+            count(&self.executed),
+            count(&self.read),
+            count(&self.written)
+        )
+    }
+
+    fn touched(&self, address: usize) -> bool {
+        self.executed[address] > 0 || self.read[address] > 0 || self.written[address] > 0
+    }
+
+    // Disassembles the ROM as it was at the end of the run: bytes that ran
+    // as instructions are decoded, everything else is listed as data.
+    // Each line shows what happened to it: x(ecuted), r(ead) and
+    // w(ritten), with counts.
+    pub fn save_disassembly(&self, chip8: &Chip8, path: &Path) -> Result<(), String> {
+        let symbols = &chip8.symbols;
+        let mut lines = vec![format!("; {}", self.summary(chip8))];
+        let mut address = chip8.layout.start;
+        while address < chip8.end {
+            if let Some(name) = symbols.label(address) {
+                lines.push(format!("{}:", name));
+            }
+            let byte = chip8.memory[address];
+            let (bytes, text, size) = if self.executed[address] > 0 && address + 1 < chip8.end {
+                let raw = RawOpCode {
+                    v0: byte,
+                    v1: chip8.memory[address + 1],
+                };
+                (raw.as_string(), symbols.mnemonic(&Chip8::decode(raw)), 2)
+            } else {
+                (format!("{:02X}", byte), format!("DB #{:02X}", byte), 1)
+            };
+
+            let mut uses = Vec::new();
+            for (letter, counts) in [
+                ('x', &self.executed),
+                ('r', &self.read),
+                ('w', &self.written),
+            ] {
+                if counts[address] > 0 {
+                    uses.push(format!("{}{}", letter, counts[address]));
+                }
+            }
+            if uses.is_empty() {
+                uses.push("unused".to_string());
+            }
+            uses.extend(symbols.line(address).map(str::to_string));
+            let uses = uses.join(" ");
+            lines.push(format!(
+                "0x{:03X}  {:<4}  {:<18} ; {}",
+                address, bytes, text, uses
+            ));
+            address += size;
+        }
+        fs::write(path, lines.join("\n") + "\n").map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // A GIF of the whole memory, 64 addresses to a row. Blue is code, green
+    // data read through I and red memory written, mixing where an address
+    // was used more than one way, and brighter the more often it was.
+    // Untouched ROM bytes are grey, the rest of memory darker.
+    pub fn save_heatmap(&self, chip8: &Chip8, path: &Path) -> Result<(), String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let memory = chip8.memory.len();
+        let width = ROW * CELL;
+        let height = memory.div_ceil(ROW) * CELL;
+
+        let mut pixels = vec![0u8; width * height];
+        for address in 0..memory {
+            let index = self.color_index(chip8, address);
+            let (left, top) = (address % ROW * CELL, address / ROW * CELL);
+            for y in top..top + CELL - 1 {
+                pixels[y * width + left..y * width + left + CELL - 1].fill(index);
+            }
+        }
+
+        let file = File::create(path).map_err(|e| error(&e))?;
+        let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &palette())
+            .map_err(|e| error(&e))?;
+        let frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+        encoder.write_frame(&frame).map_err(|e| error(&e))
+    }
+
+    fn color_index(&self, chip8: &Chip8, address: usize) -> u8 {
+        let kinds = [&self.written, &self.read, &self.executed];
+        let mut mix = 0;
+        let mut most = 0;
+        for (bit, counts) in kinds.iter().enumerate() {
+            if counts[address] > 0 {
+                mix |= 4 >> bit;
+                most = most.max(counts[address]);
+            }
+        }
+        if mix == 0 {
+            let in_rom = (chip8.layout.start..chip8.end).contains(&address);
+            return in_rom as u8;
+        }
+        // Four steps of brightness: once, up to 15, up to 255 and more.
+        let level = (most.ilog2() / 4).min(3) as u8;
+        2 + (mix - 1) * 4 + level
+    }
+}
+
+fn bump(counts: &mut [u32], address: usize) {
+    if let Some(count) = counts.get_mut(address) {
+        *count = count.saturating_add(1);
+    }
+}
+
+// Unused memory, unused ROM, then each mix of written (red), read (green)
+// and executed (blue) at four levels of brightness.
+fn palette() -> Vec<u8> {
+    let mut palette = vec![24, 24, 24, 72, 72, 72];
+    for mix in 1..8u8 {
+        for level in 0..4u8 {
+            let value = 96 + level * 53;
+            for bit in [4, 2, 1] {
+                palette.push(if mix & bit != 0 { value } else { 0 });
+            }
+        }
+    }
+    palette
+}
@@ -0,0 +1,745 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+use base64::prelude::*;
+use serde_json::{json, Value};
+
+use crate::cheat;
+use crate::debug::{self, Condition};
+use crate::{Chip8, Opcode, RawOpCode};
+
+// The machine is a single thread as far as the client is concerned.
+const THREAD: u64 = 1;
+const REGISTERS: u64 = 1;
+const STACK: u64 = 2;
+
+// A Debug Adapter Protocol server on localhost, for editors. Like the GDB
+// server it is polled from the emulator's loop and holds the program
+// stopped whenever the client has it.
+pub struct DapServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    input: Vec<u8>,
+    seq: u64,
+    // Events waiting for the response to the request that caused them.
+    outbox: Vec<Value>,
+    // Breakpoints are replaced a whole group at a time: per source file,
+    // all instruction ones and all function ones.
+    sources: Vec<(String, Vec<DapBreakpoint>)>,
+    instructions: Vec<DapBreakpoint>,
+    functions: Vec<DapBreakpoint>,
+    next_id: u64,
+    running: bool,
+    // Where the program last stopped, so resuming steps over a breakpoint.
+    stopped_at: Option<usize>,
+    // Stop once the stack is no deeper than this, after stepping over or
+    // out of a subroutine.
+    step_depth: Option<usize>,
+    // A launch waiting for its response.
+    launch: Option<Value>,
+    stop_on_entry: bool,
+    // Whether the client launched the program, so ending the session ends it.
+    launched: bool,
+    // Where source files named by the symbols are looked for.
+    base: PathBuf,
+}
+
+struct DapBreakpoint {
+    id: u64,
+    address: usize,
+    condition: Option<Condition>,
+}
+
+// What the client asked of the emulator beyond the machine itself.
+pub enum Request {
+    Launch(PathBuf),
+    Kill,
+}
+
+impl DapServer {
+    // Port 0 picks a free one; address() says which.
+    pub fn bind(port: u16) -> Result<DapServer, String> {
+        let error = |e: std::io::Error| format!("debug adapter on port {}: {}", port, e);
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(error)?;
+        listener.set_nonblocking(true).map_err(error)?;
+        Ok(DapServer {
+            listener,
+            client: None,
+            input: Vec::new(),
+            seq: 0,
+            outbox: Vec::new(),
+            sources: Vec::new(),
+            instructions: Vec::new(),
+            functions: Vec::new(),
+            next_id: 1,
+            running: true,
+            stopped_at: None,
+            step_depth: None,
+            launch: None,
+            stop_on_entry: false,
+            launched: false,
+            base: PathBuf::new(),
+        })
+    }
+
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    // Whether the program may run; without a client it always may.
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    // Checked before the instruction at PC runs. Stops at a breakpoint or
+    // the end of a step and tells the client.
+    pub fn check_stop(&mut self, chip8: &Chip8) -> bool {
+        let pc = chip8.program_counter;
+        if self.client.is_none() || self.stopped_at == Some(pc) {
+            return false;
+        }
+        self.stopped_at = None;
+
+        let hits: Vec<u64> = self
+            .sources
+            .iter()
+            .flat_map(|(_, breakpoints)| breakpoints)
+            .chain(&self.instructions)
+            .chain(&self.functions)
+            .filter(|breakpoint| {
+                breakpoint.address == pc
+                    && breakpoint
+                        .condition
+                        .as_ref()
+                        .is_none_or(|condition| condition.holds(chip8))
+            })
+            .map(|breakpoint| breakpoint.id)
+            .collect();
+        let stepped = self
+            .step_depth
+            .is_some_and(|depth| chip8.sub_pointer <= depth);
+        if hits.is_empty() && !stepped {
+            return false;
+        }
+
+        if hits.is_empty() {
+            self.stop(chip8, json!({ "reason": "step" }));
+        } else {
+            self.stop(
+                chip8,
+                json!({ "reason": "breakpoint", "hitBreakpointIds": hits }),
+            );
+        }
+        self.flush();
+        true
+    }
+
+    // Accepts a client and answers whatever it has sent so far. rom is the
+    // program running, if any, for clients that attach to it.
+    pub fn poll(&mut self, chip8: &mut Chip8, rom: Option<&Path>) -> Option<Request> {
+        if self.client.is_none() {
+            let (stream, _) = self.listener.accept().ok()?;
+            if stream.set_nonblocking(true).is_err() {
+                return None;
+            }
+            self.client = Some(stream);
+            self.input.clear();
+        }
+
+        let mut buffer = [0; 4096];
+        loop {
+            let Some(stream) = &mut self.client else {
+                return None;
+            };
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.detach();
+                    return None;
+                }
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.detach();
+                    return None;
+                }
+            }
+        }
+
+        while let Some(message) = self.next_message() {
+            if message["type"] != "request" {
+                continue;
+            }
+            if let Some(request) = self.handle(&message, chip8, rom) {
+                return Some(request);
+            }
+        }
+        None
+    }
+
+    // Answers the launch request poll handed to the emulator, once the ROM
+    // has been loaded into chip8 or failed to.
+    pub fn launched(&mut self, chip8: &Chip8, path: &Path, result: Result<(), String>) {
+        let Some(request) = self.launch.take() else {
+            return;
+        };
+        let succeeded = result.is_ok();
+        self.respond(&request, result.map(|()| Value::Null));
+        if succeeded {
+            self.start_session(chip8, path);
+        }
+        self.flush();
+    }
+
+    // Takes the next message from the input, framed by a Content-Length
+    // header; None until a whole one has arrived.
+    fn next_message(&mut self) -> Option<Value> {
+        loop {
+            let end = self.input.windows(4).position(|w| w == b"\r\n\r\n")?;
+            let header = String::from_utf8_lossy(&self.input[..end]).into_owned();
+            let length = header.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("Content-Length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            });
+            let Some(length) = length else {
+                self.input.drain(..end + 4);
+                continue;
+            };
+            if self.input.len() < end + 4 + length {
+                return None;
+            }
+            let message: Vec<u8> = self.input.drain(..end + 4 + length).collect();
+            if let Ok(value) = serde_json::from_slice(&message[end + 4..]) {
+                return Some(value);
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        request: &Value,
+        chip8: &mut Chip8,
+        rom: Option<&Path>,
+    ) -> Option<Request> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => match arguments["program"].as_str() {
+                Some(program) => {
+                    self.launch = Some(request.clone());
+                    self.launched = true;
+                    self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                    return Some(Request::Launch(PathBuf::from(program)));
+                }
+                None => Err("launch needs a \"program\" to run".to_string()),
+            },
+            "attach" => match rom {
+                Some(path) => {
+                    self.launched = false;
+                    self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                    self.respond(request, Ok(Value::Null));
+                    self.start_session(chip8, path);
+                    self.flush();
+                    return None;
+                }
+                None => Err("no ROM is running; launch one instead".to_string()),
+            },
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stop(chip8, json!({ "reason": "entry" }));
+                } else {
+                    self.running = true;
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => Ok(self.set_source_breakpoints(chip8, arguments)),
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(chip8, arguments)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(chip8, arguments)),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(chip8)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK, "expensive": false },
+            ] })),
+            "variables" => Ok(variables(chip8, arguments["variablesReference"].as_u64())),
+            "evaluate" => {
+                let expression = arguments["expression"].as_str().unwrap_or_default();
+                // Hovers and watches only ever look.
+                match arguments["context"].as_str() {
+                    Some("repl") => cheat::command(chip8, expression)
+                        .map(|result| {
+                            result.map(|text| json!({ "result": text, "variablesReference": 0 }))
+                        })
+                        .unwrap_or_else(|| evaluate(chip8, expression)),
+                    _ => evaluate(chip8, expression),
+                }
+            }
+            "readMemory" => read_memory(chip8, arguments),
+            "writeMemory" => write_memory(chip8, arguments),
+            "disassemble" => disassemble(chip8, &self.base, arguments),
+            "continue" => {
+                self.resume(chip8, None);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                // A call runs until it returns.
+                let depth = chip8.sub_pointer;
+                match decode(chip8, chip8.program_counter) {
+                    Opcode::Subroutine { .. } => match run_one(chip8) {
+                        Ok(()) => self.resume(chip8, Some(depth)),
+                        Err(e) => self.stop(chip8, exception(&e)),
+                    },
+                    _ => self.step(chip8),
+                }
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                self.step(chip8);
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                match chip8.sub_pointer.checked_sub(1) {
+                    Some(depth) => self.resume(chip8, Some(depth)),
+                    None => self.step(chip8),
+                }
+                Ok(Value::Null)
+            }
+            "pause" => {
+                self.stop(chip8, json!({ "reason": "pause" }));
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                let terminate = command == "terminate"
+                    || arguments["terminateDebuggee"]
+                        .as_bool()
+                        .unwrap_or(self.launched);
+                if command == "terminate" {
+                    self.event("terminated", Value::Null);
+                }
+                self.respond(request, Ok(Value::Null));
+                self.flush();
+                self.detach();
+                return terminate.then_some(Request::Kill);
+            }
+            _ => Err(format!("unsupported request \"{}\"", command)),
+        };
+        self.respond(request, result);
+        self.flush();
+        None
+    }
+
+    // After a launch or attach: the client sets its breakpoints, then says
+    // configurationDone, and the program is held until then.
+    fn start_session(&mut self, chip8: &Chip8, path: &Path) {
+        self.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.sources.clear();
+        self.instructions.clear();
+        self.functions.clear();
+        self.step_depth = None;
+        self.running = false;
+        self.stopped_at = Some(chip8.program_counter);
+        self.event("initialized", Value::Null);
+    }
+
+    fn stop(&mut self, chip8: &Chip8, mut body: Value) {
+        self.running = false;
+        self.step_depth = None;
+        self.stopped_at = Some(chip8.program_counter);
+        body["threadId"] = json!(THREAD);
+        body["allThreadsStopped"] = json!(true);
+        self.event("stopped", body);
+    }
+
+    fn resume(&mut self, chip8: &Chip8, step_depth: Option<usize>) {
+        self.running = true;
+        self.step_depth = step_depth;
+        self.stopped_at = Some(chip8.program_counter);
+    }
+
+    fn step(&mut self, chip8: &mut Chip8) {
+        match run_one(chip8) {
+            Ok(()) => self.stop(chip8, json!({ "reason": "step" })),
+            Err(e) => self.stop(chip8, exception(&e)),
+        }
+    }
+
+    // Stops at an instruction that cannot run and tells the client; false
+    // if none is attached.
+    pub fn fault(&mut self, chip8: &Chip8, message: &str) -> bool {
+        if self.client.is_none() {
+            return false;
+        }
+        self.stop(chip8, exception(message));
+        self.flush();
+        true
+    }
+
+    fn breakpoint(
+        &mut self,
+        chip8: &Chip8,
+        address: Option<usize>,
+        condition: &Value,
+    ) -> (Option<DapBreakpoint>, Value) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let condition = match condition.as_str().filter(|text| !text.trim().is_empty()) {
+            Some(text) => match Condition::parse(text) {
+                Ok(condition) => Some(condition),
+                Err(e) => return (None, json!({ "id": id, "verified": false, "message": e })),
+            },
+            None => None,
+        };
+        let Some(address) = address else {
+            return (
+                None,
+                json!({ "id": id, "verified": false, "message": "no instruction there" }),
+            );
+        };
+        let mut reply = json!({
+            "id": id,
+            "verified": true,
+            "instructionReference": format!("0x{:03X}", address),
+        });
+        if let Some((file, line)) = chip8.symbols.location(address) {
+            reply["source"] = source(&self.base, file);
+            reply["line"] = json!(line);
+        }
+        let breakpoint = DapBreakpoint {
+            id,
+            address,
+            condition,
+        };
+        (Some(breakpoint), reply)
+    }
+
+    fn set_source_breakpoints(&mut self, chip8: &Chip8, arguments: &Value) -> Value {
+        let source = &arguments["source"];
+        let path = source["path"]
+            .as_str()
+            .or(source["name"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let mut kept = Vec::new();
+        let mut replies = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let address = chip8
+                .symbols
+                .line_address(Path::new(&path), line)
+                .map(|(address, _)| address);
+            let (breakpoint, reply) = self.breakpoint(chip8, address, &requested["condition"]);
+            kept.extend(breakpoint);
+            replies.push(reply);
+        }
+        self.sources.retain(|(file, _)| *file != path);
+        self.sources.push((path, kept));
+        json!({ "breakpoints": replies })
+    }
+
+    fn set_function_breakpoints(&mut self, chip8: &Chip8, arguments: &Value) -> Value {
+        let mut kept = Vec::new();
+        let mut replies = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let name = requested["name"].as_str().unwrap_or_default();
+            let address = chip8.symbols.resolve(name.trim());
+            let (breakpoint, reply) = self.breakpoint(chip8, address, &requested["condition"]);
+            kept.extend(breakpoint);
+            replies.push(reply);
+        }
+        self.functions = kept;
+        json!({ "breakpoints": replies })
+    }
+
+    fn set_instruction_breakpoints(&mut self, chip8: &Chip8, arguments: &Value) -> Value {
+        let mut kept = Vec::new();
+        let mut replies = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = requested["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = requested["offset"].as_i64().unwrap_or(0);
+            let address = chip8
+                .symbols
+                .resolve(reference.trim())
+                .and_then(|address| address.checked_add_signed(offset as isize));
+            let (breakpoint, reply) = self.breakpoint(chip8, address, &requested["condition"]);
+            kept.extend(breakpoint);
+            replies.push(reply);
+        }
+        self.instructions = kept;
+        json!({ "breakpoints": replies })
+    }
+
+    // The current instruction, then the calls that led to it.
+    fn stack_trace(&self, chip8: &Chip8) -> Value {
+        let depth = chip8.sub_pointer.min(chip8.stack.len());
+        let addresses = std::iter::once(chip8.program_counter)
+            .chain(chip8.stack[..depth].iter().rev().copied());
+        let frames: Vec<Value> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let name = chip8
+                    .symbols
+                    .name(address)
+                    .unwrap_or_else(|| format!("0x{:03X}", address));
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{:03X}", address),
+                });
+                if let Some((file, line)) = chip8.symbols.location(address) {
+                    frame["source"] = source(&self.base, file);
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.outbox.push(message);
+    }
+
+    fn flush(&mut self) {
+        for message in std::mem::take(&mut self.outbox) {
+            self.send(message);
+        }
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let Some(stream) = &mut self.client else {
+            return;
+        };
+        // Messages are small; a blocking write keeps them whole.
+        let sent = stream
+            .set_nonblocking(false)
+            .and_then(|()| stream.write_all(framed.as_bytes()))
+            .and_then(|()| stream.set_nonblocking(true));
+        if sent.is_err() {
+            self.detach();
+        }
+    }
+
+    fn detach(&mut self) {
+        self.client = None;
+        self.outbox.clear();
+        self.sources.clear();
+        self.instructions.clear();
+        self.functions.clear();
+        self.step_depth = None;
+        self.launch = None;
+        self.running = true;
+    }
+}
+
+// A source file named by the symbols, found relative to base.
+fn source(base: &Path, file: &str) -> Value {
+    let path = base.join(file);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file.to_string());
+    json!({ "name": name, "path": path })
+}
+
+fn variables(chip8: &Chip8, reference: Option<u64>) -> Value {
+    let variable = |name: String, value: String, address: Option<usize>| {
+        let mut variable = json!({ "name": name, "value": value, "variablesReference": 0 });
+        if let Some(address) = address {
+            variable["memoryReference"] = json!(format!("0x{:03X}", address));
+        }
+        variable
+    };
+    let byte = |value: u8| format!("0x{:02X} ({})", value, value);
+    let mut list = Vec::new();
+    match reference {
+        Some(REGISTERS) => {
+            for (n, value) in chip8.registry.iter().enumerate() {
+                list.push(variable(format!("V{:X}", n), byte(*value), None));
+            }
+            list.push(variable(
+                "I".to_string(),
+                chip8.symbols.describe(chip8.i),
+                Some(chip8.i),
+            ));
+            list.push(variable(
+                "PC".to_string(),
+                chip8.symbols.describe(chip8.program_counter),
+                Some(chip8.program_counter),
+            ));
+            list.push(variable(
+                "SP".to_string(),
+                chip8.sub_pointer.to_string(),
+                None,
+            ));
+            list.push(variable("DT".to_string(), byte(chip8.delay_timer), None));
+            list.push(variable("ST".to_string(), byte(chip8.sound_timer), None));
+        }
+        Some(STACK) => {
+            let depth = chip8.sub_pointer.min(chip8.stack.len());
+            for (n, call) in chip8.stack[..depth].iter().enumerate() {
+                list.push(variable(
+                    format!("[{}]", n),
+                    chip8.symbols.describe(*call),
+                    Some(*call),
+                ));
+            }
+        }
+        _ => {}
+    }
+    json!({ "variables": list })
+}
+
+// A register, memory byte, number or symbol, as for breakpoint conditions.
+fn evaluate(chip8: &Chip8, expression: &str) -> Result<Value, String> {
+    let value = debug::evaluate(expression, chip8)
+        .or_else(|e| chip8.symbols.resolve(expression.trim()).ok_or(e))?;
+    Ok(json!({
+        "result": format!("0x{:X} ({})", value, value),
+        "variablesReference": 0,
+        "memoryReference": format!("0x{:03X}", value),
+    }))
+}
+
+// The start of a memory request: a reference (an address or symbol) plus
+// an offset that may be negative.
+fn memory_address(chip8: &Chip8, arguments: &Value) -> Result<i64, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or_default();
+    let address = chip8
+        .symbols
+        .resolve(reference.trim())
+        .ok_or_else(|| format!("unknown memory reference \"{}\"", reference))?;
+    Ok(address as i64 + arguments["offset"].as_i64().unwrap_or(0))
+}
+
+fn read_memory(chip8: &Chip8, arguments: &Value) -> Result<Value, String> {
+    let start = memory_address(chip8, arguments)?.max(0) as usize;
+    let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+    let end = start
+        .saturating_add(count)
+        .min(chip8.memory.len())
+        .max(start);
+    let data = chip8.memory.get(start..end).unwrap_or_default();
+    Ok(json!({
+        "address": format!("0x{:03X}", start),
+        "data": BASE64_STANDARD.encode(data),
+        "unreadableBytes": count - data.len(),
+    }))
+}
+
+fn write_memory(chip8: &mut Chip8, arguments: &Value) -> Result<Value, String> {
+    let start = memory_address(chip8, arguments)?;
+    let data = BASE64_STANDARD
+        .decode(arguments["data"].as_str().unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    let range = usize::try_from(start)
+        .ok()
+        .and_then(|start| Some(start..start.checked_add(data.len())?))
+        .filter(|range| range.end <= chip8.memory.len())
+        .ok_or("write goes outside memory")?;
+    chip8.memory[range].copy_from_slice(&data);
+    Ok(json!({ "bytesWritten": data.len() }))
+}
+
+// Instructions are two bytes, so the listing can start anywhere, even
+// before the reference.
+fn disassemble(chip8: &Chip8, base: &Path, arguments: &Value) -> Result<Value, String> {
+    let start = memory_address(chip8, arguments)?
+        + arguments["instructionOffset"].as_i64().unwrap_or(0) * 2;
+    let count = arguments["instructionCount"].as_u64().unwrap_or(0) as i64;
+    let symbols = &chip8.symbols;
+    let instructions: Vec<Value> = (0..count)
+        .map(|n| start + n * 2)
+        .map(|address| {
+            let valid = usize::try_from(address)
+                .ok()
+                .filter(|address| address + 1 < chip8.memory.len());
+            let Some(address) = valid else {
+                return json!({
+                    "address": format!("0x{:X}", address.max(0)),
+                    "instruction": "",
+                    "presentationHint": "invalid",
+                });
+            };
+            let raw = fetch(chip8, address);
+            let mut instruction = json!({
+                "address": format!("0x{:03X}", address),
+                "instructionBytes": raw.as_string(),
+                "instruction": symbols.mnemonic(&Chip8::decode(raw)),
+            });
+            if let Some(name) = symbols.label(address) {
+                instruction["symbol"] = json!(name);
+            }
+            if let Some((file, line)) = symbols.location(address) {
+                instruction["location"] = source(base, file);
+                instruction["line"] = json!(line);
+            }
+            instruction
+        })
+        .collect();
+    Ok(json!({ "instructions": instructions }))
+}
+
+// Runs one instruction; one waiting for the next frame gets it at once.
+fn exception(message: &str) -> Value {
+    json!({ "reason": "exception", "description": message, "text": message })
+}
+
+fn run_one(chip8: &mut Chip8) -> Result<(), String> {
+    if !chip8.execute(false)? {
+        chip8.execute(true)?;
+    }
+    Ok(())
+}
+
+fn fetch(chip8: &Chip8, address: usize) -> RawOpCode {
+    let byte = |n| chip8.memory.get(n).copied().unwrap_or(0);
+    RawOpCode {
+        v0: byte(address),
+        v1: byte(address + 1),
+    }
+}
+
+fn decode(chip8: &Chip8, address: usize) -> Opcode {
+    Chip8::decode(fetch(chip8, address))
+}
@@ -0,0 +1,371 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::symbols::Symbols;
+use crate::{parse_number, Chip8, RawOpCode};
+
+#[derive(Clone)]
+pub struct Breakpoint {
+    // An address or a symbol, looked up in the running ROM's symbols.
+    location: String,
+    condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    // An address or symbol, optionally followed by "if" and a condition,
+    // like "0x2A4 if V3 == 0x10 && I > 0x300" or "draw_player".
+    pub fn parse(text: &str) -> Result<Breakpoint, String> {
+        let (location, condition) = match text.split_once(" if ") {
+            Some((location, condition)) => (location, Some(Condition::parse(condition)?)),
+            None => (text, None),
+        };
+        let location = location.trim();
+        if location.is_empty() || location.contains(char::is_whitespace) {
+            return Err(format!(
+                "expected an address or symbol, not \"{}\"",
+                location
+            ));
+        }
+        Ok(Breakpoint {
+            location: location.to_string(),
+            condition,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Watchpoint {
+    // An address, symbol or range of them.
+    range: String,
+    read: bool,
+    write: bool,
+}
+
+impl Watchpoint {
+    // An address, symbol or range like "0x300-0x30F", optionally followed
+    // by :r, :w or :rw for the kind of access to stop on; both by default.
+    pub fn parse(text: &str) -> Result<Watchpoint, String> {
+        let (range, access) = text.split_once(':').unwrap_or((text, "rw"));
+        let (read, write) = match access {
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" => (true, true),
+            _ => return Err(format!("unknown access \"{}\", use r, w or rw", access)),
+        };
+        let range = range.trim();
+        if range.is_empty() {
+            return Err("expected an address, symbol or range".to_string());
+        }
+        Ok(Watchpoint {
+            range: range.to_string(),
+            read,
+            write,
+        })
+    }
+
+    // First and last address watched. Symbols may have dashes in them, so
+    // the whole text is tried as one before splitting it.
+    fn resolve(&self, symbols: &Symbols) -> Option<(usize, usize)> {
+        if let Some(address) = symbols.resolve(&self.range) {
+            return Some((address, address));
+        }
+        let (start, end) = self.range.split_once('-')?;
+        let (start, end) = (symbols.resolve(start.trim())?, symbols.resolve(end.trim())?);
+        (start <= end).then_some((start, end))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(usize),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+    Memory(usize),
+    Number(usize),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Operand, String> {
+        let upper = text.to_ascii_uppercase();
+        Ok(match upper.as_str() {
+            "I" => Operand::Index,
+            "PC" => Operand::ProgramCounter,
+            "SP" => Operand::StackPointer,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            _ => {
+                if let Some(address) = upper.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+                    Operand::Memory(parse_number(address.trim())?)
+                } else if let Some(n) = upper.strip_prefix('V').filter(|n| n.len() == 1) {
+                    let n = usize::from_str_radix(n, 16)
+                        .map_err(|_| format!("unknown register {}", text))?;
+                    Operand::Register(n)
+                } else {
+                    Operand::Number(parse_number(text).map_err(|_| {
+                        format!("expected a register, [address] or number, not \"{}\"", text)
+                    })?)
+                }
+            }
+        })
+    }
+
+    fn value(&self, chip8: &Chip8) -> usize {
+        match *self {
+            Operand::Register(n) => chip8.registry[n] as usize,
+            Operand::Index => chip8.i,
+            Operand::ProgramCounter => chip8.program_counter,
+            Operand::StackPointer => chip8.sub_pointer,
+            Operand::DelayTimer => chip8.delay_timer as usize,
+            Operand::SoundTimer => chip8.sound_timer as usize,
+            Operand::Memory(address) => chip8.memory.get(address).copied().unwrap_or(0) as usize,
+            Operand::Number(n) => n,
+        }
+    }
+}
+
+// Longer operators first, so "<=" is not taken for "<".
+const OPERATORS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+#[derive(Clone)]
+struct Comparison {
+    left: Operand,
+    operator: &'static str,
+    right: Operand,
+}
+
+impl Comparison {
+    fn parse(text: &str) -> Result<Comparison, String> {
+        for operator in OPERATORS {
+            if let Some((left, right)) = text.split_once(operator) {
+                return Ok(Comparison {
+                    left: Operand::parse(left.trim())?,
+                    operator,
+                    right: Operand::parse(right.trim())?,
+                });
+            }
+        }
+        Err(format!("no comparison in \"{}\"", text.trim()))
+    }
+
+    fn holds(&self, chip8: &Chip8) -> bool {
+        let (left, right) = (self.left.value(chip8), self.right.value(chip8));
+        match self.operator {
+            "==" => left == right,
+            "!=" => left != right,
+            "<=" => left <= right,
+            ">=" => left >= right,
+            "<" => left < right,
+            _ => left > right,
+        }
+    }
+}
+
+// Comparisons joined by && and ||, with && binding tighter.
+#[derive(Clone)]
+pub struct Condition {
+    any: Vec<Vec<Comparison>>,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let any = text
+            .split("||")
+            .map(|all| all.split("&&").map(Comparison::parse).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(Condition { any })
+    }
+
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|comparison| comparison.holds(chip8)))
+    }
+}
+
+// The value of a register, memory byte or number as conditions see it.
+pub fn evaluate(text: &str, chip8: &Chip8) -> Result<usize, String> {
+    Ok(Operand::parse(text.trim())?.value(chip8))
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    // Where the last breakpoint stopped, so resuming steps over it.
+    stopped_at: Option<usize>,
+}
+
+impl Debugger {
+    pub fn new(breakpoints: Vec<Breakpoint>, watchpoints: Vec<Watchpoint>) -> Debugger {
+        Debugger {
+            breakpoints,
+            watchpoints,
+            stopped_at: None,
+        }
+    }
+
+    // Breakpoint and watchpoint locations the ROM's symbols do not cover.
+    pub fn check_symbols(&self, symbols: &Symbols) -> Result<(), String> {
+        let breakpoints = self
+            .breakpoints
+            .iter()
+            .filter(|breakpoint| symbols.resolve(&breakpoint.location).is_none())
+            .map(|breakpoint| breakpoint.location.as_str());
+        let watchpoints = self
+            .watchpoints
+            .iter()
+            .filter(|watchpoint| watchpoint.resolve(symbols).is_none())
+            .map(|watchpoint| watchpoint.range.as_str());
+        let unknown: Vec<&str> = breakpoints.chain(watchpoints).collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "unknown symbol or bad range: {}",
+                unknown.join(", ")
+            ))
+        }
+    }
+
+    // Checked before the instruction at PC runs; describes the breakpoint
+    // that stops it, if any.
+    pub fn check_breakpoint(&mut self, chip8: &Chip8) -> Option<String> {
+        let pc = chip8.program_counter;
+        if self.stopped_at == Some(pc) {
+            return None;
+        }
+        self.stopped_at = None;
+
+        let symbols = &chip8.symbols;
+        let breakpoint = self.breakpoints.iter().find(|breakpoint| {
+            symbols.resolve(&breakpoint.location) == Some(pc)
+                && breakpoint
+                    .condition
+                    .as_ref()
+                    .is_none_or(|condition| condition.holds(chip8))
+        })?;
+        self.stopped_at = Some(pc);
+        Some(format!(
+            "Breakpoint {} at {}",
+            breakpoint.location,
+            instruction(chip8, pc)
+        ))
+    }
+
+    // Checked after an instruction runs; describes the watchpoint its
+    // memory access hit, if any. The breakpoint it resumed from, if any, has
+    // now been stepped over and stops again the next time it is reached.
+    pub fn check_access(&mut self, chip8: &Chip8) -> Option<String> {
+        self.stopped_at = None;
+        let access = chip8.access?;
+        let last = access.start + access.len.max(1) - 1;
+        let watchpoint = self.watchpoints.iter().find(|watchpoint| {
+            let wanted = if access.write {
+                watchpoint.write
+            } else {
+                watchpoint.read
+            };
+            let hit = watchpoint
+                .resolve(&chip8.symbols)
+                .is_some_and(|(start, end)| access.start <= end && last >= start);
+            wanted && hit
+        })?;
+        Some(format!(
+            "Watchpoint {}: {} 0x{:03X}-0x{:03X} by {}",
+            watchpoint.range,
+            if access.write { "write" } else { "read" },
+            access.start,
+            last,
+            instruction(chip8, access.pc)
+        ))
+    }
+}
+
+// The subroutine calls that led to the current instruction, innermost
+// first, like "called from 0x21A (main+24)".
+pub fn call_stack(chip8: &Chip8) -> Vec<String> {
+    let depth = chip8.sub_pointer.min(chip8.stack.len());
+    chip8.stack[..depth]
+        .iter()
+        .rev()
+        .map(|call| format!("called from {}", chip8.symbols.describe(*call)))
+        .collect()
+}
+
+// Address, opcode and assembly of the instruction at address.
+fn instruction(chip8: &Chip8, address: usize) -> String {
+    let byte = |n| chip8.memory.get(n).copied().unwrap_or(0);
+    let raw = RawOpCode {
+        v0: byte(address),
+        v1: byte(address + 1),
+    };
+    let text = raw.as_string();
+    format!(
+        "{} {} {}",
+        chip8.symbols.describe(address),
+        text,
+        chip8.symbols.mnemonic(&Chip8::decode(raw))
+    )
+}
+
+// Writes a line for every instruction run: where it was, what it was and
+// the registers it left behind.
+pub struct Trace {
+    out: BufWriter<File>,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> Result<Trace, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Trace {
+            out: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, chip8: &Chip8, pc: usize) -> io::Result<()> {
+        let byte = |n| chip8.memory.get(n).copied().unwrap_or(0);
+        let raw = RawOpCode {
+            v0: byte(pc),
+            v1: byte(pc + 1),
+        };
+        let registers: Vec<String> = chip8
+            .registry
+            .iter()
+            .map(|v| format!("{:02X}", v))
+            .collect();
+        writeln!(
+            self.out,
+            "0x{:03X}  {:<20} {}  {:<24} V={} I={:03X} SP={}",
+            pc,
+            chip8.symbols.name(pc).unwrap_or_default(),
+            raw.as_string(),
+            chip8.symbols.mnemonic(&Chip8::decode(raw)),
+            registers.join(" "),
+            chip8.i,
+            chip8.sub_pointer
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_stop_each_time_a_jump_to_itself_runs() {
+        let mut chip8 = Chip8::new();
+        chip8.load(vec![0x12, 0x00], &crate::romdb::RomDb::builtin());
+        chip8.power_on().unwrap();
+        let mut debugger = Debugger::new(vec![Breakpoint::parse("0x200").unwrap()], Vec::new());
+        for _ in 0..3 {
+            assert!(debugger.check_breakpoint(&chip8).is_some());
+            // Resuming runs the instruction instead of stopping again.
+            assert!(debugger.check_breakpoint(&chip8).is_none());
+            chip8.execute(false).unwrap();
+            assert!(debugger.check_access(&chip8).is_none());
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeSet;
+
+use crate::quirks::{self, Layout, Platform};
+use crate::{Chip8, Opcode, RawOpCode};
+
+pub struct Detection {
+    // The oldest platform that has every instruction found, once there are
+    // enough of them to go on.
+    pub platform: Platform,
+    // The ROM opens with the jump into the hi-res patch. This is part of
+    // the layout, whatever platform the ROM is run as.
+    pub hires: bool,
+    // Address, opcode, assembly and feature of the first use of each newer
+    // instruction.
+    pub reasons: Vec<(usize, String, String, &'static str, Platform)>,
+    pub reachable: usize,
+    // Bnnn jumps, whose targets depend on V0 and are not followed.
+    pub computed_jumps: usize,
+}
+
+// Follows every path from the entry point through jumps, calls and
+// skips, without running anything, and notes which instructions need
+// more than the original CHIP-8.
+pub fn scan(rom: &[u8], start: usize) -> Detection {
+    let mut detection = Detection {
+        platform: Platform::Chip8,
+        hires: quirks::hires_patch(rom, start),
+        reasons: Vec::new(),
+        reachable: 0,
+        computed_jumps: 0,
+    };
+    let mut seen_names = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let layout = Layout {
+        start,
+        hires: detection.hires,
+        ..Layout::default()
+    };
+    let mut pending = vec![layout.entry_point(rom)];
+    let mut newest = Platform::Chip8;
+
+    if detection.hires {
+        let reason = (
+            start,
+            "1260".to_string(),
+            "JP #260".to_string(),
+            "hi-res patch",
+            Platform::Hires,
+        );
+        detection.reasons.push(reason);
+    }
+
+    let word = |address: usize| -> Option<RawOpCode> {
+        let offset = address.checked_sub(start)?;
+        Some(RawOpCode {
+            v0: *rom.get(offset)?,
+            v1: *rom.get(offset + 1)?,
+        })
+    };
+
+    while let Some(address) = pending.pop() {
+        if !visited.insert(address) {
+            continue;
+        }
+        let Some(raw) = word(address) else {
+            continue;
+        };
+        let text = raw.as_string();
+        let opcode = Chip8::decode(raw);
+        detection.reachable += 1;
+
+        if let Some((name, platform)) = requirement(&opcode) {
+            if seen_names.insert(name) {
+                let reason = (address, text, opcode.mnemonic(), name, platform);
+                detection.reasons.push(reason);
+            }
+            newest = newest.max(platform);
+        }
+
+        // XO-CHIP skips step over the whole of a four byte F000 nnnn.
+        let skip = match word(address + 2).map(|raw| (raw.v0, raw.v1)) {
+            Some((0xF0, 0x00)) => address + 6,
+            _ => address + 4,
+        };
+        match opcode {
+            Opcode::Jump { n0, n1, n2 } => pending.push(Chip8::to_decimal(n0, n1, n2) as usize),
+            Opcode::Subroutine { n0, n1, n2 } => {
+                pending.push(Chip8::to_decimal(n0, n1, n2) as usize);
+                pending.push(address + 2);
+            }
+            Opcode::JumpOffset { .. } => detection.computed_jumps += 1,
+            Opcode::Return | Opcode::Exit | Opcode::None { .. } => {}
+            Opcode::SkipIfEqualXN { .. }
+            | Opcode::SkipIfNotEqualXN { .. }
+            | Opcode::SkipIfEqualXY { .. }
+            | Opcode::SkipIfNotEqualXY { .. }
+            | Opcode::SkipIfKeyDown { .. }
+            | Opcode::SkipIfKeyUp { .. } => {
+                pending.push(address + 2);
+                pending.push(skip);
+            }
+            Opcode::LongIndex => pending.push(address + 4),
+            _ => pending.push(address + 2),
+        }
+    }
+
+    // A single newer instruction may just be data that decodes as one, so
+    // it takes two different ones to switch platforms.
+    if seen_names.len() > 1 {
+        detection.platform = newest;
+    }
+    detection.reasons.sort_by_key(|(address, ..)| *address);
+    detection
+}
+
+// Instructions added after the original CHIP-8, and the first platform
+// to have them.
+fn requirement(opcode: &Opcode) -> Option<(&'static str, Platform)> {
+    let schip = Platform::Schip;
+    let xochip = Platform::Xochip;
+    Some(match opcode {
+        Opcode::ScrollDown { .. } => ("scroll down", schip),
+        Opcode::ScrollRight => ("scroll right", schip),
+        Opcode::ScrollLeft => ("scroll left", schip),
+        Opcode::Exit => ("exit", schip),
+        Opcode::LowRes => ("low resolution", schip),
+        Opcode::HighRes => ("high resolution", schip),
+        Opcode::Draw { n: 0, .. } => ("16x16 sprite", schip),
+        Opcode::SetBigSprite { .. } => ("big font", schip),
+        Opcode::SaveFlags { .. } => ("save flags", schip),
+        Opcode::LoadFlags { .. } => ("load flags", schip),
+        Opcode::ScrollUp { .. } => ("scroll up", xochip),
+        Opcode::SaveRange { .. } => ("save register range", xochip),
+        Opcode::LoadRange { .. } => ("load register range", xochip),
+        Opcode::LongIndex => ("16-bit index", xochip),
+        Opcode::Plane { .. } => ("select plane", xochip),
+        Opcode::LoadAudio => ("audio pattern", xochip),
+        Opcode::SetPitch { .. } => ("pitch", xochip),
+        _ => return None,
+    })
+}
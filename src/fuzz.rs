@@ -0,0 +1,253 @@
+// The core must run any bytes without panicking: every instruction either
+// runs, waits for the next frame or fails with an error that leaves the
+// machine as it was. The properties below check that over random programs,
+// layouts and machine states; `PROPTEST_CASES=100000 cargo test --release
+// fuzz` fuzzes for longer, and `cargo +nightly fuzz run execute` in fuzz/
+// hands the same checks to libFuzzer.
+
+use clap::ValueEnum;
+
+use crate::quirks::{LayoutOverrides, Platform};
+use crate::romdb::RomDb;
+use crate::{Chip8, STACK};
+
+// Instructions run per case, with a frame every FRAME of them.
+const STEPS: usize = 2000;
+const FRAME: usize = 10;
+// Bytes at the front of a libFuzzer input that set up the machine.
+const HEADER: usize = 14;
+
+// The parts of the machine an instruction changes.
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    memory: Vec<u8>,
+    registry: [u8; 16],
+    stack: [usize; STACK],
+    sub_pointer: usize,
+    i: usize,
+    program_counter: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+fn snapshot(chip8: &Chip8) -> Snapshot {
+    Snapshot {
+        memory: chip8.memory.clone(),
+        registry: chip8.registry,
+        stack: chip8.stack,
+        sub_pointer: chip8.sub_pointer,
+        i: chip8.i,
+        program_counter: chip8.program_counter,
+        delay_timer: chip8.delay_timer,
+        sound_timer: chip8.sound_timer,
+    }
+}
+
+// The libFuzzer target. The header picks the platform, which parts of its
+// layout to override and with what, and the keys held each frame; the rest
+// of the input is the ROM.
+pub fn execute(data: &[u8]) {
+    let Some((header, rom)) = data.split_first_chunk::<HEADER>() else {
+        return;
+    };
+    let platforms = Platform::value_variants();
+    let platform = platforms[header[0] as usize % platforms.len()];
+    let word = |n: usize| u16::from_be_bytes([header[n], header[n + 1]]) as usize;
+    let flags = header[1];
+    let layout = LayoutOverrides {
+        start: (flags & 1 != 0).then(|| word(2) % 0x1000),
+        font: (flags & 2 != 0).then(|| word(4) % 0x1000),
+        memory: (flags & 4 != 0).then(|| 0x1000 + word(6) % 0xF001),
+        hires: (flags & 8 != 0).then_some(flags & 16 != 0),
+    };
+    let keys = [word(8) as u16, word(10) as u16, word(12) as u16];
+    run_rom(rom.to_vec(), platform, &layout, &keys);
+}
+
+// Loads a ROM the way a run does, the layout overrides after the platform
+// and database, and runs it if it fits.
+fn run_rom(rom: Vec<u8>, platform: Platform, layout: &LayoutOverrides, keys: &[u16]) {
+    let mut chip8 = Chip8::new();
+    chip8.set_platform(platform);
+    chip8.load(rom, &RomDb::builtin());
+    // Overrides that do not check out are refused on the command line.
+    if layout.check().is_err() {
+        return;
+    }
+    layout.apply(&mut chip8.layout);
+    // ROMs and fonts that do not fit are refused before anything runs.
+    if chip8.power_on().is_ok() {
+        run(&mut chip8, keys);
+    }
+}
+
+// Runs the machine, pressing keys from the input now and then, until it
+// faults or the steps run out. Panics only if the core breaks its promises.
+fn run(chip8: &mut Chip8, keys: &[u16]) {
+    for step in 0..STEPS {
+        if step % FRAME == 0 {
+            let down = keys.get(step / FRAME % keys.len().max(1)).copied();
+            for key in 0..16 {
+                chip8.keypad.set(key, down.unwrap_or(0) & 1 << key != 0);
+            }
+        }
+        let before = snapshot(chip8);
+        match chip8.execute(step % FRAME == 0) {
+            Ok(_) => {
+                assert!(chip8.program_counter < chip8.memory.len());
+                assert!(chip8.i <= 0xFFFF);
+                assert!(chip8.sub_pointer <= chip8.stack.len());
+            }
+            Err(e) => {
+                assert_eq!(before, snapshot(chip8), "{} changed the machine", e);
+                assert_eq!(chip8.execute(true), Err(e), "faults should repeat");
+                return;
+            }
+        }
+        if step % FRAME == 0 {
+            chip8.keypad.end_frame();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn platform() -> impl Strategy<Value = Platform> {
+        prop::sample::select(Platform::value_variants())
+    }
+
+    // Mostly layouts that fit, with some past the end of memory and some
+    // with the font over the ROM.
+    fn layout() -> impl Strategy<Value = LayoutOverrides> {
+        let memory = prop_oneof![Just(0x1000), Just(0x10000), 0x1000..=0x10000usize];
+        (
+            prop::option::of(0..0x1000usize),
+            prop::option::of(prop_oneof![Just(0), 0..0x1000usize, 0xF00..0x1000usize]),
+            prop::option::of(memory),
+            prop::option::of(any::<bool>()),
+        )
+            .prop_map(|(start, font, memory, hires)| LayoutOverrides {
+                start,
+                font,
+                memory,
+                hires,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn any_rom_loads_and_runs(
+            data in prop::collection::vec(any::<u8>(), 0..0x1000),
+            platform in platform(),
+            layout in layout(),
+            keys in prop::collection::vec(any::<u16>(), 0..8),
+        ) {
+            run_rom(data, platform, &layout, &keys);
+        }
+
+        #[test]
+        fn any_fuzzer_input_runs(data in prop::collection::vec(any::<u8>(), 0..0x200)) {
+            execute(&data);
+        }
+
+        #[test]
+        fn any_memory_and_state_runs(
+            memory in prop::collection::vec(any::<u8>(), 0x1000),
+            registry in any::<[u8; 16]>(),
+            i in 0..=0xFFFFusize,
+            program_counter in 0..0x1000usize,
+            sub_pointer in 0..=STACK,
+            platform in platform(),
+            keys in prop::collection::vec(any::<u16>(), 0..8),
+        ) {
+            let mut chip8 = Chip8::new();
+            chip8.quirks = platform.quirks();
+            chip8.memory = memory;
+            chip8.registry = registry;
+            chip8.i = i;
+            chip8.program_counter = program_counter;
+            chip8.sub_pointer = sub_pointer;
+            run(&mut chip8, &keys);
+        }
+
+        #[test]
+        fn stores_and_loads_wrap_at_the_end_of_memory(
+            // Near the end of 4 KiB and of 64 KiB, clear of the code at 0x200.
+            i in prop_oneof![0xFF0..=0x10FFusize, 0xFFF0..=0xFFFFusize],
+            x in 0..16u8,
+            registry in any::<[u8; 16]>(),
+            platform in platform(),
+        ) {
+            let mut chip8 = Chip8::new();
+            chip8.set_platform(platform);
+            chip8.power_on().unwrap();
+            let len = chip8.memory.len();
+            chip8.registry = registry;
+            chip8.i = i;
+            // Fx55, then Fx65 from the same I.
+            chip8.memory[0x200..0x204].copy_from_slice(&[0xF0 | x, 0x55, 0xF0 | x, 0x65]);
+            chip8.program_counter = 0x200;
+            chip8.execute(false).unwrap();
+            for (n, value) in registry.iter().enumerate().take(x as usize + 1) {
+                prop_assert_eq!(chip8.memory[(i + n) % len], *value);
+            }
+            chip8.registry = [0; 16];
+            chip8.i = i;
+            chip8.execute(false).unwrap();
+            prop_assert_eq!(&chip8.registry[..=x as usize], &registry[..=x as usize]);
+        }
+    }
+
+    #[test]
+    fn layouts_that_do_not_fit_are_errors() {
+        let mut chip8 = Chip8::new();
+        chip8.load(vec![0; 0x100], &RomDb::builtin());
+        chip8.layout.font = 0x1000;
+        let error = chip8.power_on().unwrap_err();
+        assert_eq!(
+            error,
+            "font at 0x1000 does not fit before the end of memory at 0x1000"
+        );
+
+        chip8.layout.font = 0x280;
+        let error = chip8.power_on().unwrap_err();
+        assert_eq!(error, "font at 0x280-0x36F overlaps the ROM at 0x200-0x2FF");
+
+        chip8.layout.font = 0x50;
+        chip8.layout.start = 0x1000;
+        let error = chip8.power_on().unwrap_err();
+        assert_eq!(error, "start 0x1000 is past the end of memory at 0x1000");
+    }
+
+    #[test]
+    fn stack_overflow_and_underflow_are_errors() {
+        let mut chip8 = Chip8::new();
+        chip8.power_on().unwrap();
+        // A subroutine that calls itself, and a return with nothing to return to.
+        chip8.memory[0x200..0x204].copy_from_slice(&[0x22, 0x00, 0x00, 0xEE]);
+        for _ in 0..chip8.stack.len() {
+            assert_eq!(chip8.execute(false), Ok(true));
+        }
+        let error = chip8.execute(false).unwrap_err();
+        assert_eq!(error, "stack overflow: more than 16 nested calls at 0x200");
+
+        chip8.sub_pointer = 0;
+        chip8.program_counter = 0x202;
+        let error = chip8.execute(false).unwrap_err();
+        assert_eq!(error, "return with an empty stack at 0x202");
+    }
+
+    #[test]
+    fn unknown_opcodes_are_errors() {
+        let mut chip8 = Chip8::new();
+        chip8.power_on().unwrap();
+        chip8.memory[0x200..0x202].copy_from_slice(&[0x01, 0x23]);
+        let error = chip8.execute(false).unwrap_err();
+        assert_eq!(error, "opcode 0123 not implemented at 0x200");
+        assert_eq!(chip8.program_counter, 0x200);
+    }
+}
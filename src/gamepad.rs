@@ -0,0 +1,250 @@
+use sdl3::{
+    event::Event,
+    gamepad::{Axis, Gamepad},
+    GamepadSubsystem,
+};
+
+use crate::keymap::HostKey;
+use crate::osd::Osd;
+
+pub const PLAYERS: usize = 2;
+pub const DEAD_ZONE: i16 = 8000;
+
+const AXES: [Axis; 6] = [
+    Axis::LeftX,
+    Axis::LeftY,
+    Axis::RightX,
+    Axis::RightY,
+    Axis::TriggerLeft,
+    Axis::TriggerRight,
+];
+
+struct Slot {
+    gamepad: Gamepad,
+    held: Vec<HostKey>,
+    // Direction each axis is pushed in: -1, 0 or 1.
+    axes: [i8; AXES.len()],
+}
+
+pub struct Gamepads {
+    subsystem: GamepadSubsystem,
+    slots: [Option<Slot>; PLAYERS],
+    pub dead_zone: i16,
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GamepadSubsystem) -> Gamepads {
+        Gamepads {
+            subsystem,
+            slots: Default::default(),
+            dead_zone: DEAD_ZONE,
+        }
+    }
+
+    fn player(&self, which: u32) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.as_ref()
+                .is_some_and(|slot| slot.gamepad.instance_id() == which)
+        })
+    }
+
+    // Translates gamepad events into host key presses (true) and releases (false).
+    pub fn handle(&mut self, event: &Event, osd: &mut Osd) -> Vec<(HostKey, bool)> {
+        let mut inputs = Vec::new();
+
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                if self.player(which).is_some() {
+                    return inputs;
+                }
+                let Some(player) = self.slots.iter().position(Option::is_none) else {
+                    osd.message("No free player for gamepad");
+                    return inputs;
+                };
+
+                match self.subsystem.open(which) {
+                    Ok(gamepad) => {
+                        osd.message(format!("{} is player {}", gamepad.name(), player + 1));
+                        self.slots[player] = Some(Slot {
+                            gamepad,
+                            held: Vec::new(),
+                            axes: [0; AXES.len()],
+                        });
+                    }
+                    Err(e) => osd.message(format!("Gamepad error: {}", e)),
+                }
+            }
+
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(player) = self.player(which) {
+                    let slot = self.slots[player].take().unwrap();
+                    osd.message(format!("Player {} disconnected", player + 1));
+                    inputs.extend(slot.held.into_iter().map(|host| (host, false)));
+                }
+            }
+
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let Some(player) = self.player(which) {
+                    self.press(player, HostKey::PadButton { player, button }, &mut inputs);
+                }
+            }
+
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let Some(player) = self.player(which) {
+                    self.release(player, HostKey::PadButton { player, button }, &mut inputs);
+                }
+            }
+
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                if let Some(player) = self.player(which) {
+                    self.axis_motion(player, axis, value, &mut inputs);
+                }
+            }
+
+            _ => {}
+        }
+
+        inputs
+    }
+
+    fn axis_motion(
+        &mut self,
+        player: usize,
+        axis: Axis,
+        value: i16,
+        inputs: &mut Vec<(HostKey, bool)>,
+    ) {
+        let Some(n) = AXES.iter().position(|a| *a == axis) else {
+            return;
+        };
+
+        let direction = if value > self.dead_zone {
+            1
+        } else if value < -self.dead_zone {
+            -1
+        } else {
+            0
+        };
+
+        let slot = self.slots[player].as_mut().unwrap();
+        let previous = slot.axes[n];
+        if previous == direction {
+            return;
+        }
+        slot.axes[n] = direction;
+
+        if previous != 0 {
+            let positive = previous > 0;
+            let host = HostKey::PadAxis {
+                player,
+                axis,
+                positive,
+            };
+            self.release(player, host, inputs);
+        }
+        if direction != 0 {
+            let positive = direction > 0;
+            let host = HostKey::PadAxis {
+                player,
+                axis,
+                positive,
+            };
+            self.press(player, host, inputs);
+        }
+    }
+
+    fn press(&mut self, player: usize, host: HostKey, inputs: &mut Vec<(HostKey, bool)>) {
+        let slot = self.slots[player].as_mut().unwrap();
+        if !slot.held.contains(&host) {
+            slot.held.push(host);
+        }
+        inputs.push((host, true));
+    }
+
+    fn release(&mut self, player: usize, host: HostKey, inputs: &mut Vec<(HostKey, bool)>) {
+        let slot = self.slots[player].as_mut().unwrap();
+        slot.held.retain(|held| *held != host);
+        inputs.push((host, false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::KeyMap;
+    use sdl3::sys::{gamepad as sys_gamepad, joystick as sys_joystick};
+
+    fn attach_virtual_gamepad() -> (u32, *mut sys_joystick::SDL_Joystick) {
+        let mut desc = sys_joystick::SDL_VirtualJoystickDesc::new();
+        desc.r#type = sys_joystick::SDL_JOYSTICK_TYPE_GAMEPAD.0 as u16;
+        desc.naxes = sys_gamepad::SDL_GAMEPAD_AXIS_COUNT.0 as u16;
+        desc.nbuttons = sys_gamepad::SDL_GAMEPAD_BUTTON_COUNT.0 as u16;
+
+        unsafe {
+            let id = sys_joystick::SDL_AttachVirtualJoystick(&desc);
+            assert_ne!(id, 0, "failed to attach virtual joystick");
+            (id, sys_joystick::SDL_OpenJoystick(id))
+        }
+    }
+
+    fn pump(
+        event_pump: &mut sdl3::EventPump,
+        gamepads: &mut Gamepads,
+        osd: &mut Osd,
+        keymap: &KeyMap,
+        keys: &mut [bool; 16],
+    ) {
+        unsafe { sys_joystick::SDL_UpdateJoysticks() };
+        for event in event_pump.poll_iter() {
+            for (host, pressed) in gamepads.handle(&event, osd) {
+                for key in keymap.keys(&[host]) {
+                    keys[key] = pressed;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn virtual_gamepad_maps_to_keys() {
+        let sdl_context = sdl3::init().unwrap();
+        let mut gamepads = Gamepads::new(sdl_context.gamepad().unwrap());
+        let mut event_pump = sdl_context.event_pump().unwrap();
+        let mut osd = Osd::new(String::new());
+        let keymap = KeyMap::default();
+        let mut keys = [false; 16];
+
+        let (id, joystick) = attach_virtual_gamepad();
+        pump(&mut event_pump, &mut gamepads, &mut osd, &keymap, &mut keys);
+        assert!(gamepads.slots[0].is_some());
+
+        let dpad_up = sys_gamepad::SDL_GAMEPAD_BUTTON_DPAD_UP.0;
+        unsafe { sys_joystick::SDL_SetJoystickVirtualButton(joystick, dpad_up, true) };
+        pump(&mut event_pump, &mut gamepads, &mut osd, &keymap, &mut keys);
+        assert!(keys[0x5]);
+
+        unsafe { sys_joystick::SDL_SetJoystickVirtualButton(joystick, dpad_up, false) };
+        pump(&mut event_pump, &mut gamepads, &mut osd, &keymap, &mut keys);
+        assert!(!keys[0x5]);
+
+        // Inside the dead zone nothing happens, past it the stick acts as a d-pad.
+        let left_x = sys_gamepad::SDL_GAMEPAD_AXIS_LEFTX.0;
+        unsafe { sys_joystick::SDL_SetJoystickVirtualAxis(joystick, left_x, DEAD_ZONE / 2) };
+        pump(&mut event_pump, &mut gamepads, &mut osd, &keymap, &mut keys);
+        assert!(!keys[0x9]);
+
+        unsafe { sys_joystick::SDL_SetJoystickVirtualAxis(joystick, left_x, i16::MAX) };
+        pump(&mut event_pump, &mut gamepads, &mut osd, &keymap, &mut keys);
+        assert!(keys[0x9]);
+
+        // Unplugging releases whatever the gamepad was holding.
+        unsafe {
+            sys_joystick::SDL_CloseJoystick(joystick);
+            sys_joystick::SDL_DetachVirtualJoystick(id);
+        }
+        pump(&mut event_pump, &mut gamepads, &mut osd, &keymap, &mut keys);
+        assert!(gamepads.slots[0].is_none());
+        assert!(!keys[0x9]);
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::{cheat, Chip8};
+
+// Register numbers as GDB sees them: V0-VF, then I, PC, SP, DT and ST.
+const REGISTERS: usize = 21;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// A GDB Remote Serial Protocol server on localhost. It is polled from the
+// emulator's loop, never blocks it, and holds the program stopped while
+// a client is attached until told to continue.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    input: Vec<u8>,
+    breakpoints: BTreeSet<usize>,
+    running: bool,
+    // Where the last breakpoint stopped, so continuing steps over it.
+    stopped_at: Option<usize>,
+    // Running a single instruction for the client, which stops after it.
+    stepping: bool,
+}
+
+// What the client asked of the emulator beyond the machine itself.
+pub enum Request {
+    Kill,
+}
+
+impl GdbServer {
+    // Port 0 picks a free one; address() says which.
+    pub fn bind(port: u16) -> Result<GdbServer, String> {
+        let error = |e: std::io::Error| format!("gdb server on port {}: {}", port, e);
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(error)?;
+        listener.set_nonblocking(true).map_err(error)?;
+        Ok(GdbServer {
+            listener,
+            client: None,
+            input: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            running: true,
+            stopped_at: None,
+            stepping: false,
+        })
+    }
+
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    // Whether the program may run; without a client it always may.
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    // Checked before the instruction at PC runs. Stops at a breakpoint
+    // and tells the client, which then owns the machine again.
+    pub fn check_breakpoint(&mut self, chip8: &Chip8) -> bool {
+        let pc = chip8.program_counter;
+        if self.stopped_at == Some(pc) {
+            return false;
+        }
+        self.stopped_at = None;
+        if self.client.is_none() || !self.breakpoints.contains(&pc) {
+            return false;
+        }
+        self.stopped_at = Some(pc);
+        self.running = false;
+        self.stepping = false;
+        self.send("S05");
+        true
+    }
+
+    // Checked after an instruction has run. Stops if the client stepped
+    // it, as a breakpoint would.
+    pub fn check_step(&mut self, chip8: &Chip8) {
+        if self.stepping {
+            self.stepping = false;
+            self.stopped_at = Some(chip8.program_counter);
+            self.running = false;
+            self.send("S05");
+        }
+    }
+
+    // Stops at an instruction that cannot run, reported to the client as
+    // an illegal instruction; false if no client is attached.
+    pub fn fault(&mut self, chip8: &Chip8) -> bool {
+        if self.client.is_none() {
+            return false;
+        }
+        self.stepping = false;
+        self.stopped_at = Some(chip8.program_counter);
+        self.running = false;
+        self.send("S04");
+        true
+    }
+
+    // Accepts a client and answers whatever it has sent so far.
+    pub fn poll(&mut self, chip8: &mut Chip8) -> Option<Request> {
+        if self.client.is_none() {
+            let (stream, _) = self.listener.accept().ok()?;
+            if stream.set_nonblocking(true).is_err() {
+                return None;
+            }
+            self.client = Some(stream);
+            self.input.clear();
+            self.running = false;
+            self.stopped_at = Some(chip8.program_counter);
+        }
+
+        let mut buffer = [0; 4096];
+        loop {
+            let Some(stream) = &mut self.client else {
+                return None;
+            };
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.detach();
+                    return None;
+                }
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.detach();
+                    return None;
+                }
+            }
+        }
+
+        while let Some(packet) = self.next_packet() {
+            let Some(packet) = packet else {
+                // Ctrl-C from the client.
+                self.running = false;
+                self.stepping = false;
+                self.stopped_at = Some(chip8.program_counter);
+                self.send("S02");
+                continue;
+            };
+            if let Some(request) = self.handle(&packet, chip8) {
+                return Some(request);
+            }
+        }
+        None
+    }
+
+    // Takes the next packet from the input: Some(None) for an interrupt,
+    // None when no whole packet has arrived yet.
+    fn next_packet(&mut self) -> Option<Option<String>> {
+        loop {
+            match self.input.first()? {
+                b'$' => break,
+                0x03 => {
+                    self.input.remove(0);
+                    return Some(None);
+                }
+                // Acknowledgements and noise between packets.
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+        let end = self.input.iter().position(|b| *b == b'#')?;
+        if self.input.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if checksum != Some(sum) {
+            self.write(b"-");
+            return self.next_packet();
+        }
+        self.write(b"+");
+        Some(Some(String::from_utf8_lossy(data).into_owned()))
+    }
+
+    fn handle(&mut self, packet: &str, chip8: &mut Chip8) -> Option<Request> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => (0..REGISTERS).map(|n| register(chip8, n)).collect(),
+            Some(b'G') => match set_registers(chip8, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < REGISTERS => register(chip8, n),
+                _ => "E01".to_string(),
+            },
+            Some(b'P') => {
+                let set = packet[1..].split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    set_register(chip8, n, &bytes(value)?)
+                });
+                match set {
+                    Some(()) => "OK".to_string(),
+                    None => "E01".to_string(),
+                }
+            }
+            Some(b'm') => read_memory(chip8, &packet[1..]).unwrap_or_else(|| "E01".to_string()),
+            Some(b'M') => match write_memory(chip8, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            Some(b'Z' | b'z') => self.breakpoint(packet),
+            Some(b'c') => {
+                if let Some(address) = address(&packet[1..]) {
+                    chip8.program_counter = address;
+                }
+                self.running = true;
+                self.stepping = false;
+                return None;
+            }
+            // The emulator's loop runs the instruction like any other, and
+            // the reply comes once it has, from check_step or fault.
+            Some(b's') => {
+                if let Some(address) = address(&packet[1..]) {
+                    chip8.program_counter = address;
+                }
+                self.running = true;
+                self.stepping = true;
+                return None;
+            }
+            Some(b'H' | b'T') => "OK".to_string(),
+            Some(b'k') => {
+                self.detach();
+                return Some(Request::Kill);
+            }
+            Some(b'D') => {
+                self.send("OK");
+                self.detach();
+                return None;
+            }
+            Some(b'q') if packet.starts_with("qRcmd,") => monitor(chip8, &packet[6..]),
+            _ => self.query(packet),
+        };
+        self.send(&reply);
+        None
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = range.split_once(',') else {
+                return "E01".to_string();
+            };
+            let offset = usize::from_str_radix(offset, 16).unwrap_or(usize::MAX);
+            let length = usize::from_str_radix(length, 16).unwrap_or(0);
+            let Some(rest) = TARGET_XML.get(offset..) else {
+                return "E01".to_string();
+            };
+            return if rest.len() <= length {
+                format!("l{}", rest)
+            } else {
+                format!("m{}", &rest[..length])
+            };
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            // Anything else is unsupported, which GDB copes with.
+            _ => String::new(),
+        }
+    }
+
+    // Z0/Z1 set and z0/z1 clear a breakpoint; both kinds are the same here.
+    fn breakpoint(&mut self, packet: &str) -> String {
+        let mut fields = packet[1..].split(',');
+        let (Some(kind), Some(address)) = (fields.next(), fields.next()) else {
+            return "E01".to_string();
+        };
+        if kind != "0" && kind != "1" {
+            return String::new();
+        }
+        let Ok(address) = usize::from_str_radix(address, 16) else {
+            return "E01".to_string();
+        };
+        if packet.starts_with('Z') {
+            self.breakpoints.insert(address);
+        } else {
+            self.breakpoints.remove(&address);
+        }
+        "OK".to_string()
+    }
+
+    fn detach(&mut self) {
+        self.client = None;
+        self.breakpoints.clear();
+        self.running = true;
+        self.stepping = false;
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.write(format!("${}#{:02x}", data, sum).as_bytes());
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let Some(stream) = &mut self.client else {
+            return;
+        };
+        // Replies are small; a blocking write keeps them whole.
+        let sent = stream
+            .set_nonblocking(false)
+            .and_then(|()| stream.write_all(bytes))
+            .and_then(|()| stream.set_nonblocking(true));
+        if sent.is_err() {
+            self.detach();
+        }
+    }
+}
+
+// A register's value as little-endian hex.
+fn register(chip8: &Chip8, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", chip8.registry[n]),
+        16 => format!("{:04x}", (chip8.i as u16).swap_bytes()),
+        17 => format!("{:04x}", (chip8.program_counter as u16).swap_bytes()),
+        18 => format!("{:02x}", chip8.sub_pointer as u8),
+        19 => format!("{:02x}", chip8.delay_timer),
+        _ => format!("{:02x}", chip8.sound_timer),
+    }
+}
+
+fn register_size(n: usize) -> usize {
+    if n == 16 || n == 17 {
+        2
+    } else {
+        1
+    }
+}
+
+fn set_register(chip8: &mut Chip8, n: usize, value: &[u8]) -> Option<()> {
+    if n >= REGISTERS || value.len() != register_size(n) {
+        return None;
+    }
+    let wide = || value[0] as usize | (value[1] as usize) << 8;
+    match n {
+        0..=15 => chip8.registry[n] = value[0],
+        16 => chip8.i = wide(),
+        17 => chip8.program_counter = wide(),
+        18 if (value[0] as usize) <= chip8.stack.len() => chip8.sub_pointer = value[0] as usize,
+        18 => return None,
+        19 => chip8.delay_timer = value[0],
+        _ => chip8.sound_timer = value[0],
+    }
+    Some(())
+}
+
+fn set_registers(chip8: &mut Chip8, hex: &str) -> Option<()> {
+    let values = bytes(hex)?;
+    let mut offset = 0;
+    for n in 0..REGISTERS {
+        let size = register_size(n);
+        set_register(chip8, n, values.get(offset..offset + size)?)?;
+        offset += size;
+    }
+    Some(())
+}
+
+// "addr,length" as hex.
+fn range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+// "monitor search" and the other cheat commands, with the output hex
+// encoded for GDB to print.
+fn monitor(chip8: &mut Chip8, hex: &str) -> String {
+    let Some(command) = bytes(hex).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+        return "E01".to_string();
+    };
+    let output = match cheat::command(chip8, &command) {
+        Some(Ok(text) | Err(text)) => text,
+        None => format!("monitor commands:\n{}", cheat::HELP),
+    };
+    format!("{}\n", output)
+        .bytes()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn read_memory(chip8: &Chip8, text: &str) -> Option<String> {
+    let (address, length) = range(text)?;
+    let bytes = chip8.memory.get(address..address.checked_add(length)?)?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn write_memory(chip8: &mut Chip8, text: &str) -> Option<()> {
+    let (range_text, data) = text.split_once(':')?;
+    let (address, length) = range(range_text)?;
+    let data = bytes(data)?;
+    if data.len() != length {
+        return None;
+    }
+    chip8
+        .memory
+        .get_mut(address..address.checked_add(length)?)?
+        .copy_from_slice(&data);
+    Some(())
+}
+
+fn address(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(hex.get(n..n + 2)?, 16).ok())
+        .collect()
+}
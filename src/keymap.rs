@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use sdl3::{
+    event::Event,
+    gamepad::{Axis, Button},
+    keyboard::{Keycode, Scancode},
+    pixels::Color,
+    render::Canvas,
+    video::Window,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::gamepad::PLAYERS;
+use crate::osd::draw_text;
+
+// Chip8 keys in the order they appear on the COSMAC VIP keypad.
+pub const LAYOUT: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF, //
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum HostKey {
+    Keycode(Keycode),
+    Scancode(Scancode),
+    PadButton {
+        player: usize,
+        button: Button,
+    },
+    // A stick or trigger pushed past the dead zone in one direction.
+    PadAxis {
+        player: usize,
+        axis: Axis,
+        positive: bool,
+    },
+}
+
+impl HostKey {
+    fn parse(name: &str) -> Result<HostKey, String> {
+        if let Some(name) = name.strip_prefix("scancode:") {
+            Scancode::from_name(name)
+                .map(HostKey::Scancode)
+                .ok_or(format!("unknown scancode \"{}\"", name))
+        } else if let Some(pad) = name.strip_prefix("pad") {
+            HostKey::parse_pad(pad).ok_or(format!("unknown gamepad input \"{}\"", name))
+        } else {
+            Keycode::from_name(name)
+                .map(HostKey::Keycode)
+                .ok_or(format!("unknown keycode \"{}\"", name))
+        }
+    }
+
+    // Gamepad inputs look like "pad1:dpup" or "pad2:leftx-".
+    fn parse_pad(name: &str) -> Option<HostKey> {
+        let (player, input) = name.split_once(':')?;
+        let player = match player.parse::<usize>() {
+            Ok(n) if (1..=PLAYERS).contains(&n) => n - 1,
+            _ => return None,
+        };
+
+        if let Some(axis) = input.strip_suffix('+') {
+            Axis::from_string(axis).map(|axis| HostKey::PadAxis {
+                player,
+                axis,
+                positive: true,
+            })
+        } else if let Some(axis) = input.strip_suffix('-') {
+            Axis::from_string(axis).map(|axis| HostKey::PadAxis {
+                player,
+                axis,
+                positive: false,
+            })
+        } else {
+            Button::from_string(input).map(|button| HostKey::PadButton { player, button })
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            HostKey::Keycode(keycode) => keycode.name(),
+            HostKey::Scancode(scancode) => format!("scancode:{}", scancode.name()),
+            HostKey::PadButton { player, button } => {
+                format!("pad{}:{}", player + 1, button.string())
+            }
+            HostKey::PadAxis {
+                player,
+                axis,
+                positive,
+            } => {
+                let sign = if *positive { '+' } else { '-' };
+                format!("pad{}:{}{}", player + 1, axis.string(), sign)
+            }
+        }
+    }
+}
+
+// The host keys behind a keyboard event, and whether they went down.
+pub fn event_hosts(event: &Event) -> Option<(Vec<HostKey>, bool)> {
+    let (keycode, scancode, pressed) = match *event {
+        Event::KeyDown {
+            keycode,
+            scancode,
+            repeat: false,
+            ..
+        } => (keycode, scancode, true),
+        Event::KeyUp {
+            keycode, scancode, ..
+        } => (keycode, scancode, false),
+        _ => return None,
+    };
+
+    let mut hosts = Vec::new();
+    hosts.extend(keycode.map(HostKey::Keycode));
+    hosts.extend(scancode.map(HostKey::Scancode));
+    Some((hosts, pressed))
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct KeyMapFile {
+    #[serde(default)]
+    keys: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    rom: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Clone)]
+pub struct KeyMap {
+    bindings: [Vec<HostKey>; 16],
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        let host = [
+            Keycode::_1,
+            Keycode::_2,
+            Keycode::_3,
+            Keycode::_4,
+            Keycode::Q,
+            Keycode::W,
+            Keycode::E,
+            Keycode::R,
+            Keycode::A,
+            Keycode::S,
+            Keycode::D,
+            Keycode::F,
+            Keycode::Z,
+            Keycode::X,
+            Keycode::C,
+            Keycode::V,
+        ];
+
+        let mut keymap = KeyMap {
+            bindings: Default::default(),
+        };
+        for (key, keycode) in LAYOUT.iter().zip(host) {
+            keymap.bindings[*key].push(HostKey::Keycode(keycode));
+        }
+
+        let pad = [
+            (0x5, Button::DPadUp, Axis::LeftY, false),
+            (0x8, Button::DPadDown, Axis::LeftY, true),
+            (0x7, Button::DPadLeft, Axis::LeftX, false),
+            (0x9, Button::DPadRight, Axis::LeftX, true),
+        ];
+        for (key, button, axis, positive) in pad {
+            keymap.bindings[key].push(HostKey::PadButton { player: 0, button });
+            keymap.bindings[key].push(HostKey::PadAxis {
+                player: 0,
+                axis,
+                positive,
+            });
+        }
+        keymap.bindings[0x6].push(HostKey::PadButton {
+            player: 0,
+            button: Button::South,
+        });
+        keymap.bindings[0x4].push(HostKey::PadButton {
+            player: 0,
+            button: Button::East,
+        });
+        keymap
+    }
+}
+
+impl KeyMap {
+    // Layers a key binding file, and its section for the ROM, on top.
+    pub fn load(&mut self, path: &Path, rom_name: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let file: KeyMapFile =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        self.apply(&file.keys)?;
+        if let Some(keys) = file.rom.get(rom_name) {
+            self.apply(keys)?;
+        }
+        Ok(())
+    }
+
+    // Writes the bindings of the given keys into the global table, or into
+    // the ROM's section, and leaves the rest of the file as it was.
+    pub fn save(&self, path: &Path, keys: &[usize], rom: Option<&str>) -> Result<(), String> {
+        let mut file: KeyMapFile = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(_) => KeyMapFile::default(),
+        };
+
+        let table = match rom {
+            Some(rom) => file.rom.entry(rom.to_string()).or_default(),
+            None => &mut file.keys,
+        };
+        for &key in keys {
+            let names = self.bindings[key].iter().map(HostKey::name).collect();
+            table.insert(format!("{:X}", key), names);
+        }
+
+        let text = toml::to_string(&file).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn apply(&mut self, keys: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        for (key, names) in keys {
+            let key = match u8::from_str_radix(key, 16) {
+                Ok(key) if key < 16 => key as usize,
+                _ => return Err(format!("\"{}\" is not a chip8 key (0-F)", key)),
+            };
+
+            self.bindings[key] = names
+                .iter()
+                .map(|name| HostKey::parse(name))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    pub fn bindings(&self, key: usize) -> &[HostKey] {
+        &self.bindings[key]
+    }
+
+    pub fn keys<'a>(&'a self, hosts: &'a [HostKey]) -> impl Iterator<Item = usize> + 'a {
+        (0..16).filter(move |key| self.bindings[*key].iter().any(|host| hosts.contains(host)))
+    }
+}
+
+// Host keys and on-screen panel keys held down. A chip8 key held through
+// several of them stays down until the last one comes up.
+#[derive(Default)]
+pub struct HeldKeys {
+    hosts: Vec<HostKey>,
+    panel: [bool; 16],
+}
+
+impl HeldKeys {
+    // The chip8 keys behind some host keys going down or up, and whether
+    // each is now down.
+    pub fn update(
+        &mut self,
+        hosts: &[HostKey],
+        pressed: bool,
+        keymap: &KeyMap,
+    ) -> Vec<(usize, bool)> {
+        if pressed {
+            for host in hosts {
+                if !self.hosts.contains(host) {
+                    self.hosts.push(*host);
+                }
+            }
+        } else {
+            self.hosts.retain(|host| !hosts.contains(host));
+        }
+        keymap
+            .keys(hosts)
+            .map(|key| (key, pressed || self.held(key, keymap)))
+            .collect()
+    }
+
+    // A key going down or up on the panel, and whether it is now down.
+    pub fn update_panel(&mut self, key: usize, pressed: bool, keymap: &KeyMap) -> bool {
+        self.panel[key] = pressed;
+        pressed || self.held(key, keymap)
+    }
+
+    fn held(&self, key: usize, keymap: &KeyMap) -> bool {
+        self.panel[key]
+            || keymap
+                .bindings(key)
+                .iter()
+                .any(|host| self.hosts.contains(host))
+    }
+
+    pub fn clear(&mut self) {
+        self.hosts.clear();
+        self.panel = [false; 16];
+    }
+}
+
+pub struct Rebinder {
+    position: usize,
+    pressed: bool,
+    // Keys rebound on this screen; only these are saved.
+    changed: Vec<usize>,
+    // The running ROM, whose section Tab switches to, and the section the
+    // bindings are saved to if not the global table.
+    rom: Option<String>,
+    scope: Option<String>,
+}
+
+impl Rebinder {
+    pub fn new(rom: Option<String>) -> Rebinder {
+        Rebinder {
+            position: 0,
+            pressed: false,
+            changed: Vec::new(),
+            rom,
+            scope: None,
+        }
+    }
+
+    // Writes the keys rebound here to the file, in the scope they were
+    // edited in; false if nothing was rebound.
+    pub fn save(&self, keymap: &KeyMap, path: &Path) -> Result<bool, String> {
+        if self.changed.is_empty() {
+            return Ok(false);
+        }
+        keymap.save(path, &self.changed, self.scope.as_deref())?;
+        Ok(true)
+    }
+
+    fn change(&mut self, key: usize) {
+        if !self.changed.contains(&key) {
+            self.changed.push(key);
+        }
+    }
+
+    // Returns false once every key has been visited or the screen was closed.
+    pub fn handle(&mut self, event: &Event, keymap: &mut KeyMap) -> bool {
+        let Event::KeyDown {
+            keycode: Some(keycode),
+            repeat: false,
+            ..
+        } = event
+        else {
+            return true;
+        };
+
+        let key = LAYOUT[self.position];
+        match *keycode {
+            Keycode::Escape => return false,
+            Keycode::Return => {
+                self.position += 1;
+                self.pressed = false;
+            }
+            Keycode::Backspace => {
+                keymap.bindings[key].clear();
+                self.change(key);
+                self.pressed = false;
+            }
+            Keycode::Tab => {
+                self.scope = match self.scope {
+                    Some(_) => None,
+                    None => self.rom.clone(),
+                };
+            }
+            keycode => self.bind(HostKey::Keycode(keycode), keymap),
+        }
+
+        self.position < LAYOUT.len()
+    }
+
+    pub fn bind(&mut self, host: HostKey, keymap: &mut KeyMap) {
+        let key = LAYOUT[self.position];
+        if !self.pressed {
+            keymap.bindings[key].clear();
+            self.change(key);
+            self.pressed = true;
+        }
+        if !keymap.bindings[key].contains(&host) {
+            keymap.bindings[key].push(host);
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, keymap: &KeyMap) {
+        canvas.set_draw_color(Color::RGB(16, 16, 64));
+        canvas.clear();
+        let (scale_x, scale_y) = canvas.scale();
+        canvas.set_scale(3.0, 3.0).unwrap();
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+
+        let title = match &self.scope {
+            Some(rom) => format!("REBIND KEYS FOR {}", rom),
+            None => "REBIND KEYS FOR ALL ROMS".to_string(),
+        };
+        draw_text(canvas, 2, 2, &title);
+        let help = match self.rom {
+            Some(_) => "ENTER NEXT  BACKSPACE CLEAR  TAB ROM/ALL  ESC DONE",
+            None => "ENTER NEXT  BACKSPACE CLEAR  ESC DONE",
+        };
+        draw_text(canvas, 2, 10, help);
+
+        for (n, key) in LAYOUT.iter().enumerate() {
+            let names: Vec<String> = keymap.bindings[*key].iter().map(HostKey::name).collect();
+            let marker = if n == self.position { ">" } else { " " };
+            let line = format!("{}{:X} {}", marker, key, names.join(" "));
+
+            let x = 2 + (n % 4) as i32 * 80;
+            let y = 24 + (n / 4) as i32 * 10;
+            draw_text(canvas, x, y, &line);
+        }
+
+        canvas.set_scale(scale_x, scale_y).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_stays_down_while_another_binding_is_held() {
+        let w = [HostKey::Keycode(Keycode::W)];
+        let up = [HostKey::Keycode(Keycode::Up)];
+        let mut keymap = KeyMap::default();
+        keymap.bindings[5] = vec![w[0], up[0]];
+        let mut held = HeldKeys::default();
+
+        assert_eq!(held.update(&w, true, &keymap), [(5, true)]);
+        assert_eq!(held.update(&up, true, &keymap), [(5, true)]);
+        assert_eq!(held.update(&w, false, &keymap), [(5, true)]);
+        assert_eq!(held.update(&up, false, &keymap), [(5, false)]);
+    }
+
+    #[test]
+    fn panel_and_keyboard_hold_a_key_together() {
+        let w = [HostKey::Keycode(Keycode::W)];
+        let mut keymap = KeyMap::default();
+        keymap.bindings[5] = vec![w[0]];
+        let mut held = HeldKeys::default();
+
+        assert_eq!(held.update(&w, true, &keymap), [(5, true)]);
+        assert!(held.update_panel(5, true, &keymap));
+        assert!(held.update_panel(5, false, &keymap));
+        assert!(held.update_panel(5, true, &keymap));
+        assert_eq!(held.update(&w, false, &keymap), [(5, true)]);
+        assert!(!held.update_panel(5, false, &keymap));
+    }
+
+    #[test]
+    fn saving_writes_only_the_rebound_keys_in_their_scope() {
+        let path = std::env::temp_dir().join(format!("chip8-keys-{}.toml", std::process::id()));
+        fs::write(&path, "[keys]\n1 = [\"X\"]\n\n[rom.pong]\n4 = [\"S\"]\n").unwrap();
+        let mut keymap = KeyMap::default();
+        keymap.bindings[5].clear();
+        keymap.bindings[6].clear();
+
+        keymap.save(&path, &[5], Some("pong")).unwrap();
+        keymap.save(&path, &[6], None).unwrap();
+        let file: KeyMapFile = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let names = |pairs: &[(&str, &[&str])]| -> BTreeMap<String, Vec<String>> {
+            pairs
+                .iter()
+                .map(|(key, hosts)| {
+                    (
+                        key.to_string(),
+                        hosts.iter().map(|h| h.to_string()).collect(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(file.keys, names(&[("1", &["X"]), ("6", &[])]));
+        assert_eq!(file.rom["pong"], names(&[("4", &["S"]), ("5", &[])]));
+    }
+}
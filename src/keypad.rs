@@ -0,0 +1,158 @@
+// How Fx0A decides a key has been entered.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WaitMode {
+    // COSMAC VIP: the key has to be pressed and released again.
+    Release,
+    // Some later interpreters finish as soon as a key goes down.
+    Press,
+}
+
+#[derive(Default)]
+pub struct Keypad {
+    down: [bool; 16],
+    pressed: [bool; 16],
+    released: [bool; 16],
+    polled: [bool; 16],
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad {
+            down: [false; 16],
+            pressed: [false; 16],
+            released: [false; 16],
+            polled: [false; 16],
+        }
+    }
+
+    pub fn set(&mut self, key: usize, down: bool) {
+        if self.down[key] == down {
+            return;
+        }
+
+        self.down[key] = down;
+        if down {
+            self.pressed[key] = true;
+        } else {
+            self.released[key] = true;
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        for key in 0..16 {
+            self.set(key, false);
+        }
+    }
+
+    pub fn is_down(&self, key: usize) -> bool {
+        self.down[key]
+    }
+
+    pub fn was_pressed(&self, key: usize) -> bool {
+        self.pressed[key]
+    }
+
+    pub fn was_released(&self, key: usize) -> bool {
+        self.released[key]
+    }
+
+    pub fn down(&self) -> &[bool; 16] {
+        &self.down
+    }
+
+    // Marks a key as checked by the ROM, or every key while it waits for any.
+    pub fn poll(&mut self, key: Option<usize>) {
+        match key {
+            Some(key) => self.polled[key] = true,
+            None => self.polled = [true; 16],
+        }
+    }
+
+    pub fn polled(&self) -> &[bool; 16] {
+        &self.polled
+    }
+
+    // Forgets a key's edges once Fx0A has taken them, so another Fx0A in
+    // the same frame waits for a new key.
+    pub fn consume(&mut self, key: usize) {
+        self.pressed[key] = false;
+        self.released[key] = false;
+    }
+
+    // Called once per frame after the emulator has seen this frame's edges.
+    pub fn end_frame(&mut self) {
+        self.pressed = [false; 16];
+        self.released = [false; 16];
+        self.polled = [false; 16];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+
+    // Fx0A twice: LD V0, K then LD V1, K.
+    fn waiting(mode: WaitMode) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.power_on().unwrap();
+        chip8.memory[0x200..0x204].copy_from_slice(&[0xF0, 0x0A, 0xF1, 0x0A]);
+        chip8.wait_mode = mode;
+        chip8
+    }
+
+    #[test]
+    fn edges_last_until_the_end_of_the_frame() {
+        let mut keypad = Keypad::new();
+        keypad.set(3, true);
+        keypad.set(3, false);
+        assert!(keypad.was_pressed(3) && keypad.was_released(3) && !keypad.is_down(3));
+        keypad.end_frame();
+        assert!(!keypad.was_pressed(3) && !keypad.was_released(3));
+        // Holding a key is not a new press.
+        keypad.set(3, true);
+        keypad.end_frame();
+        keypad.set(3, true);
+        assert!(!keypad.was_pressed(3) && keypad.is_down(3));
+    }
+
+    #[test]
+    fn press_mode_finishes_when_a_key_goes_down() {
+        let mut chip8 = waiting(WaitMode::Press);
+        assert_eq!(chip8.execute(false), Ok(false));
+        chip8.keypad.set(7, true);
+        assert_eq!(chip8.execute(false), Ok(true));
+        assert_eq!(chip8.registry[0], 7);
+        // The same press does not finish the next Fx0A too.
+        assert_eq!(chip8.execute(false), Ok(false));
+        chip8.keypad.end_frame();
+        chip8.keypad.set(7, false);
+        chip8.keypad.set(9, true);
+        assert_eq!(chip8.execute(false), Ok(true));
+        assert_eq!(chip8.registry[1], 9);
+    }
+
+    #[test]
+    fn release_mode_finishes_when_the_key_comes_up() {
+        let mut chip8 = waiting(WaitMode::Release);
+        chip8.keypad.set(7, true);
+        assert_eq!(chip8.execute(false), Ok(false));
+        chip8.keypad.set(7, false);
+        assert_eq!(chip8.execute(false), Ok(true));
+        assert_eq!(chip8.registry[0], 7);
+        assert_eq!(chip8.execute(false), Ok(false));
+        assert_eq!(chip8.program_counter, 0x202);
+    }
+
+    #[test]
+    fn two_waits_in_a_frame_take_different_keys() {
+        let mut chip8 = waiting(WaitMode::Release);
+        chip8.keypad.set(7, true);
+        chip8.keypad.set(7, false);
+        chip8.keypad.set(2, true);
+        chip8.keypad.set(2, false);
+        assert_eq!(chip8.execute(false), Ok(true));
+        assert_eq!(chip8.execute(false), Ok(true));
+        assert_eq!(chip8.registry[..2], [2, 7]);
+    }
+}
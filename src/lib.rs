@@ -0,0 +1,1111 @@
+// The CHIP-8 machine itself: memory, registers, the display and the
+// instructions, with the platform quirks and memory layouts they run under.
+// The emulator in main.rs and the fuzz targets in fuzz/ both build on it.
+
+use rand::prelude::*;
+
+use cheat::Cheats;
+use keypad::{Keypad, WaitMode};
+use quirks::{Layout, Platform, Quirks};
+use romdb::{RomDb, RomInfo};
+use symbols::Symbols;
+
+pub mod cheat;
+pub mod font;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+pub mod keypad;
+pub mod quirks;
+#[cfg(test)]
+mod reference;
+pub mod romdb;
+pub mod symbols;
+
+// Calls the stack holds, as in most interpreters after the VIP's 12.
+pub const STACK: usize = 16;
+
+pub const FONT: [u8; 80] = [
+    0xF0u8, 0x90u8, 0x90u8, 0x90u8, 0xF0u8, // 0
+    0x20u8, 0x60u8, 0x20u8, 0x20u8, 0x70u8, // 1
+    0xF0u8, 0x10u8, 0xF0u8, 0x80u8, 0xF0u8, // 2
+    0xF0u8, 0x10u8, 0xF0u8, 0x10u8, 0xF0u8, // 3
+    0x90u8, 0x90u8, 0xF0u8, 0x10u8, 0x10u8, // 4
+    0xF0u8, 0x80u8, 0xF0u8, 0x10u8, 0xF0u8, // 5
+    0xF0u8, 0x80u8, 0xF0u8, 0x90u8, 0xF0u8, // 6
+    0xF0u8, 0x10u8, 0x20u8, 0x40u8, 0x40u8, // 7
+    0xF0u8, 0x90u8, 0xF0u8, 0x90u8, 0xF0u8, // 8
+    0xF0u8, 0x90u8, 0xF0u8, 0x10u8, 0xF0u8, // 9
+    0xF0u8, 0x90u8, 0xF0u8, 0x90u8, 0x90u8, // A
+    0xE0u8, 0x90u8, 0xE0u8, 0x90u8, 0xE0u8, // B
+    0xF0u8, 0x80u8, 0x80u8, 0x80u8, 0xF0u8, // C
+    0xE0u8, 0x90u8, 0x90u8, 0x90u8, 0xE0u8, // D
+    0xF0u8, 0x80u8, 0xF0u8, 0x80u8, 0xF0u8, // E
+    0xF0u8, 0x80u8, 0xF0u8, 0x80u8, 0x80u8, // F
+];
+
+// A number in decimal or, with 0x, in hex.
+pub fn parse_number(text: &str) -> Result<usize, String> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+// The memory an instruction read or wrote, noted while it runs.
+#[derive(Clone, Copy)]
+pub struct MemoryAccess {
+    pub pc: usize,
+    pub start: usize,
+    pub len: usize,
+    pub write: bool,
+}
+
+pub enum Opcode {
+    Clear,
+    Return,
+
+    Random { x: u8, n0: u8, n1: u8 },
+
+    NormalRegistry { x: u8, n0: u8, n1: u8 },
+    IndexRegistry { n0: u8, n1: u8, n2: u8 },
+    AddRegistry { x: u8, n0: u8, n1: u8 },
+
+    SaveToMemory { x: u8 },
+    LoadFromMemory { x: u8 },
+    AddVxToI { x: u8 },
+    SaveDigits { x: u8 },
+    SetSprite { x: u8 },
+
+    SetTimer { x: u8 },
+    SaveTimer { x: u8 },
+    SetSoundTimer { x: u8 },
+
+    SkipIfEqualXN { x: u8, n0: u8, n1: u8 },
+    SkipIfNotEqualXN { x: u8, n0: u8, n1: u8 },
+    SkipIfEqualXY { x: u8, y: u8 },
+    SkipIfNotEqualXY { x: u8, y: u8 },
+    Jump { n0: u8, n1: u8, n2: u8 },
+    JumpOffset { n0: u8, n1: u8, n2: u8 },
+    Subroutine { n0: u8, n1: u8, n2: u8 },
+
+    Set { x: u8, y: u8 },
+    Or { x: u8, y: u8 },
+    And { x: u8, y: u8 },
+    Xor { x: u8, y: u8 },
+    Add { x: u8, y: u8 },
+    Subtract { x: u8, y: u8 },
+    SubtractRev { x: u8, y: u8 },
+    ShiftLeft { x: u8, y: u8 },
+    ShiftRight { x: u8, y: u8 },
+
+    SkipIfKeyDown { x: u8 },
+    SkipIfKeyUp { x: u8 },
+    WaitKeyDown { x: u8 },
+
+    Draw { x: u8, y: u8, n: u8 },
+
+    // SCHIP
+    ScrollDown { n: u8 },
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    SetBigSprite { x: u8 },
+    SaveFlags { x: u8 },
+    LoadFlags { x: u8 },
+
+    // XO-CHIP
+    ScrollUp { n: u8 },
+    SaveRange { x: u8, y: u8 },
+    LoadRange { x: u8, y: u8 },
+    LongIndex,
+    Plane { n: u8 },
+    LoadAudio,
+    SetPitch { x: u8 },
+
+    None { raw: RawOpCode },
+}
+
+impl Opcode {
+    // The address a jump, call or I load refers to.
+    pub fn target(&self) -> Option<usize> {
+        match *self {
+            Opcode::Jump { n0, n1, n2 }
+            | Opcode::JumpOffset { n0, n1, n2 }
+            | Opcode::Subroutine { n0, n1, n2 }
+            | Opcode::IndexRegistry { n0, n1, n2 } => Some(Chip8::to_decimal(n0, n1, n2) as usize),
+            _ => None,
+        }
+    }
+
+    // The variant's name, for counting instructions by kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::Clear => "Clear",
+            Opcode::Return => "Return",
+            Opcode::Random { .. } => "Random",
+            Opcode::NormalRegistry { .. } => "NormalRegistry",
+            Opcode::IndexRegistry { .. } => "IndexRegistry",
+            Opcode::AddRegistry { .. } => "AddRegistry",
+            Opcode::SaveToMemory { .. } => "SaveToMemory",
+            Opcode::LoadFromMemory { .. } => "LoadFromMemory",
+            Opcode::AddVxToI { .. } => "AddVxToI",
+            Opcode::SaveDigits { .. } => "SaveDigits",
+            Opcode::SetSprite { .. } => "SetSprite",
+            Opcode::SetTimer { .. } => "SetTimer",
+            Opcode::SaveTimer { .. } => "SaveTimer",
+            Opcode::SetSoundTimer { .. } => "SetSoundTimer",
+            Opcode::SkipIfEqualXN { .. } => "SkipIfEqualXN",
+            Opcode::SkipIfNotEqualXN { .. } => "SkipIfNotEqualXN",
+            Opcode::SkipIfEqualXY { .. } => "SkipIfEqualXY",
+            Opcode::SkipIfNotEqualXY { .. } => "SkipIfNotEqualXY",
+            Opcode::Jump { .. } => "Jump",
+            Opcode::JumpOffset { .. } => "JumpOffset",
+            Opcode::Subroutine { .. } => "Subroutine",
+            Opcode::Set { .. } => "Set",
+            Opcode::Or { .. } => "Or",
+            Opcode::And { .. } => "And",
+            Opcode::Xor { .. } => "Xor",
+            Opcode::Add { .. } => "Add",
+            Opcode::Subtract { .. } => "Subtract",
+            Opcode::SubtractRev { .. } => "SubtractRev",
+            Opcode::ShiftLeft { .. } => "ShiftLeft",
+            Opcode::ShiftRight { .. } => "ShiftRight",
+            Opcode::SkipIfKeyDown { .. } => "SkipIfKeyDown",
+            Opcode::SkipIfKeyUp { .. } => "SkipIfKeyUp",
+            Opcode::WaitKeyDown { .. } => "WaitKeyDown",
+            Opcode::Draw { .. } => "Draw",
+            Opcode::ScrollDown { .. } => "ScrollDown",
+            Opcode::ScrollRight => "ScrollRight",
+            Opcode::ScrollLeft => "ScrollLeft",
+            Opcode::Exit => "Exit",
+            Opcode::LowRes => "LowRes",
+            Opcode::HighRes => "HighRes",
+            Opcode::SetBigSprite { .. } => "SetBigSprite",
+            Opcode::SaveFlags { .. } => "SaveFlags",
+            Opcode::LoadFlags { .. } => "LoadFlags",
+            Opcode::ScrollUp { .. } => "ScrollUp",
+            Opcode::SaveRange { .. } => "SaveRange",
+            Opcode::LoadRange { .. } => "LoadRange",
+            Opcode::LongIndex => "LongIndex",
+            Opcode::Plane { .. } => "Plane",
+            Opcode::LoadAudio => "LoadAudio",
+            Opcode::SetPitch { .. } => "SetPitch",
+            Opcode::None { .. } => "None",
+        }
+    }
+
+    // Assembly in the usual Cowgod style, like "LD V1, #0C".
+    pub fn mnemonic(&self) -> String {
+        let address = |n0, n1, n2| format!("#{:03X}", Chip8::to_decimal(n0, n1, n2));
+        match *self {
+            Opcode::Clear => "CLS".to_string(),
+            Opcode::Return => "RET".to_string(),
+            Opcode::Random { x, n0, n1 } => format!("RND V{:X}, #{:X}{:X}", x, n0, n1),
+            Opcode::NormalRegistry { x, n0, n1 } => format!("LD V{:X}, #{:X}{:X}", x, n0, n1),
+            Opcode::IndexRegistry { n0, n1, n2 } => format!("LD I, {}", address(n0, n1, n2)),
+            Opcode::AddRegistry { x, n0, n1 } => format!("ADD V{:X}, #{:X}{:X}", x, n0, n1),
+            Opcode::SaveToMemory { x } => format!("LD [I], V{:X}", x),
+            Opcode::LoadFromMemory { x } => format!("LD V{:X}, [I]", x),
+            Opcode::AddVxToI { x } => format!("ADD I, V{:X}", x),
+            Opcode::SaveDigits { x } => format!("LD B, V{:X}", x),
+            Opcode::SetSprite { x } => format!("LD F, V{:X}", x),
+            Opcode::SetTimer { x } => format!("LD DT, V{:X}", x),
+            Opcode::SaveTimer { x } => format!("LD V{:X}, DT", x),
+            Opcode::SetSoundTimer { x } => format!("LD ST, V{:X}", x),
+            Opcode::SkipIfEqualXN { x, n0, n1 } => format!("SE V{:X}, #{:X}{:X}", x, n0, n1),
+            Opcode::SkipIfNotEqualXN { x, n0, n1 } => format!("SNE V{:X}, #{:X}{:X}", x, n0, n1),
+            Opcode::SkipIfEqualXY { x, y } => format!("SE V{:X}, V{:X}", x, y),
+            Opcode::SkipIfNotEqualXY { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+            Opcode::Jump { n0, n1, n2 } => format!("JP {}", address(n0, n1, n2)),
+            Opcode::JumpOffset { n0, n1, n2 } => format!("JP V0, {}", address(n0, n1, n2)),
+            Opcode::Subroutine { n0, n1, n2 } => format!("CALL {}", address(n0, n1, n2)),
+            Opcode::Set { x, y } => format!("LD V{:X}, V{:X}", x, y),
+            Opcode::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+            Opcode::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+            Opcode::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+            Opcode::Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+            Opcode::Subtract { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+            Opcode::SubtractRev { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+            Opcode::ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+            Opcode::ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+            Opcode::SkipIfKeyDown { x } => format!("SKP V{:X}", x),
+            Opcode::SkipIfKeyUp { x } => format!("SKNP V{:X}", x),
+            Opcode::WaitKeyDown { x } => format!("LD V{:X}, K", x),
+            Opcode::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Opcode::ScrollDown { n } => format!("SCD {}", n),
+            Opcode::ScrollRight => "SCR".to_string(),
+            Opcode::ScrollLeft => "SCL".to_string(),
+            Opcode::Exit => "EXIT".to_string(),
+            Opcode::LowRes => "LOW".to_string(),
+            Opcode::HighRes => "HIGH".to_string(),
+            Opcode::SetBigSprite { x } => format!("LD HF, V{:X}", x),
+            Opcode::SaveFlags { x } => format!("LD R, V{:X}", x),
+            Opcode::LoadFlags { x } => format!("LD V{:X}, R", x),
+            Opcode::ScrollUp { n } => format!("SCU {}", n),
+            Opcode::SaveRange { x, y } => format!("LD [I], V{:X}-V{:X}", x, y),
+            Opcode::LoadRange { x, y } => format!("LD V{:X}-V{:X}, [I]", x, y),
+            Opcode::LongIndex => "LD I, long".to_string(),
+            Opcode::Plane { n } => format!("PLANE {}", n),
+            Opcode::LoadAudio => "AUDIO".to_string(),
+            Opcode::SetPitch { x } => format!("PITCH V{:X}", x),
+            Opcode::None { ref raw } => format!("DW #{}", raw.as_string()),
+        }
+    }
+}
+
+pub struct RawOpCode {
+    pub v0: u8,
+    pub v1: u8,
+}
+
+impl RawOpCode {
+    pub fn as_string(&self) -> String {
+        format!("{:02X}{:02X}", self.v0, self.v1)
+    }
+}
+
+pub struct Chip8 {
+    pub memory: Vec<u8>,
+    pub registry: [u8; 16],
+    pub stack: [usize; STACK],
+    pub keypad: Keypad,
+    pub sub_pointer: usize,
+    pub i: usize,
+    pub layout: Layout,
+    pub end: usize,
+    pub program_counter: usize,
+    // Only the top 32 rows are used outside hi-res mode.
+    pub pixel_map: [[u8; 64]; 64],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    wait_key: Option<usize>,
+    pub wait_mode: WaitMode,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub ipf: Option<u32>,
+    pub palette: [(u8, u8, u8); 2],
+    // Small glyphs followed by big ones, copied to the font address.
+    pub font: Vec<u8>,
+    // Memory read or written by the last instruction, for watchpoints.
+    pub access: Option<MemoryAccess>,
+    pub symbols: Symbols,
+    // Values held in place every frame.
+    pub cheats: Cheats,
+    pub rom: Vec<u8>,
+    rng: ThreadRng,
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+impl Chip8 {
+    pub fn new() -> Chip8 {
+        Chip8 {
+            memory: vec![0; Layout::default().memory],
+            registry: [0; 16],
+            stack: [0; STACK],
+            keypad: Keypad::new(),
+            sub_pointer: 0,
+            i: 0,
+            layout: Layout::default(),
+            end: 512,
+            program_counter: 512,
+            pixel_map: [[0; 64]; 64],
+            delay_timer: 0,
+            sound_timer: 0,
+            wait_key: None,
+            wait_mode: WaitMode::Release,
+            platform: Platform::Chip8,
+            quirks: Quirks::default(),
+            ipf: None,
+            palette: [(16, 16, 64), (224, 224, 128)],
+            font: font::load(None, None).unwrap(),
+            access: None,
+            symbols: Symbols::default(),
+            cheats: Cheats::default(),
+            rom: Vec::new(),
+            rng: rand::rng(),
+        }
+    }
+
+    // Takes the ROM and whatever the database knows about it. Nothing is
+    // put in memory until power_on, once the layout is settled.
+    pub fn load(&mut self, data: Vec<u8>, romdb: &RomDb) -> Option<RomInfo> {
+        self.rom = data;
+        let info = romdb.lookup(&self.rom)?;
+        if let Some(platform) = info.platform {
+            self.set_platform(platform);
+        }
+        self.quirks = info.quirks();
+        info.layout.apply(&mut self.layout);
+        if info.ipf.is_some() {
+            self.ipf = info.ipf;
+        }
+        if let Some(palette) = info.palette() {
+            self.palette = palette;
+        }
+        Some(info.clone())
+    }
+
+    // Switches to a platform's quirks and memory layout.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.quirks = platform.quirks();
+        self.layout = platform.layout();
+    }
+
+    // Sizes memory for the layout and puts the font and ROM in place.
+    pub fn power_on(&mut self) -> Result<(), String> {
+        // Each override is checked on its own; here they have been merged.
+        let font = self.layout.font..self.layout.font + self.font.len();
+        if font.end > self.layout.memory {
+            return Err(format!(
+                "font at {:#X} does not fit before the end of memory at {:#X}",
+                font.start, self.layout.memory
+            ));
+        }
+        if self.layout.start >= self.layout.memory {
+            return Err(format!(
+                "start {:#X} is past the end of memory at {:#X}",
+                self.layout.start, self.layout.memory
+            ));
+        }
+        self.end = self.layout.start + self.rom.len();
+        if self.end > self.layout.memory {
+            return Err(format!(
+                "{} byte ROM does not fit between {:#X} and the end of memory at {:#X}",
+                self.rom.len(),
+                self.layout.start,
+                self.layout.memory
+            ));
+        }
+        if font.start < self.end && self.layout.start < font.end {
+            return Err(format!(
+                "font at {:#X}-{:#X} overlaps the ROM at {:#X}-{:#X}",
+                font.start,
+                font.end - 1,
+                self.layout.start,
+                self.end - 1
+            ));
+        }
+        self.memory = vec![0; self.layout.memory];
+        self.soft_reset();
+        Ok(())
+    }
+
+    pub fn screen_height(&self) -> usize {
+        if self.layout.hires {
+            64
+        } else {
+            32
+        }
+    }
+
+    // Carries the CPU state of the previous machine over to a reloaded ROM.
+    pub fn keep_registers(&mut self, old: &Chip8) {
+        self.registry = old.registry;
+        self.stack = old.stack;
+        self.sub_pointer = old.sub_pointer;
+        self.i = old.i;
+        self.program_counter = old.program_counter;
+        self.delay_timer = old.delay_timer;
+        self.sound_timer = old.sound_timer;
+    }
+
+    // Restarts the program with a fresh copy of the ROM, like the reset
+    // switch on a real machine. Memory outside the ROM is left alone.
+    pub fn soft_reset(&mut self) {
+        self.registry = [0; 16];
+        self.stack = [0; STACK];
+        self.sub_pointer = 0;
+        self.i = 0;
+        self.program_counter = self.layout.entry_point(&self.rom);
+        self.pixel_map = [[0; 64]; 64];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.wait_key = None;
+        self.keypad.release_all();
+        let font = self.layout.font;
+        self.memory[font..font + self.font.len()].copy_from_slice(&self.font);
+        self.memory[self.layout.start..self.end].copy_from_slice(&self.rom);
+    }
+
+    fn fetch(&self) -> RawOpCode {
+        RawOpCode {
+            v0: self.memory[self.address(self.program_counter)],
+            v1: self.memory[self.address(self.program_counter + 1)],
+        }
+    }
+
+    // Addresses past the end of memory wrap around to the start.
+    fn address(&self, address: usize) -> usize {
+        address % self.memory.len()
+    }
+
+    pub fn decode(raw_opcode: RawOpCode) -> Opcode {
+        let hex = ((raw_opcode.v0 as i32) << 8) | raw_opcode.v1 as i32;
+        let c0 = ((hex & 0xF000) >> 12) as u8;
+        let c1 = ((hex & 0x0F00) >> 8) as u8;
+        let c2 = ((hex & 0x00F0) >> 4) as u8;
+        let c3 = (hex & 0x000F) as u8;
+
+        match c0 {
+            0x0 => match (c1, c2, c3) {
+                (0x0, 0xE, 0x0) => Opcode::Clear, // 00E0
+
+                (0x0, 0xE, 0xE) => Opcode::Return, // 00EE
+
+                (0x2, 0x3, 0x0) => Opcode::Clear, // 0230, hi-res
+
+                (0x0, 0xC, n) => Opcode::ScrollDown { n }, // 00Cn
+
+                (0x0, 0xD, n) => Opcode::ScrollUp { n }, // 00Dn
+
+                (0x0, 0xF, 0xB) => Opcode::ScrollRight, // 00FB
+
+                (0x0, 0xF, 0xC) => Opcode::ScrollLeft, // 00FC
+
+                (0x0, 0xF, 0xD) => Opcode::Exit, // 00FD
+
+                (0x0, 0xF, 0xE) => Opcode::LowRes, // 00FE
+
+                (0x0, 0xF, 0xF) => Opcode::HighRes, // 00FF
+
+                _ => Opcode::None { raw: raw_opcode },
+            },
+
+            0xC => Opcode::Random {
+                x: c1,
+                n0: c2,
+                n1: c3,
+            },
+
+            0x6 => Opcode::NormalRegistry {
+                x: c1,
+                n0: c2,
+                n1: c3,
+            }, // 6xnn
+
+            0xA => Opcode::IndexRegistry {
+                n0: c1,
+                n1: c2,
+                n2: c3,
+            }, // Annn
+
+            0x7 => Opcode::AddRegistry {
+                x: c1,
+                n0: c2,
+                n1: c3,
+            }, // 7xnn
+
+            0xF => match raw_opcode.v1 {
+                0x55 => Opcode::SaveToMemory { x: c1 }, // Fx55
+
+                0x65 => Opcode::LoadFromMemory { x: c1 }, // Fx65
+
+                0x1E => Opcode::AddVxToI { x: c1 }, // Fx1E
+
+                0x33 => Opcode::SaveDigits { x: c1 }, // Fx33
+
+                0x29 => Opcode::SetSprite { x: c1 }, // Fx29
+
+                0x15 => Opcode::SetTimer { x: c1 }, // Fx15
+
+                0x07 => Opcode::SaveTimer { x: c1 }, // Fx07
+
+                0x18 => Opcode::SetSoundTimer { x: c1 }, // Fx18
+
+                0x0A => Opcode::WaitKeyDown { x: c1 }, // Fx0A
+
+                0x30 => Opcode::SetBigSprite { x: c1 }, // Fx30
+
+                0x75 => Opcode::SaveFlags { x: c1 }, // Fx75
+
+                0x85 => Opcode::LoadFlags { x: c1 }, // Fx85
+
+                0x3A => Opcode::SetPitch { x: c1 }, // Fx3A
+
+                0x01 => Opcode::Plane { n: c1 }, // Fn01
+
+                0x00 if c1 == 0 => Opcode::LongIndex, // F000 nnnn
+
+                0x02 if c1 == 0 => Opcode::LoadAudio, // F002
+
+                _ => Opcode::None { raw: raw_opcode },
+            },
+
+            0x3 => Opcode::SkipIfEqualXN {
+                x: c1,
+                n0: c2,
+                n1: c3,
+            }, // 3Xnn
+
+            0x4 => Opcode::SkipIfNotEqualXN {
+                x: c1,
+                n0: c2,
+                n1: c3,
+            }, // 4Xnn
+
+            0x5 => match c3 {
+                0x0 => Opcode::SkipIfEqualXY { x: c1, y: c2 }, // 5xy0
+
+                0x2 => Opcode::SaveRange { x: c1, y: c2 }, // 5xy2
+
+                0x3 => Opcode::LoadRange { x: c1, y: c2 }, // 5xy3
+
+                _ => Opcode::None { raw: raw_opcode },
+            },
+
+            0x9 => match c3 {
+                0x0 => Opcode::SkipIfNotEqualXY { x: c1, y: c2 }, // 9xy0
+
+                _ => Opcode::None { raw: raw_opcode },
+            },
+
+            0x1 => Opcode::Jump {
+                n0: c1,
+                n1: c2,
+                n2: c3,
+            }, // 1nnn
+
+            0xB => Opcode::JumpOffset {
+                n0: c1,
+                n1: c2,
+                n2: c3,
+            }, // Bnnn
+
+            0x2 => Opcode::Subroutine {
+                n0: c1,
+                n1: c2,
+                n2: c3,
+            }, // 2nnn
+
+            0x8 => match c3 {
+                0x0 => Opcode::Set { x: c1, y: c2 }, // 8xy0
+
+                0x1 => Opcode::Or { x: c1, y: c2 }, // 8xy1
+
+                0x2 => Opcode::And { x: c1, y: c2 }, // 8xy2
+
+                0x3 => Opcode::Xor { x: c1, y: c2 }, // 8xy3
+
+                0x4 => Opcode::Add { x: c1, y: c2 }, // 8xy4
+
+                0x5 => Opcode::Subtract { x: c1, y: c2 }, // 8xy5
+
+                0x7 => Opcode::SubtractRev { x: c1, y: c2 }, // 8xy7
+
+                0x6 => Opcode::ShiftRight { x: c1, y: c2 }, // 8xy6
+
+                0xE => Opcode::ShiftLeft { x: c1, y: c2 }, // 8xyE
+
+                _ => Opcode::None { raw: raw_opcode },
+            },
+            0xE => match raw_opcode.v1 {
+                0x9E => Opcode::SkipIfKeyDown { x: c1 }, // Ex9E
+
+                0xA1 => Opcode::SkipIfKeyUp { x: c1 }, // ExA1
+
+                _ => Opcode::None { raw: raw_opcode },
+            },
+
+            0xD => Opcode::Draw {
+                x: c1,
+                y: c2,
+                n: c3,
+            }, // DxyN
+
+            _ => Opcode::None { raw: raw_opcode },
+        }
+    }
+
+    pub fn to_decimal(n0: u8, n1: u8, n2: u8) -> u16 {
+        n0 as u16 * 256 + n1 as u16 * 16 + n2 as u16
+    }
+
+    fn set_normal_registry(&mut self, x: u8, n0: u8, n1: u8) {
+        self.registry[x as usize] = Chip8::to_decimal(0, n0, n1) as u8;
+    }
+
+    fn set_index_registry(&mut self, n0: u8, n1: u8, n2: u8) {
+        self.i = Chip8::to_decimal(n0, n1, n2) as usize;
+    }
+
+    fn add_registry(&mut self, x: u8, n0: u8, n1: u8) {
+        let result = (self.registry[x as usize] as u16 + Chip8::to_decimal(0, n0, n1)) & 0xFF;
+        self.registry[x as usize] = result as u8;
+    }
+
+    fn note_access(&mut self, start: usize, len: usize, write: bool) {
+        self.access = Some(MemoryAccess {
+            pc: self.program_counter,
+            start,
+            len,
+            write,
+        });
+    }
+
+    fn save_to_memory(&mut self, x: u8) {
+        let d = x as usize + 1;
+        self.note_access(self.address(self.i), d, true);
+        for n in 0..d {
+            let address = self.address(self.i + n);
+            self.memory[address] = self.registry[n];
+        }
+        if self.quirks.memory_increment {
+            self.i = (self.i + d) & 0xFFFF;
+        }
+    }
+
+    fn load_from_memory(&mut self, x: u8) {
+        let d = x as usize + 1;
+        self.note_access(self.address(self.i), d, false);
+        for n in 0..d {
+            self.registry[n] = self.memory[self.address(self.i + n)];
+        }
+        if self.quirks.memory_increment {
+            self.i = (self.i + d) & 0xFFFF;
+        }
+    }
+
+    fn set_sprite(&mut self, x: u8) {
+        let digit = self.registry[x as usize] as usize & 0xF;
+        self.i = self.layout.font + digit * 5;
+    }
+
+    fn set_big_sprite(&mut self, x: u8) {
+        let digit = self.registry[x as usize] as usize & 0xF;
+        self.i = self.layout.font + font::SMALL_SIZE + digit * 10;
+    }
+
+    // I is 16 bits wide and wraps.
+    fn add_vx_to_i(&mut self, x: u8) {
+        self.i = (self.i + self.registry[x as usize] as usize) & 0xFFFF;
+    }
+
+    fn set_timer(&mut self, x: u8) {
+        self.delay_timer = self.registry[x as usize];
+    }
+
+    fn save_timer(&mut self, x: u8) {
+        self.registry[x as usize] = self.delay_timer;
+    }
+
+    fn set_sound_timer(&mut self, x: u8) {
+        self.sound_timer = self.registry[x as usize];
+    }
+
+    fn save_digits(&mut self, x: u8) {
+        let value = self.registry[x as usize];
+        self.note_access(self.address(self.i), 3, true);
+        for (n, digit) in [value / 100, value / 10 % 10, value % 10]
+            .into_iter()
+            .enumerate()
+        {
+            let address = self.address(self.i + n);
+            self.memory[address] = digit;
+        }
+    }
+
+    fn skip_if_equal_xn(&mut self, x: u8, n0: u8, n1: u8) {
+        if self.registry[x as usize] == Chip8::to_decimal(0, n0, n1) as u8 {
+            self.step_counter();
+        }
+    }
+
+    fn skip_if_equal_xy(&mut self, x: u8, y: u8) {
+        if self.registry[x as usize] == self.registry[y as usize] {
+            self.step_counter();
+        }
+    }
+
+    fn skip_if_not_equal_xn(&mut self, x: u8, n0: u8, n1: u8) {
+        if self.registry[x as usize] != Chip8::to_decimal(0, n0, n1) as u8 {
+            self.step_counter();
+        }
+    }
+
+    fn skip_if_not_equal_xy(&mut self, x: u8, y: u8) {
+        if self.registry[x as usize] != self.registry[y as usize] {
+            self.step_counter();
+        }
+    }
+
+    fn jump(&mut self, n0: u8, n1: u8, n2: u8) {
+        self.program_counter = Chip8::to_decimal(n0, n1, n2) as usize;
+    }
+
+    fn jump_offset(&mut self, n0: u8, n1: u8, n2: u8) {
+        let offset = self.registry[if self.quirks.jump_vx { n0 } else { 0 } as usize];
+        // 0xFFF plus VX can run past 4 KiB of memory.
+        let target = Chip8::to_decimal(n0, n1, n2) as usize + offset as usize;
+        self.program_counter = self.address(target);
+    }
+
+    fn subroutine(&mut self, n0: u8, n1: u8, n2: u8) -> Result<(), String> {
+        if self.sub_pointer == self.stack.len() {
+            return Err(format!(
+                "stack overflow: more than {} nested calls",
+                self.stack.len()
+            ));
+        }
+        self.stack[self.sub_pointer] = self.program_counter;
+        self.sub_pointer += 1;
+        self.jump(n0, n1, n2);
+        Ok(())
+    }
+
+    fn return_subroutine(&mut self) -> Result<(), String> {
+        if self.sub_pointer == 0 {
+            return Err("return with an empty stack".to_string());
+        }
+        self.sub_pointer -= 1;
+        self.program_counter = self.stack[self.sub_pointer];
+        self.stack[self.sub_pointer] = 0;
+        Ok(())
+    }
+
+    fn set(&mut self, x: u8, y: u8) {
+        self.registry[x as usize] = self.registry[y as usize];
+    }
+
+    fn or(&mut self, x: u8, y: u8) {
+        self.registry[x as usize] |= self.registry[y as usize];
+        if self.quirks.vf_reset {
+            self.registry[0xF] = 0;
+        }
+    }
+
+    fn and(&mut self, x: u8, y: u8) {
+        self.registry[x as usize] &= self.registry[y as usize];
+        if self.quirks.vf_reset {
+            self.registry[0xF] = 0;
+        }
+    }
+
+    fn xor(&mut self, x: u8, y: u8) {
+        self.registry[x as usize] ^= self.registry[y as usize];
+        if self.quirks.vf_reset {
+            self.registry[0xF] = 0;
+        }
+    }
+
+    fn add(&mut self, x: u8, y: u8) {
+        let n = self.registry[x as usize] as u16 + self.registry[y as usize] as u16;
+        self.registry[x as usize] = (n & 0xFF) as u8;
+        self.registry[0xF] = (n > 255) as u8;
+    }
+
+    fn subtract(&mut self, x: u8, y: u8) {
+        let n = self.registry[x as usize] as i16 - self.registry[y as usize] as i16;
+        self.registry[x as usize] = (n & 0xFF) as u8;
+        self.registry[0xF] = (n >= 0) as u8;
+    }
+
+    fn subtract_rev(&mut self, x: u8, y: u8) {
+        let n = self.registry[y as usize] as i16 - self.registry[x as usize] as i16;
+        self.registry[x as usize] = (n & 0xFF) as u8;
+        self.registry[0xF] = (n >= 0) as u8;
+    }
+
+    fn shift_left(&mut self, x: u8, y: u8) {
+        let r = self.registry[if self.quirks.shift_vy { y } else { x } as usize];
+        self.registry[x as usize] = r << 1;
+        self.registry[0xF] = (r & 0b10000000) >> 7;
+    }
+
+    fn shift_right(&mut self, x: u8, y: u8) {
+        let r = self.registry[if self.quirks.shift_vy { y } else { x } as usize];
+        self.registry[x as usize] = r >> 1;
+        self.registry[0xF] = r & 0b00000001;
+    }
+
+    // Only the low nibble of VX picks the key.
+    fn skip_if_keydown(&mut self, x: u8) {
+        let key = self.registry[x as usize] as usize & 0xF;
+        self.keypad.poll(Some(key));
+        if self.keypad.is_down(key) {
+            self.step_counter();
+        }
+    }
+
+    fn skip_if_keyup(&mut self, x: u8) {
+        let key = self.registry[x as usize] as usize & 0xF;
+        self.keypad.poll(Some(key));
+        if !self.keypad.is_down(key) {
+            self.step_counter();
+        }
+    }
+
+    fn wait_keydown(&mut self, x: u8) {
+        self.keypad.poll(None);
+
+        if self.wait_key.is_none() {
+            self.wait_key = (0..16).find(|key| self.keypad.was_pressed(*key));
+        }
+
+        let Some(key) = self.wait_key else {
+            return;
+        };
+
+        let done = match self.wait_mode {
+            WaitMode::Press => true,
+            WaitMode::Release => self.keypad.was_released(key) || !self.keypad.is_down(key),
+        };
+
+        if done {
+            self.registry[x as usize] = key as u8;
+            self.keypad.consume(key);
+            self.step_counter();
+            self.wait_key = None;
+        }
+    }
+
+    fn random(&mut self, x: u8, n0: u8, n1: u8) {
+        self.registry[x as usize] = self.rng.random::<u8>() & Chip8::to_decimal(0, n0, n1) as u8;
+    }
+
+    fn draw(&mut self, x: u8, y: u8, n: u8) {
+        let px = self.registry[x as usize] as usize % 64;
+        let height = self.screen_height();
+        let py = self.registry[y as usize] as usize % height;
+
+        // SCHIP and XO-CHIP draw Dxy0 as a 16x16 sprite, two bytes to a
+        // row; earlier machines draw nothing.
+        let big = n == 0 && matches!(self.platform, Platform::Schip | Platform::Xochip);
+        let (rows, width) = if big { (16, 16) } else { (n as usize, 8) };
+        let row_bytes = width / 8;
+
+        self.registry[0xF] = 0;
+        self.note_access(self.address(self.i), rows * row_bytes, false);
+
+        for oy in 0..rows {
+            let mut bit_row = 0u16;
+            for byte in 0..row_bytes {
+                let address = self.address(self.i + oy * row_bytes + byte);
+                bit_row = bit_row << 8 | self.memory[address] as u16;
+            }
+            for ox in (0..width).rev() {
+                let pixel = bit_row & 0b1;
+                bit_row >>= 1;
+
+                let dx = px + ox;
+                let dy = py + oy;
+                if self.quirks.clipping && (dx >= 64 || dy >= height) {
+                    continue;
+                }
+                let dx = dx % 64;
+                let dy = dy % height;
+
+                if pixel == 1 {
+                    if self.pixel_map[dx][dy] == 1 {
+                        self.registry[0xF] = 1;
+                    }
+                    self.pixel_map[dx][dy] ^= 1;
+                }
+            }
+        }
+    }
+
+    fn step_counter(&mut self) {
+        self.program_counter = self.address(self.program_counter + 2);
+    }
+
+    // Runs the instruction at PC, or leaves it for the next frame when it
+    // waits for one, and says which. An instruction that cannot run is an
+    // error and leaves the machine as it was.
+    pub fn execute(&mut self, allow_display: bool) -> Result<bool, String> {
+        let mut retired = true;
+        self.access = None;
+        let opcode = Chip8::decode(self.fetch());
+        match opcode {
+            Opcode::Clear => {
+                if allow_display || !self.quirks.display_wait {
+                    self.pixel_map = [[0; 64]; 64];
+                    self.step_counter();
+                } else {
+                    retired = false;
+                }
+            }
+            Opcode::Return => {
+                self.return_subroutine().map_err(|e| self.fault(e))?;
+                self.step_counter();
+            }
+            Opcode::Random { x, n0, n1 } => {
+                self.random(x, n0, n1);
+                self.step_counter();
+            }
+            Opcode::NormalRegistry { x, n0, n1 } => {
+                self.set_normal_registry(x, n0, n1);
+                self.step_counter();
+            }
+            Opcode::IndexRegistry { n0, n1, n2 } => {
+                self.set_index_registry(n0, n1, n2);
+                self.step_counter();
+            }
+            Opcode::AddRegistry { x, n0, n1 } => {
+                self.add_registry(x, n0, n1);
+                self.step_counter();
+            }
+            Opcode::SaveToMemory { x } => {
+                self.save_to_memory(x);
+                self.step_counter();
+            }
+            Opcode::LoadFromMemory { x } => {
+                self.load_from_memory(x);
+                self.step_counter();
+            }
+            Opcode::SetSprite { x } => {
+                self.set_sprite(x);
+                self.step_counter();
+            }
+            Opcode::SetBigSprite { x } => {
+                self.set_big_sprite(x);
+                self.step_counter();
+            }
+            Opcode::AddVxToI { x } => {
+                self.add_vx_to_i(x);
+                self.step_counter();
+            }
+            Opcode::SetTimer { x } => {
+                self.set_timer(x);
+                self.step_counter();
+            }
+            Opcode::SaveTimer { x } => {
+                self.save_timer(x);
+                self.step_counter();
+            }
+            Opcode::SetSoundTimer { x } => {
+                self.set_sound_timer(x);
+                self.step_counter();
+            }
+            Opcode::SaveDigits { x } => {
+                self.save_digits(x);
+                self.step_counter();
+            }
+            Opcode::SkipIfEqualXN { x, n0, n1 } => {
+                self.skip_if_equal_xn(x, n0, n1);
+                self.step_counter();
+            }
+            Opcode::SkipIfNotEqualXN { x, n0, n1 } => {
+                self.skip_if_not_equal_xn(x, n0, n1);
+                self.step_counter();
+            }
+            Opcode::SkipIfEqualXY { x, y } => {
+                self.skip_if_equal_xy(x, y);
+                self.step_counter();
+            }
+            Opcode::SkipIfNotEqualXY { x, y } => {
+                self.skip_if_not_equal_xy(x, y);
+                self.step_counter();
+            }
+            Opcode::Jump { n0, n1, n2 } => {
+                self.jump(n0, n1, n2);
+            }
+            Opcode::JumpOffset { n0, n1, n2 } => {
+                self.jump_offset(n0, n1, n2);
+            }
+            Opcode::Subroutine { n0, n1, n2 } => {
+                self.subroutine(n0, n1, n2).map_err(|e| self.fault(e))?;
+            }
+            Opcode::Set { x, y } => {
+                self.set(x, y);
+                self.step_counter();
+            }
+            Opcode::Or { x, y } => {
+                self.or(x, y);
+                self.step_counter();
+            }
+            Opcode::And { x, y } => {
+                self.and(x, y);
+                self.step_counter();
+            }
+            Opcode::Xor { x, y } => {
+                self.xor(x, y);
+                self.step_counter();
+            }
+            Opcode::Add { x, y } => {
+                self.add(x, y);
+                self.step_counter();
+            }
+            Opcode::Subtract { x, y } => {
+                self.subtract(x, y);
+                self.step_counter();
+            }
+            Opcode::SubtractRev { x, y } => {
+                self.subtract_rev(x, y);
+                self.step_counter();
+            }
+            Opcode::ShiftRight { x, y } => {
+                self.shift_right(x, y);
+                self.step_counter();
+            }
+            Opcode::ShiftLeft { x, y } => {
+                self.shift_left(x, y);
+                self.step_counter();
+            }
+            Opcode::SkipIfKeyDown { x } => {
+                self.skip_if_keydown(x);
+                self.step_counter();
+            }
+            Opcode::SkipIfKeyUp { x } => {
+                self.skip_if_keyup(x);
+                self.step_counter();
+            }
+            Opcode::WaitKeyDown { x } => {
+                let program_counter = self.program_counter;
+                self.wait_keydown(x);
+                retired = self.program_counter != program_counter;
+            }
+            Opcode::Draw { x, y, n } => {
+                if allow_display || !self.quirks.display_wait {
+                    self.draw(x, y, n);
+                    self.step_counter();
+                } else {
+                    retired = false;
+                }
+            }
+            Opcode::ScrollDown { .. }
+            | Opcode::ScrollRight
+            | Opcode::ScrollLeft
+            | Opcode::Exit
+            | Opcode::LowRes
+            | Opcode::HighRes
+            | Opcode::SaveFlags { .. }
+            | Opcode::LoadFlags { .. }
+            | Opcode::ScrollUp { .. }
+            | Opcode::SaveRange { .. }
+            | Opcode::LoadRange { .. }
+            | Opcode::LongIndex
+            | Opcode::Plane { .. }
+            | Opcode::LoadAudio
+            | Opcode::SetPitch { .. }
+            | Opcode::None { .. } => {
+                let opcode = self.fetch().as_string();
+                return Err(self.fault(format!("opcode {} not implemented", opcode)));
+            }
+        }
+
+        if allow_display {
+            self.delay_timer = self.delay_timer.saturating_sub(1);
+            self.sound_timer = self.sound_timer.saturating_sub(1);
+            self.cheats.apply(&mut self.memory, &mut self.registry);
+        }
+
+        Ok(retired)
+    }
+
+    fn fault(&self, message: String) -> String {
+        format!(
+            "{} at {}",
+            message,
+            self.symbols.describe(self.program_counter)
+        )
+    }
+}
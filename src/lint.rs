@@ -0,0 +1,628 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::quirks::{Layout, Platform};
+use crate::{detect, Chip8, Opcode, RawOpCode, STACK};
+
+// Stack depth of the original interpreter; most later ones, and this
+// emulator, have STACK.
+const VIP_STACK: usize = 12;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+pub struct Finding {
+    pub address: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+pub struct Report {
+    pub platform: Platform,
+    pub reachable: usize,
+    pub findings: Vec<Finding>,
+}
+
+// What is known about the machine on the way into an instruction, on
+// every path that reaches it.
+#[derive(Clone, PartialEq)]
+struct State {
+    registers: [Option<u8>; 16],
+    i: Option<usize>,
+    // The Fx55 or Fx65 that last moved I, by an amount interpreters
+    // disagree on, if I has not been set since.
+    i_moved_by: Option<usize>,
+    screen: Option<(usize, usize)>,
+}
+
+impl State {
+    // Keeps only what both paths agree on; true if anything was lost.
+    fn merge(&mut self, other: &State) -> bool {
+        let before = self.clone();
+        for (mine, theirs) in self.registers.iter_mut().zip(other.registers) {
+            if *mine != theirs {
+                *mine = None;
+            }
+        }
+        if self.i != other.i {
+            self.i = None;
+        }
+        self.i_moved_by = self.i_moved_by.or(other.i_moved_by);
+        if self.screen != other.screen {
+            self.screen = None;
+        }
+        *self != before
+    }
+}
+
+// What a subroutine does, not counting the ones it calls.
+#[derive(Default)]
+struct Subroutine {
+    // Call instructions and their targets.
+    calls: Vec<(usize, usize)>,
+    registers: u16,
+    i: bool,
+    screen: bool,
+}
+
+// The same as Subroutine, with everything its calls do as well.
+#[derive(Clone, Copy, Default)]
+struct Clobbers {
+    registers: u16,
+    i: bool,
+    screen: bool,
+}
+
+struct Linter<'a> {
+    rom: &'a [u8],
+    start: usize,
+    memory: usize,
+    findings: BTreeSet<(usize, Severity, String)>,
+    // Memory written and read through a known I, by instruction.
+    writes: Vec<(usize, usize, usize)>,
+    reads: Vec<(usize, usize, usize)>,
+}
+
+// Follows every path from the start address, like the platform detection,
+// tracking the register and I values that are known for certain, and
+// reports what would behave differently between interpreters or go wrong.
+pub fn check(rom: &[u8], layout: &Layout) -> Report {
+    let start = layout.start;
+    let detection = detect::scan(rom, start);
+    let entry = layout.entry_point(rom);
+    let screen = (64, if layout.hires { 64 } else { 32 });
+
+    let mut linter = Linter {
+        rom,
+        start,
+        memory: layout.memory,
+        findings: BTreeSet::new(),
+        writes: Vec::new(),
+        reads: Vec::new(),
+    };
+    let clobbers = linter.check_calls(entry);
+
+    let initial = State {
+        registers: [Some(0); 16],
+        i: Some(0),
+        i_moved_by: None,
+        screen: Some(screen),
+    };
+    let mut states = BTreeMap::from([(entry, initial)]);
+    let mut pending = vec![entry];
+    while let Some(address) = pending.pop() {
+        let Some(raw) = linter.word(address) else {
+            continue;
+        };
+        let mut state = states[&address].clone();
+        let opcode = Chip8::decode(raw);
+        linter.step(address, &opcode, &mut state);
+
+        for (next, call) in linter.successors(address, &opcode) {
+            let mut next_state = state.clone();
+            if let Some(target) = call {
+                let clobbers = clobbers.get(&target).copied().unwrap_or_default();
+                for (n, register) in next_state.registers.iter_mut().enumerate() {
+                    if clobbers.registers & 1 << n != 0 {
+                        *register = None;
+                    }
+                }
+                if clobbers.i {
+                    next_state.i = None;
+                    next_state.i_moved_by = None;
+                }
+                if clobbers.screen {
+                    next_state.screen = None;
+                }
+            }
+            match states.get_mut(&next) {
+                Some(known) => {
+                    if known.merge(&next_state) {
+                        pending.push(next);
+                    }
+                }
+                None => {
+                    states.insert(next, next_state);
+                    pending.push(next);
+                }
+            }
+        }
+    }
+
+    let instructions: Vec<usize> = states
+        .into_keys()
+        .filter(|address| linter.word(*address).is_some())
+        .collect();
+    let code = instructions.iter().flat_map(|a| [*a, a + 1]).collect();
+    linter.check_memory(&code);
+
+    let findings = linter
+        .findings
+        .into_iter()
+        .map(|(address, severity, message)| Finding {
+            address,
+            severity,
+            message,
+        })
+        .collect();
+    Report {
+        platform: detection.platform,
+        reachable: instructions.len(),
+        findings,
+    }
+}
+
+impl Linter<'_> {
+    fn word(&self, address: usize) -> Option<RawOpCode> {
+        let offset = address.checked_sub(self.start)?;
+        Some(RawOpCode {
+            v0: *self.rom.get(offset)?,
+            v1: *self.rom.get(offset + 1)?,
+        })
+    }
+
+    fn report(&mut self, address: usize, severity: Severity, message: String) {
+        self.findings.insert((address, severity, message));
+    }
+
+    // Where execution can go after the instruction at address, and for a
+    // call, the subroutine run before getting there.
+    fn successors(&self, address: usize, opcode: &Opcode) -> Vec<(usize, Option<usize>)> {
+        // XO-CHIP skips step over the whole of a four byte F000 nnnn.
+        let skip = match self.word(address + 2).map(|raw| (raw.v0, raw.v1)) {
+            Some((0xF0, 0x00)) => address + 6,
+            _ => address + 4,
+        };
+        match *opcode {
+            Opcode::Jump { n0, n1, n2 } => vec![(Chip8::to_decimal(n0, n1, n2) as usize, None)],
+            Opcode::Subroutine { n0, n1, n2 } => {
+                let target = Chip8::to_decimal(n0, n1, n2) as usize;
+                vec![(target, None), (address + 2, Some(target))]
+            }
+            Opcode::JumpOffset { .. } | Opcode::Return | Opcode::Exit | Opcode::None { .. } => {
+                Vec::new()
+            }
+            Opcode::SkipIfEqualXN { .. }
+            | Opcode::SkipIfNotEqualXN { .. }
+            | Opcode::SkipIfEqualXY { .. }
+            | Opcode::SkipIfNotEqualXY { .. }
+            | Opcode::SkipIfKeyDown { .. }
+            | Opcode::SkipIfKeyUp { .. } => vec![(address + 2, None), (skip, None)],
+            Opcode::LongIndex => vec![(address + 4, None)],
+            _ => vec![(address + 2, None)],
+        }
+    }
+
+    // Walks each subroutine's own body to find what it changes and whom it
+    // calls, then checks how deep the calls can nest.
+    fn check_calls(&mut self, entry: usize) -> BTreeMap<usize, Clobbers> {
+        let mut subroutines = BTreeMap::new();
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            if subroutines.contains_key(&start) {
+                continue;
+            }
+            let subroutine = self.subroutine(start);
+            pending.extend(subroutine.calls.iter().map(|(_, target)| *target));
+            subroutines.insert(start, subroutine);
+        }
+
+        let mut clobbers = BTreeMap::new();
+        let mut depths = BTreeMap::new();
+        let mut path = Vec::new();
+        let deepest = self.depth(entry, &subroutines, &mut clobbers, &mut depths, &mut path);
+        if deepest > STACK {
+            let message = format!(
+                "calls nest {} deep, more than the {} levels most interpreters have",
+                deepest, STACK
+            );
+            self.report(entry, Severity::Error, message);
+        } else if deepest > VIP_STACK {
+            let message = format!(
+                "calls nest {} deep, more than the {} levels of the COSMAC VIP",
+                deepest, VIP_STACK
+            );
+            self.report(entry, Severity::Warning, message);
+        }
+        clobbers
+    }
+
+    // The deepest the stack gets below a subroutine, and everything it
+    // changes, noting recursion on the way.
+    fn depth(
+        &mut self,
+        start: usize,
+        subroutines: &BTreeMap<usize, Subroutine>,
+        clobbers: &mut BTreeMap<usize, Clobbers>,
+        depths: &mut BTreeMap<usize, usize>,
+        path: &mut Vec<usize>,
+    ) -> usize {
+        if let Some(depth) = depths.get(&start) {
+            return *depth;
+        }
+        let Some(subroutine) = subroutines.get(&start) else {
+            return 0;
+        };
+        path.push(start);
+        let mut deepest = 0;
+        let mut all = Clobbers {
+            registers: subroutine.registers,
+            i: subroutine.i,
+            screen: subroutine.screen,
+        };
+        for (site, target) in &subroutine.calls {
+            if path.contains(target) {
+                let message = format!("recursive call to 0x{:03X}; the stack can overflow", target);
+                self.report(*site, Severity::Warning, message);
+                // Whatever the recursion changes, the caller may see.
+                all = Clobbers {
+                    registers: 0xFFFF,
+                    i: true,
+                    screen: true,
+                };
+                continue;
+            }
+            deepest = deepest.max(1 + self.depth(*target, subroutines, clobbers, depths, path));
+            let theirs = clobbers.get(target).copied().unwrap_or_default();
+            all.registers |= theirs.registers;
+            all.i |= theirs.i;
+            all.screen |= theirs.screen;
+        }
+        path.pop();
+        depths.insert(start, deepest);
+        clobbers.insert(start, all);
+        deepest
+    }
+
+    // A subroutine's own instructions, stepping over the calls it makes.
+    fn subroutine(&self, start: usize) -> Subroutine {
+        let mut subroutine = Subroutine::default();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(address) = pending.pop() {
+            if !visited.insert(address) {
+                continue;
+            }
+            let Some(raw) = self.word(address) else {
+                continue;
+            };
+            let opcode = Chip8::decode(raw);
+            subroutine.registers |= written_registers(&opcode);
+            subroutine.i |= changes_i(&opcode);
+            subroutine.screen |= matches!(opcode, Opcode::HighRes | Opcode::LowRes);
+            for (next, call) in self.successors(address, &opcode) {
+                match call {
+                    Some(target) => {
+                        subroutine.calls.push((address, target));
+                        pending.push(next);
+                    }
+                    None if matches!(opcode, Opcode::Subroutine { .. }) => {}
+                    None => pending.push(next),
+                }
+            }
+        }
+        subroutine
+    }
+
+    // Checks one instruction against what is known on the way in, and
+    // updates that to what is known after it.
+    fn step(&mut self, address: usize, opcode: &Opcode, state: &mut State) {
+        let uses_i = matches!(
+            opcode,
+            Opcode::Draw { .. }
+                | Opcode::SaveToMemory { .. }
+                | Opcode::LoadFromMemory { .. }
+                | Opcode::SaveDigits { .. }
+                | Opcode::AddVxToI { .. }
+                | Opcode::SaveRange { .. }
+                | Opcode::LoadRange { .. }
+                | Opcode::LoadAudio
+        );
+        if let (true, Some(moved_by)) = (uses_i, state.i_moved_by) {
+            let message = format!(
+                "uses I as left by the load or store at 0x{:03X}, which some interpreters \
+                 advance past the registers and others leave alone",
+                moved_by
+            );
+            self.report(address, Severity::Warning, message);
+            state.i_moved_by = None;
+        }
+
+        let value = |x: u8| state.registers[x as usize];
+        match *opcode {
+            Opcode::None { ref raw } if raw.v0 >> 4 == 0 => {
+                let message = format!(
+                    "machine code call to 0x{:03X}, which only the COSMAC VIP can run",
+                    Chip8::to_decimal(raw.v0 & 0xF, raw.v1 >> 4, raw.v1 & 0xF)
+                );
+                self.report(address, Severity::Error, message);
+            }
+            Opcode::None { ref raw } => {
+                let message = format!("{} is not an instruction", raw.as_string());
+                self.report(address, Severity::Error, message);
+            }
+            Opcode::ShiftLeft { x, y } | Opcode::ShiftRight { x, y } if x != y => {
+                let message = format!(
+                    "shifts V{:X} on SCHIP but V{:X} on CHIP-8; shift a register into itself \
+                     to work on both",
+                    x, y
+                );
+                self.report(address, Severity::Warning, message);
+            }
+            Opcode::JumpOffset { n0, .. } if n0 != 0 => {
+                let message = format!(
+                    "adds V0 on CHIP-8 but V{:X} on SCHIP; jump tables starting at 0x0nn \
+                     work on both",
+                    n0
+                );
+                self.report(address, Severity::Warning, message);
+            }
+            Opcode::JumpOffset { .. } => {
+                let message = "computed jump; its targets are not checked".to_string();
+                self.report(address, Severity::Info, message);
+            }
+            Opcode::SaveToMemory { .. } | Opcode::LoadFromMemory { .. } => {
+                let message = "leaves I after the registers on CHIP-8 and XO-CHIP but unchanged \
+                               on SCHIP"
+                    .to_string();
+                self.report(address, Severity::Info, message);
+            }
+            Opcode::Draw { x, y, n } => self.check_draw(address, state, value(x), value(y), n),
+            _ => {}
+        }
+
+        // Memory reached through I, checked once the code is known.
+        let access = match *opcode {
+            Opcode::SaveToMemory { x } => Some((x as usize + 1, true)),
+            Opcode::SaveDigits { .. } => Some((3, true)),
+            Opcode::SaveRange { x, y } => Some((x.abs_diff(y) as usize + 1, true)),
+            Opcode::LoadFromMemory { x } => Some((x as usize + 1, false)),
+            Opcode::LoadRange { x, y } => Some((x.abs_diff(y) as usize + 1, false)),
+            Opcode::Draw { n: 0, .. } => Some((32, false)),
+            Opcode::Draw { n, .. } => Some((n as usize, false)),
+            Opcode::LoadAudio => Some((16, false)),
+            _ => None,
+        };
+        if let (Some((len, write)), Some(i)) = (access, state.i) {
+            let list = if write {
+                &mut self.writes
+            } else {
+                &mut self.reads
+            };
+            list.push((address, i, len));
+        }
+
+        self.update(address, opcode, state);
+    }
+
+    fn check_draw(&mut self, address: usize, state: &State, x: Option<u8>, y: Option<u8>, n: u8) {
+        let (Some(x), Some(y), Some((width, height))) = (x, y, state.screen) else {
+            return;
+        };
+        let (sprite_width, sprite_height) = match self.sprite_size(state.i, n) {
+            Some(size) => size,
+            None if n == 0 => (16, 16),
+            None => (8, n as usize),
+        };
+        let (left, top) = (x as usize % width, y as usize % height);
+        let mut edges = Vec::new();
+        if left + sprite_width > width {
+            edges.push("right");
+        }
+        if top + sprite_height > height {
+            edges.push("bottom");
+        }
+        if !edges.is_empty() {
+            let message = format!(
+                "sprite at ({}, {}) crosses the {} edge, where interpreters either clip or wrap it",
+                left,
+                top,
+                edges.join(" and ")
+            );
+            self.report(address, Severity::Warning, message);
+        }
+    }
+
+    // How far right and down a sprite in the ROM has pixels set, so blank
+    // margins can cross an edge without a warning.
+    fn sprite_size(&self, i: Option<usize>, n: u8) -> Option<(usize, usize)> {
+        let (row_bytes, rows) = if n == 0 { (2, 16) } else { (1, n as usize) };
+        let offset = i?.checked_sub(self.start)?;
+        let data = self.rom.get(offset..offset + row_bytes * rows)?;
+        let mut size = (0, 0);
+        for (row, bytes) in data.chunks(row_bytes).enumerate() {
+            let bits = bytes
+                .iter()
+                .fold(0u16, |bits, byte| bits << 8 | *byte as u16);
+            if bits != 0 {
+                let width = row_bytes * 8 - bits.trailing_zeros() as usize;
+                size = (size.0.max(width), row + 1);
+            }
+        }
+        Some(size)
+    }
+
+    fn update(&self, address: usize, opcode: &Opcode, state: &mut State) {
+        let registers = &mut state.registers;
+        let byte = |n0: u8, n1: u8| n0 << 4 | n1;
+        match *opcode {
+            Opcode::NormalRegistry { x, n0, n1 } => registers[x as usize] = Some(byte(n0, n1)),
+            Opcode::AddRegistry { x, n0, n1 } => {
+                let x = x as usize;
+                registers[x] = registers[x].map(|v| v.wrapping_add(byte(n0, n1)));
+            }
+            Opcode::Set { x, y } => registers[x as usize] = registers[y as usize],
+            Opcode::Or { x, y } | Opcode::And { x, y } | Opcode::Xor { x, y } => {
+                let (vx, vy) = (registers[x as usize], registers[y as usize]);
+                registers[x as usize] = vx.zip(vy).map(|(vx, vy)| match *opcode {
+                    Opcode::Or { .. } => vx | vy,
+                    Opcode::And { .. } => vx & vy,
+                    _ => vx ^ vy,
+                });
+                // Whether VF is reset depends on the interpreter.
+                registers[0xF] = None;
+            }
+            Opcode::Add { x, y } | Opcode::Subtract { x, y } | Opcode::SubtractRev { x, y } => {
+                let (vx, vy) = (registers[x as usize], registers[y as usize]);
+                registers[x as usize] = vx.zip(vy).map(|(vx, vy)| match *opcode {
+                    Opcode::Add { .. } => vx.wrapping_add(vy),
+                    Opcode::Subtract { .. } => vx.wrapping_sub(vy),
+                    _ => vy.wrapping_sub(vx),
+                });
+                registers[0xF] = None;
+            }
+            Opcode::IndexRegistry { n0, n1, n2 } => {
+                state.i = Some(Chip8::to_decimal(n0, n1, n2) as usize);
+                state.i_moved_by = None;
+            }
+            Opcode::LongIndex => {
+                state.i = self
+                    .word(address + 2)
+                    .map(|raw| (raw.v0 as usize) << 8 | raw.v1 as usize);
+                state.i_moved_by = None;
+            }
+            Opcode::AddVxToI { x } => {
+                state.i = state
+                    .i
+                    .zip(registers[x as usize])
+                    .map(|(i, v)| i + v as usize);
+            }
+            Opcode::SaveToMemory { .. } | Opcode::LoadFromMemory { .. } => {
+                state.i = None;
+                state.i_moved_by = Some(address);
+            }
+            Opcode::HighRes => state.screen = Some((128, 64)),
+            Opcode::LowRes => state.screen = Some((64, 32)),
+            Opcode::SetSprite { .. } | Opcode::SetBigSprite { .. } => {
+                state.i = None;
+                state.i_moved_by = None;
+            }
+            _ => {
+                let written = written_registers(opcode);
+                for (n, register) in registers.iter_mut().enumerate() {
+                    if written & 1 << n != 0 {
+                        *register = None;
+                    }
+                }
+            }
+        }
+    }
+
+    // Writes into instructions and reads beyond the ROM, now that the code
+    // is known.
+    fn check_memory(&mut self, code: &BTreeSet<usize>) {
+        let rom_end = self.start + self.rom.len();
+        for (address, start, len) in self.writes.clone() {
+            let end = start + len;
+            if end > self.memory {
+                let message = format!(
+                    "writes 0x{:03X}-0x{:03X}, past the end of memory",
+                    start,
+                    end - 1
+                );
+                self.report(address, Severity::Error, message);
+            } else if let Some(target) = code.range(start..end).next() {
+                let message = format!("writes into the instruction at 0x{:03X}", target & !1);
+                self.report(address, Severity::Warning, message);
+            }
+        }
+        for (address, start, len) in self.reads.clone() {
+            let end = start + len;
+            let written = self
+                .writes
+                .iter()
+                .any(|(_, w_start, w_len)| *w_start <= start && end <= w_start + w_len);
+            if end > self.memory {
+                let message = format!(
+                    "reads 0x{:03X}-0x{:03X}, past the end of memory",
+                    start,
+                    end - 1
+                );
+                self.report(address, Severity::Error, message);
+            } else if end > rom_end && !written {
+                let message = format!(
+                    "reads 0x{:03X}-0x{:03X}, past the end of the ROM at 0x{:03X}",
+                    start,
+                    end - 1,
+                    rom_end - 1
+                );
+                self.report(address, Severity::Warning, message);
+            }
+        }
+    }
+}
+
+// Registers an instruction may change, as a mask with bit n for Vn.
+fn written_registers(opcode: &Opcode) -> u16 {
+    let one = |x: u8| 1u16 << x;
+    let up_to = |x: u8| ((2u32 << x) - 1) as u16;
+    match *opcode {
+        Opcode::NormalRegistry { x, .. }
+        | Opcode::AddRegistry { x, .. }
+        | Opcode::Random { x, .. }
+        | Opcode::SaveTimer { x }
+        | Opcode::WaitKeyDown { x } => one(x),
+        Opcode::Set { x, .. } => one(x),
+        Opcode::Or { x, .. }
+        | Opcode::And { x, .. }
+        | Opcode::Xor { x, .. }
+        | Opcode::Add { x, .. }
+        | Opcode::Subtract { x, .. }
+        | Opcode::SubtractRev { x, .. }
+        | Opcode::ShiftLeft { x, .. }
+        | Opcode::ShiftRight { x, .. } => one(x) | one(0xF),
+        Opcode::Draw { .. } => one(0xF),
+        Opcode::LoadFromMemory { x } | Opcode::LoadFlags { x } => up_to(x),
+        Opcode::LoadRange { x, y } => {
+            let (low, high) = (x.min(y), x.max(y));
+            up_to(high) & !(one(low) - 1)
+        }
+        _ => 0,
+    }
+}
+
+fn changes_i(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::IndexRegistry { .. }
+            | Opcode::LongIndex
+            | Opcode::AddVxToI { .. }
+            | Opcode::SetSprite { .. }
+            | Opcode::SetBigSprite { .. }
+            | Opcode::SaveToMemory { .. }
+            | Opcode::LoadFromMemory { .. }
+    )
+}
@@ -1,765 +1,1366 @@
-use clap::Parser;
-use rand::prelude::*;
-use std::io::Read;
-use std::time::Instant;
-use std::{fs, path::PathBuf};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
 
 use sdl3::{
     event::Event, keyboard::Keycode, pixels::Color, rect::Point, render::Canvas, video::Window,
 };
 
+use cheat::Cheats;
+use chip8::{cheat, font, keypad, quirks, romdb, symbols};
+use chip8::{parse_number, Chip8, MemoryAccess, Opcode, RawOpCode, STACK};
+use config::Settings;
+use control::Control;
+use coverage::Coverage;
+use dap::DapServer;
+use debug::{Breakpoint, Debugger, Trace, Watchpoint};
+use detect::Detection;
+use font::FontPreset;
+use gamepad::Gamepads;
+use gdb::{GdbServer, Request};
+use keymap::{HeldKeys, HostKey, KeyMap, Rebinder};
+use keypad::WaitMode;
+use menu::Menu;
+use osd::Osd;
+use panel::Panel;
+use profile::Profiler;
+use quirks::{LayoutOverrides, Platform};
+use romdb::{RomDb, RomInfo};
+use script::Scripts;
+use source::Rom;
+use watch::Watcher;
+
+mod config;
+mod control;
+mod coverage;
+mod dap;
+mod debug;
+mod detect;
+mod gamepad;
+mod gdb;
+mod keymap;
+mod lint;
+mod menu;
+mod octo;
+mod osd;
+mod panel;
+mod profile;
+mod script;
+mod source;
+mod watch;
+
 fn main() {
     let args = Cli::parse();
-    let mut chip8 = Chip8::new();
-    chip8.load(args.path);
-
-    let sdl_context = sdl3::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
 
-    let window = video_subsystem.window("chip8", 960, 480).build().unwrap();
+    let cli = args.settings();
+    let config = Settings::load(args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    });
+    let global = config.for_rom(&[], &Settings::default(), &cli);
+
+    if args.print_config {
+        let settings = match &args.path {
+            Some(path) if is_rom(path) => {
+                let rom = source::read(path).unwrap_or_else(|e| {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                });
+                config.for_rom(&rom_keys(path, &rom), &rom.options, &cli)
+            }
+            _ => global,
+        };
+        print!("{}", settings.effective().to_toml());
+        return;
+    }
 
-    let mut canvas = window.into_canvas();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(15.0, 15.0).unwrap();
+    let mut romdb = RomDb::builtin();
+    if let Some(path) = &global.romdb {
+        romdb.load(path).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+    }
 
-    let instant = Instant::now();
-    let mut time;
-    let mut last_frame_time = 0.0f32;
-    let frame_rate_inv = 1.0f32 / 60.0f32;
+    if let Some(Command::Info { rom }) = &args.command {
+        if let Err(e) = print_info(rom, &romdb) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    'running: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => {
-                    break 'running;
-                }
-                _ => {}
+    if let Some(Command::Lint { rom }) = &args.command {
+        match lint_rom(rom, &config, &cli, &romdb) {
+            Ok(clean) => process::exit(if clean { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
             }
-
-            chip8.input_handle(&event);
         }
+    }
 
-        time = instant.elapsed().as_secs_f32();
-        let allow_display = (time - last_frame_time) > frame_rate_inv;
-
-        chip8.execute(allow_display);
+    let mut gdb = args.gdb.map(|port| {
+        let server = GdbServer::bind(port).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+        if let Some(address) = server.address() {
+            println!("GDB server listening on {}", address);
+        }
+        server
+    });
+    let mut dap = args.dap.map(|port| {
+        let server = DapServer::bind(port).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+        if let Some(address) = server.address() {
+            println!("Debug adapter listening on {}", address);
+        }
+        server
+    });
 
-        if allow_display {
-            last_frame_time = time;
-            chip8.display(&mut canvas);
+    if args.headless {
+        if args.path.is_none() && dap.is_none() {
+            eprintln!("error: --headless needs a ROM unless a --dap client launches one");
+            process::exit(1);
         }
+        process::exit(run_headless(
+            &args, &global, &config, &cli, &romdb, gdb, dap,
+        ));
     }
-}
 
-#[derive(Parser)]
-struct Cli {
-    path: PathBuf,
-}
-
-enum Opcode {
-    Clear,
-    Return,
-
-    Random { x: u8, n0: u8, n1: u8 },
-
-    NormalRegistry { x: u8, n0: u8, n1: u8 },
-    IndexRegistry { n0: u8, n1: u8, n2: u8 },
-    AddRegistry { x: u8, n0: u8, n1: u8 },
-
-    SaveToMemory { x: u8 },
-    LoadFromMemory { x: u8 },
-    AddVxToI { x: u8 },
-    SaveDigits { x: u8 },
-    SetSprite { x: u8 },
-
-    SetTimer { x: u8 },
-    SaveTimer { x: u8 },
-    SetSoundTimer { x: u8 },
-
-    SkipIfEqualXN { x: u8, n0: u8, n1: u8 },
-    SkipIfNotEqualXN { x: u8, n0: u8, n1: u8 },
-    SkipIfEqualXY { x: u8, y: u8 },
-    SkipIfNotEqualXY { x: u8, y: u8 },
-    Jump { n0: u8, n1: u8, n2: u8 },
-    JumpOffset { n0: u8, n1: u8, n2: u8 },
-    Subroutine { n0: u8, n1: u8, n2: u8 },
-
-    Set { x: u8, y: u8 },
-    Or { x: u8, y: u8 },
-    And { x: u8, y: u8 },
-    Xor { x: u8, y: u8 },
-    Add { x: u8, y: u8 },
-    Subtract { x: u8, y: u8 },
-    SubtractRev { x: u8, y: u8 },
-    ShiftLeft { x: u8, y: u8 },
-    ShiftRight { x: u8, y: u8 },
-
-    SkipIfKeyDown { x: u8 },
-    SkipIfKeyUp { x: u8 },
-    WaitKeyDown { x: u8 },
-
-    Draw { x: u8, y: u8, n: u8 },
-
-    None { raw: RawOpCode },
-}
+    let sdl_context = sdl3::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
 
-struct RawOpCode {
-    v0: u8,
-    v1: u8,
-}
+    let scale = global.scale();
+    let window = video_subsystem
+        .window("chip8", 64 * scale, 32 * scale)
+        .build()
+        .unwrap();
 
-impl RawOpCode {
-    fn as_string(&self) -> String {
-        format!("{:02X}{:02X}", self.v0, self.v1)
+    let mut canvas = window.into_canvas();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(scale as f32, scale as f32).unwrap();
+
+    let mut gamepads = Gamepads::new(sdl_context.gamepad().unwrap());
+    let mut panel = Panel::new((64 * scale, 32 * scale));
+    let mut osd = Osd::new(String::new());
+    let mut rebinder: Option<Rebinder> = None;
+
+    // ROMs are picked from this directory when none is given or Escape is pressed.
+    let menu_dir = match &args.path {
+        Some(path) if path.is_dir() => path.clone(),
+        Some(path) => match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+        None => PathBuf::from("."),
+    };
+
+    let mut scripts = load_scripts(&args);
+    let mut chip8 = Chip8::new();
+    let mut keymap = load_keymap(&global, "", &mut osd);
+    let mut held = HeldKeys::default();
+    let mut settings = global.clone();
+    let mut menu = None;
+    let mut rom_path = None;
+    let mut watcher = None;
+    match &args.path {
+        Some(path) if is_rom(path) => {
+            (chip8, keymap, settings) = launch(path, &config, &cli, &romdb, &mut canvas, &mut osd)
+                .unwrap_or_else(|e| {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                });
+            rom_path = Some(path.clone());
+            watcher = args.watch.then(|| Watcher::new(path));
+            if let Err(e) = scripts.start(&mut chip8) {
+                osd.message(e);
+            }
+        }
+        _ => menu = Some(Menu::open(&menu_dir, &romdb)),
     }
-}
 
-struct Chip8 {
-    memory: [u8; 4096],
-    registry: [u8; 16],
-    stack: [usize; 8],
-    key: [bool; 16],
-    sub_pointer: usize,
-    i: usize,
-    start: usize,
-    end: usize,
-    program_counter: usize,
-    pixel_map: [[u8; 32]; 64],
-    delay_timer: u8,
-    sound_timer: u8,
-    wait_key_down: bool,
-    rng: ThreadRng,
-}
-
-impl Chip8 {
-    fn new() -> Chip8 {
-        Chip8 {
-            memory: [0; 4096],
-            registry: [0; 16],
-            stack: [0; 8],
-            key: [false; 16],
-            sub_pointer: 0,
-            i: 0,
-            start: 512,
-            end: 512,
-            program_counter: 512,
-            pixel_map: [[0; 32]; 64],
-            delay_timer: 0,
-            sound_timer: 0,
-            wait_key_down: false,
-            rng: rand::rng(),
+    let mut control = Control::new(settings.fast_forward());
+    let mut tools = Tools::new(&args);
+    if rom_path.is_some() {
+        if let Err(e) = tools.debugger.check_symbols(&chip8.symbols) {
+            eprintln!("error: {}", e);
+            process::exit(1);
         }
     }
 
-    fn load(&mut self, file_path: PathBuf) {
-        let mut data = Vec::new();
-        let mut file = fs::File::open(file_path).unwrap();
-
-        file.read_to_end(&mut data).unwrap();
-        self.end = self.start + data.len();
-        self.memory[self.start..self.end].copy_from_slice(&data[..]);
-
-        let font = [
-            0xF0u8, 0x90u8, 0x90u8, 0x90u8, 0xF0u8, // 0
-            0x20u8, 0x60u8, 0x20u8, 0x20u8, 0x70u8, // 1
-            0xF0u8, 0x10u8, 0xF0u8, 0x80u8, 0xF0u8, // 2
-            0xF0u8, 0x10u8, 0xF0u8, 0x10u8, 0xF0u8, // 3
-            0x90u8, 0x90u8, 0xF0u8, 0x10u8, 0x10u8, // 4
-            0xF0u8, 0x80u8, 0xF0u8, 0x10u8, 0xF0u8, // 5
-            0xF0u8, 0x80u8, 0xF0u8, 0x90u8, 0xF0u8, // 6
-            0xF0u8, 0x10u8, 0x20u8, 0x40u8, 0x40u8, // 7
-            0xF0u8, 0x90u8, 0xF0u8, 0x90u8, 0xF0u8, // 8
-            0xF0u8, 0x90u8, 0xF0u8, 0x10u8, 0xF0u8, // 9
-            0xF0u8, 0x90u8, 0xF0u8, 0x90u8, 0x90u8, // A
-            0xE0u8, 0x90u8, 0xE0u8, 0x90u8, 0xE0u8, // B
-            0xF0u8, 0x80u8, 0x80u8, 0x80u8, 0xF0u8, // C
-            0xE0u8, 0x90u8, 0x90u8, 0x90u8, 0xE0u8, // D
-            0xF0u8, 0x80u8, 0xF0u8, 0x80u8, 0xF0u8, // E
-            0xF0u8, 0x80u8, 0xF0u8, 0x80u8, 0x80u8, // F
-        ];
-
-        self.memory[0x50..0xA0].copy_from_slice(&font[..]);
-    }
-
-    fn fetch(&self) -> RawOpCode {
-        RawOpCode {
-            v0: self.memory[self.program_counter],
-            v1: self.memory[self.program_counter + 1],
-        }
-    }
-
-    fn decode(raw_opcode: RawOpCode) -> Opcode {
-        let hex = ((raw_opcode.v0 as i32) << 8) | raw_opcode.v1 as i32;
-        let c0 = ((hex & 0xF000) >> 12) as u8;
-        let c1 = ((hex & 0x0F00) >> 8) as u8;
-        let c2 = ((hex & 0x00F0) >> 4) as u8;
-        let c3 = (hex & 0x000F) as u8;
-
-        match c0 {
-            0x0 => match c3 {
-                0x0 => Opcode::Clear, // 00E0
-
-                0xE => Opcode::Return, // 00EE
-
-                _ => Opcode::None { raw: raw_opcode },
-            },
-
-            0xC => Opcode::Random {
-                x: c1,
-                n0: c2,
-                n1: c3,
-            },
-
-            0x6 => Opcode::NormalRegistry {
-                x: c1,
-                n0: c2,
-                n1: c3,
-            }, // 6xnn
-
-            0xA => Opcode::IndexRegistry {
-                n0: c1,
-                n1: c2,
-                n2: c3,
-            }, // Annn
-
-            0x7 => Opcode::AddRegistry {
-                x: c1,
-                n0: c2,
-                n1: c3,
-            }, // 7xnn
-
-            0xF => match raw_opcode.v1 {
-                0x55 => Opcode::SaveToMemory { x: c1 }, // Fx55
-
-                0x65 => Opcode::LoadFromMemory { x: c1 }, // Fx65
-
-                0x1E => Opcode::AddVxToI { x: c1 }, // Fx1E
-
-                0x33 => Opcode::SaveDigits { x: c1 }, // Fx33
-
-                0x29 => Opcode::SetSprite { x: c1 }, // Fx29
-
-                0x15 => Opcode::SetTimer { x: c1 }, // Fx15
-
-                0x07 => Opcode::SaveTimer { x: c1 }, // Fx07
-
-                0x18 => Opcode::SetSoundTimer { x: c1 }, // Fx18
-
-                0x0A => Opcode::WaitKeyDown { x: c1 }, // Fx0A
-
-                _ => Opcode::None { raw: raw_opcode },
-            },
-
-            0x3 => Opcode::SkipIfEqualXN {
-                x: c1,
-                n0: c2,
-                n1: c3,
-            }, // 3Xnn
+    let instant = Instant::now();
+    let mut time;
+    let mut last_frame_time = 0.0f32;
+    let mut frame_rate_inv = 1.0f32 / settings.frame_rate();
+    let mut instructions = 0;
 
-            0x4 => Opcode::SkipIfNotEqualXN {
-                x: c1,
-                n0: c2,
-                n1: c3,
-            }, // 4Xnn
+    'running: loop {
+        let mut picked = None;
+        for event in event_pump.poll_iter() {
+            let pad_inputs = gamepads.handle(&event, &mut osd);
 
-            0x5 => Opcode::SkipIfEqualXY { x: c1, y: c2 }, // 5xy0
+            if let Some(screen) = &mut rebinder {
+                for (host, pressed) in pad_inputs {
+                    if pressed {
+                        screen.bind(host, &mut keymap);
+                    }
+                }
+                if !screen.handle(&event, &mut keymap) {
+                    chip8.keypad.release_all();
+                    held.clear();
+                    match &settings.keymap {
+                        Some(path) => match screen.save(&keymap, path) {
+                            Ok(true) => osd.message("Key bindings saved"),
+                            Ok(false) => osd.message("Key bindings unchanged"),
+                            Err(e) => osd.message(e),
+                        },
+                        None => osd.message("Key bindings updated"),
+                    }
+                    rebinder = None;
+                }
+                if let Event::Quit { .. } = event {
+                    break 'running;
+                }
+                continue;
+            }
 
-            0x9 => Opcode::SkipIfNotEqualXY { x: c1, y: c2 }, // 9xy0
+            if let Some(screen) = &mut menu {
+                let mut hosts: Vec<HostKey> = pad_inputs
+                    .iter()
+                    .filter(|(_, pressed)| *pressed)
+                    .map(|(host, _)| *host)
+                    .collect();
+                if let Some((keys, true)) = keymap::event_hosts(&event) {
+                    hosts.extend(keys);
+                }
 
-            0x1 => Opcode::Jump {
-                n0: c1,
-                n1: c2,
-                n2: c3,
-            }, // 1nnn
+                let mut keys: Vec<usize> = keymap.keys(&hosts).collect();
+                for (key, pressed) in panel.handle(&event, &canvas) {
+                    if pressed {
+                        keys.push(key);
+                    }
+                }
 
-            0xB => Opcode::JumpOffset {
-                n0: c1,
-                n1: c2,
-                n2: c3,
-            }, // Bnnn
+                let mut selected = screen.handle(&event);
+                for key in keys {
+                    if selected.is_none() {
+                        selected = screen.key(key);
+                    }
+                }
 
-            0x2 => Opcode::Subroutine {
-                n0: c1,
-                n1: c2,
-                n2: c3,
-            }, // 2nnn
+                if selected.is_some() {
+                    picked = selected;
+                }
+                if let Event::Quit { .. } = event {
+                    break 'running;
+                }
+                continue;
+            }
 
-            0x8 => match c3 {
-                0x0 => Opcode::Set { x: c1, y: c2 }, // 8xy0
+            match event {
+                Event::Quit { .. } => {
+                    break 'running;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    repeat: false,
+                    ..
+                } => {
+                    menu = Some(Menu::open(&menu_dir, &romdb));
+                    canvas.window_mut().set_title("chip8").unwrap();
+                    keymap = load_keymap(&global, "", &mut osd);
+                    continue;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    repeat: false,
+                    ..
+                } => {
+                    osd.show_fps = !osd.show_fps;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => {
+                    osd.show_ips = !osd.show_ips;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => {
+                    let keys = panel.toggle(&mut canvas);
+                    panel_input(&mut chip8, &keys, &keymap, &mut held);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => {
+                    let rom = rom_path.as_deref().filter(|_| menu.is_none());
+                    rebinder = Some(Rebinder::new(rom.map(rom_name)));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    osd.message(control.toggle_pause());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    control.advance();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } => {
+                    osd.message(control.cycle_slow_motion());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => {
+                    chip8.soft_reset();
+                    osd.message("Soft reset");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(path) = &rom_path {
+                        match launch(path, &config, &cli, &romdb, &mut canvas, &mut osd) {
+                            Ok(launched) => {
+                                (chip8, keymap, settings) = launched;
+                                osd.message("Hard reset");
+                                if let Err(e) = scripts.start(&mut chip8) {
+                                    osd.message(e);
+                                }
+                            }
+                            Err(e) => osd.message(e),
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => {
+                    chip8.cheats.enabled = !chip8.cheats.enabled;
+                    osd.message(if chip8.cheats.enabled {
+                        "Cheats on"
+                    } else {
+                        "Cheats off"
+                    });
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    if let Some(message) = control.set_fast_forward(true) {
+                        osd.message(message);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    if let Some(message) = control.set_fast_forward(false) {
+                        osd.message(message);
+                    }
+                }
+                _ => {}
+            }
 
-                0x1 => Opcode::Or { x: c1, y: c2 }, // 8xy1
+            input_handle(&mut chip8, &event, &keymap, &mut held);
+            for (host, pressed) in pad_inputs {
+                host_input(&mut chip8, &[host], pressed, &keymap, &mut held);
+            }
+            let keys = panel.handle(&event, &canvas);
+            panel_input(&mut chip8, &keys, &keymap, &mut held);
+        }
 
-                0x2 => Opcode::And { x: c1, y: c2 }, // 8xy2
+        // A ROM picked from the menu or launched by a DAP client.
+        let mut requested = picked.map(|path| (path, false));
+        if let Some(server) = &mut dap {
+            let running_rom = rom_path.as_deref().filter(|_| menu.is_none());
+            match server.poll(&mut chip8, running_rom) {
+                Some(dap::Request::Launch(path)) => requested = Some((path, true)),
+                Some(dap::Request::Kill) => break 'running,
+                None => {}
+            }
+        }
+        if let Some((path, from_dap)) = requested {
+            let result =
+                launch(&path, &config, &cli, &romdb, &mut canvas, &mut osd).map(|launched| {
+                    menu = None;
+                    (chip8, keymap, settings) = launched;
+                    watcher = args.watch.then(|| Watcher::new(&path));
+                    rom_path = Some(path.clone());
+                    control = Control::new(settings.fast_forward());
+                    frame_rate_inv = 1.0 / settings.frame_rate();
+                    tools.restart();
+                });
+            match &result {
+                Ok(()) => {
+                    if let Err(e) = tools.debugger.check_symbols(&chip8.symbols) {
+                        osd.message(e);
+                    }
+                    if let Err(e) = scripts.start(&mut chip8) {
+                        osd.message(e);
+                    }
+                }
+                Err(e) => osd.message(e.clone()),
+            }
+            if let (true, Some(server)) = (from_dap, &mut dap) {
+                server.launched(&chip8, &path, result);
+            }
+        }
 
-                0x3 => Opcode::Xor { x: c1, y: c2 }, // 8xy3
+        // Picks up a rebuilt ROM, keeping the key bindings and the window.
+        if menu.is_none() && watcher.as_mut().is_some_and(Watcher::changed) {
+            if let Some(path) = &rom_path {
+                // A broken rebuild leaves the old program running.
+                match launch(path, &config, &cli, &romdb, &mut canvas, &mut osd) {
+                    Ok((mut reloaded, _, _)) => {
+                        if args.keep_registers {
+                            reloaded.keep_registers(&chip8);
+                        }
+                        chip8 = reloaded;
+                        osd.message("ROM changed on disk, reloaded");
+                    }
+                    Err(e) => osd.message(e),
+                }
+            }
+        }
 
-                0x4 => Opcode::Add { x: c1, y: c2 }, // 8xy4
+        // Screens that only change on input are redrawn once a frame, with
+        // the thread asleep in between.
+        if let Some(screen) = &rebinder {
+            screen.draw(&mut canvas, &keymap);
+            canvas.present();
+            thread::sleep(Duration::from_secs_f32(frame_rate_inv));
+            continue;
+        }
 
-                0x5 => Opcode::Subtract { x: c1, y: c2 }, // 8xy5
+        if let Some(screen) = &menu {
+            screen.draw(&mut canvas);
+            osd.draw(&mut canvas);
+            canvas.present();
+            thread::sleep(Duration::from_secs_f32(frame_rate_inv));
+            continue;
+        }
 
-                0x7 => Opcode::SubtractRev { x: c1, y: c2 }, // 8xy7
+        if let Err(e) = scripts.keys(&mut chip8) {
+            osd.message(e);
+        }
 
-                0x6 => Opcode::ShiftRight { x: c1, y: c2 }, // 8xy6
+        time = instant.elapsed().as_secs_f32();
+        let running = control.running();
+        let frame_time = if running {
+            frame_rate_inv / control.speed()
+        } else {
+            frame_rate_inv
+        };
+        let allow_display = (time - last_frame_time) > frame_time;
+
+        if let Some(server) = &mut gdb {
+            if let Some(Request::Kill) = server.poll(&mut chip8) {
+                break 'running;
+            }
+        }
+        let attached_running = gdb.as_ref().is_none_or(GdbServer::running)
+            && dap.as_ref().is_none_or(DapServer::running);
+
+        let throttled = control
+            .ipf(chip8.ipf)
+            .is_some_and(|ipf| instructions >= ipf);
+        if running && attached_running && (allow_display || !throttled) {
+            let step = step(
+                &mut chip8,
+                allow_display,
+                &mut gdb,
+                &mut dap,
+                &mut tools,
+                &mut scripts,
+                &mut osd,
+            );
+            match step {
+                Step::Attached(message) => osd.message(message),
+                Step::Ran(retired) => instructions += retired as u32,
+                Step::Stopped(report) => stop(report, &chip8, &mut control, &mut osd),
+                Step::Fault(e) => fault(e, &chip8, &mut gdb, &mut dap, &mut control, &mut osd),
+            }
+        }
 
-                0xE => Opcode::ShiftLeft { x: c1, y: c2 }, // 8xyE
+        if allow_display {
+            last_frame_time = time;
+            if running {
+                control.frame_done(instructions);
+                if let Err(e) = scripts.frame(&mut chip8) {
+                    osd.message(e);
+                }
+            }
+            instructions = 0;
+            osd.status = control.status();
+            osd.overlay = scripts.texts();
+            display(&chip8, &mut canvas);
+            if panel.visible {
+                panel.draw(
+                    &mut canvas,
+                    chip8.keypad.polled(),
+                    chip8.keypad.down(),
+                    &keymap,
+                );
+            }
+            chip8.keypad.end_frame();
+            osd.count_frame();
+            osd.draw(&mut canvas);
+            canvas.present();
+        }
 
-                _ => Opcode::None { raw: raw_opcode },
-            },
-            0xE => match raw_opcode.v1 {
-                0x9E => Opcode::SkipIfKeyDown { x: c1 }, // Ex9E
+        if scripts.exit_code().is_some() {
+            break 'running;
+        }
+    }
 
-                0xA1 => Opcode::SkipIfKeyUp { x: c1 }, // ExA1
+    if let Err(e) = tools.finish(&chip8, &args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 
-                _ => Opcode::None { raw: raw_opcode },
-            },
+    if let Some(code) = scripts.exit_code() {
+        process::exit(code);
+    }
+}
 
-            0xD => Opcode::Draw {
-                x: c1,
-                y: c2,
-                n: c3,
-            }, // DxyN
+// The debugger and recorders the command line turns on, which see every
+// instruction either loop runs.
+struct Tools {
+    debugger: Debugger,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    trace: Option<Trace>,
+}
 
-            _ => Opcode::None { raw: raw_opcode },
+impl Tools {
+    fn new(args: &Cli) -> Tools {
+        let trace = args
+            .trace
+            .as_deref()
+            .map(Trace::create)
+            .transpose()
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            });
+        let profiling = args.profile || args.profile_output.is_some();
+        let covering = args.coverage.is_some() || args.heatmap.is_some();
+        Tools {
+            debugger: Debugger::new(args.breakpoints.clone(), args.watchpoints.clone()),
+            profiler: profiling.then(Profiler::new),
+            coverage: covering.then(Coverage::new),
+            trace,
         }
     }
 
-    fn to_decimal(n0: u8, n1: u8, n2: u8) -> u16 {
-        n0 as u16 * 256 + n1 as u16 * 16 + n2 as u16
+    // Counts afresh for a newly launched ROM.
+    fn restart(&mut self) {
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new());
+        }
+        if self.coverage.is_some() {
+            self.coverage = Some(Coverage::new());
+        }
     }
 
-    fn set_normal_registry(&mut self, x: u8, n0: u8, n1: u8) {
-        self.registry[x as usize] = Chip8::to_decimal(0, n0, n1) as u8;
+    // Prints the profile and writes the reports asked for, once the
+    // program has ended.
+    fn finish(&self, chip8: &Chip8, args: &Cli) -> Result<(), String> {
+        if let Some(profiler) = &self.profiler {
+            profiler.print(chip8);
+            if let Some(path) = &args.profile_output {
+                profiler.save(chip8, path)?;
+            }
+        }
+        if let Some(coverage) = &self.coverage {
+            println!("{}", coverage.summary(chip8));
+            if let Some(path) = &args.coverage {
+                coverage.save_disassembly(chip8, path)?;
+            }
+            if let Some(path) = &args.heatmap {
+                coverage.save_heatmap(chip8, path)?;
+            }
+        }
+        Ok(())
     }
+}
 
-    fn set_index_registry(&mut self, n0: u8, n1: u8, n2: u8) {
-        self.i = Chip8::to_decimal(n0, n1, n2) as usize;
-    }
+// What came of one step of the machine.
+enum Step {
+    // An attached GDB or DAP client stopped the program, for this reason.
+    Attached(&'static str),
+    // The instruction ran, or waits for the next frame if not retired.
+    Ran(bool),
+    // A --break or --watchpoint stopped the program, with its report.
+    Stopped(String),
+    Fault(String),
+}
 
-    fn add_registry(&mut self, x: u8, n0: u8, n1: u8) {
-        let result =
-            (self.registry[x as usize] as u16 + Chip8::to_decimal(0, n0, n1) as u16) & 0xFF;
-        self.registry[x as usize] = result as u8;
+// Runs the instruction at PC unless something stops the program before
+// it, and records it; both the window and the headless loop step this way.
+fn step(
+    chip8: &mut Chip8,
+    allow_display: bool,
+    gdb: &mut Option<GdbServer>,
+    dap: &mut Option<DapServer>,
+    tools: &mut Tools,
+    scripts: &mut Scripts,
+    osd: &mut Osd,
+) -> Step {
+    if gdb
+        .as_mut()
+        .is_some_and(|server| server.check_breakpoint(chip8))
+    {
+        return Step::Attached("Stopped at a GDB breakpoint");
     }
-
-    fn save_to_memory(&mut self, x: u8) {
-        let d = x as usize + 1;
-        let s = self.i & 0xFFF;
-        let e = (self.i + d) & 0xFFF;
-        self.memory[s..e].copy_from_slice(&self.registry[0..d]);
-        self.i += d;
+    if dap.as_mut().is_some_and(|server| server.check_stop(chip8)) {
+        return Step::Attached("Stopped in the debugger");
     }
-
-    fn load_from_memory(&mut self, x: u8) {
-        let d = x as usize + 1;
-        let s = self.i & 0xFFF;
-        let e = (self.i + d) & 0xFFF;
-        self.registry[0..d].copy_from_slice(&self.memory[s..e]);
-        self.i += d;
+    if let Some(report) = tools.debugger.check_breakpoint(chip8) {
+        return Step::Stopped(report);
     }
 
-    fn set_sprite(&mut self, x: u8) {
-        self.i = 0x050 + self.registry[x as usize] as usize * 5;
+    let pc = chip8.program_counter;
+    let retired = match chip8.execute(allow_display) {
+        Ok(retired) => retired,
+        Err(e) => return Step::Fault(e),
+    };
+    if let Some(profiler) = &mut tools.profiler {
+        profiler.record(chip8, pc, retired);
     }
-
-    fn add_vx_to_i(&mut self, x: u8) {
-        self.i += self.registry[x as usize] as usize;
+    if !retired {
+        return Step::Ran(false);
     }
-
-    fn set_timer(&mut self, x: u8) {
-        self.delay_timer = self.registry[x as usize];
+    osd.count_instruction();
+    if let Some(coverage) = &mut tools.coverage {
+        coverage.record(pc, chip8.access);
     }
-
-    fn save_timer(&mut self, x: u8) {
-        self.registry[x as usize] = self.delay_timer;
+    if tools
+        .trace
+        .as_mut()
+        .is_some_and(|trace| trace.write(chip8, pc).is_err())
+    {
+        osd.message("Could not write the trace, stopped tracing");
+        tools.trace = None;
+    }
+    let stopped = tools.debugger.check_access(chip8);
+    if let Err(e) = scripts.instruction(chip8, pc) {
+        osd.message(e);
+    }
+    // A GDB single step stops here, once the instruction has been recorded.
+    if let Some(server) = gdb {
+        server.check_step(chip8);
+    }
+    match stopped {
+        Some(report) => Step::Stopped(report),
+        None => Step::Ran(true),
     }
+}
 
-    fn set_sound_timer(&mut self, x: u8) {
-        self.sound_timer = self.registry[x as usize];
+// Pauses at a breakpoint or watchpoint, showing what stopped the program
+// and printing the calls that led there.
+fn stop(report: String, chip8: &Chip8, control: &mut Control, osd: &mut Osd) {
+    control.pause();
+    println!("{}", report);
+    for call in debug::call_stack(chip8) {
+        println!("  {}", call);
     }
+    osd.message(report);
+}
 
-    fn save_digits(&mut self, x: u8) {
-        let ci = self.i & 0xFFF;
-        self.memory[ci] = self.registry[x as usize] / 100;
-        self.memory[ci + 1] = (self.registry[x as usize] / 10) % 10;
-        self.memory[ci + 2] = self.registry[x as usize] % 10;
+// Stops at an instruction that cannot run, in the attached debugger if
+// there is one.
+fn fault(
+    message: String,
+    chip8: &Chip8,
+    gdb: &mut Option<GdbServer>,
+    dap: &mut Option<DapServer>,
+    control: &mut Control,
+    osd: &mut Osd,
+) {
+    let reported = gdb.as_mut().is_some_and(|server| server.fault(chip8))
+        || dap
+            .as_mut()
+            .is_some_and(|server| server.fault(chip8, &message));
+    if reported {
+        osd.message(message);
+    } else {
+        stop(message, chip8, control, osd);
     }
+}
 
-    fn skip_if_equal_xn(&mut self, x: u8, n0: u8, n1: u8) {
-        if self.registry[x as usize] == Chip8::to_decimal(0, n0, n1) as u8 {
-            self.step_counter();
-        }
+// Runs without a window, for a GDB or DAP client or scripts to drive,
+// until one of them ends it, and returns the exit code. A DAP client may
+// launch the ROM itself.
+fn run_headless(
+    args: &Cli,
+    global: &Settings,
+    config: &Settings,
+    cli: &Settings,
+    romdb: &RomDb,
+    mut gdb: Option<GdbServer>,
+    mut dap: Option<DapServer>,
+) -> i32 {
+    let mut scripts = load_scripts(args);
+    let mut tools = Tools::new(args);
+    let mut osd = Osd::new(String::new());
+    let mut chip8 = Chip8::new();
+    let mut settings = global.clone();
+    let mut rom_path = args.path.clone();
+    if let Some(path) = &rom_path {
+        (chip8, _, settings) = start_rom(path, config, cli, romdb, &mut osd).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+        // Failed hooks have been reported and their scripts stopped.
+        let _ = scripts.start(&mut chip8);
     }
 
-    fn skip_if_equal_xy(&mut self, x: u8, y: u8) {
-        if self.registry[x as usize] == self.registry[y as usize] {
-            self.step_counter();
+    let mut next_frame = Instant::now();
+    let mut instructions = 0;
+    let code = loop {
+        if let Some(code) = scripts.exit_code() {
+            break code;
+        }
+        if let Some(server) = &mut gdb {
+            if let Some(Request::Kill) = server.poll(&mut chip8) {
+                break 0;
+            }
+        }
+        if let Some(server) = &mut dap {
+            match server.poll(&mut chip8, rom_path.as_deref()) {
+                Some(dap::Request::Launch(path)) => {
+                    let result = start_rom(&path, config, cli, romdb, &mut osd).map(
+                        |(launched, _, launched_settings)| {
+                            chip8 = launched;
+                            settings = launched_settings;
+                            rom_path = Some(path.clone());
+                            tools.restart();
+                        },
+                    );
+                    if result.is_ok() {
+                        let _ = scripts.start(&mut chip8);
+                    }
+                    server.launched(&chip8, &path, result);
+                }
+                Some(dap::Request::Kill) => break 0,
+                None => {}
+            }
+        }
+        let attached_running = gdb.as_ref().is_none_or(GdbServer::running)
+            && dap.as_ref().is_none_or(DapServer::running);
+        if rom_path.is_none() || !attached_running {
+            thread::sleep(Duration::from_millis(1));
+            continue;
         }
-    }
 
-    fn skip_if_not_equal_xn(&mut self, x: u8, n0: u8, n1: u8) {
-        if self.registry[x as usize] != Chip8::to_decimal(0, n0, n1) as u8 {
-            self.step_counter();
+        let now = Instant::now();
+        let allow_display = now >= next_frame;
+        if allow_display {
+            let frame = Duration::from_secs_f32(1.0 / settings.frame_rate());
+            next_frame = (next_frame + frame).max(now);
+            instructions = 0;
+            let _ = scripts.frame(&mut chip8);
         }
-    }
+        let _ = scripts.keys(&mut chip8);
+        let throttled = chip8.ipf.is_some_and(|ipf| instructions >= ipf);
+        if throttled && !allow_display {
+            thread::sleep(next_frame - now);
+            continue;
+        }
+        let step = step(
+            &mut chip8,
+            allow_display,
+            &mut gdb,
+            &mut dap,
+            &mut tools,
+            &mut scripts,
+            &mut osd,
+        );
+        match step {
+            // --break and --watchpoint conflict with --headless, as nothing
+            // could resume the program; clients set their own breakpoints.
+            Step::Attached(_) | Step::Stopped(_) => {}
+            Step::Ran(retired) => instructions += retired as u32,
+            Step::Fault(e) => {
+                let reported = gdb.as_mut().is_some_and(|server| server.fault(&chip8))
+                    || dap.as_mut().is_some_and(|server| server.fault(&chip8, &e));
+                if !reported {
+                    eprintln!("error: {}", e);
+                    break 1;
+                }
+            }
+        }
+        if allow_display {
+            chip8.keypad.end_frame();
+        }
+    };
 
-    fn skip_if_not_equal_xy(&mut self, x: u8, y: u8) {
-        if self.registry[x as usize] != self.registry[y as usize] {
-            self.step_counter();
+    match tools.finish(&chip8, args) {
+        Ok(()) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
         }
     }
+}
 
-    fn jump(&mut self, n0: u8, n1: u8, n2: u8) {
-        self.program_counter = Chip8::to_decimal(n0, n1, n2) as usize;
-    }
+// The --script files, ready to run; a script that does not compile ends
+// the emulator before it starts.
+fn load_scripts(args: &Cli) -> Scripts {
+    Scripts::load(&args.scripts, args.script_dir.as_deref()).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    })
+}
 
-    fn jump_offset(&mut self, n0: u8, n1: u8, n2: u8) {
-        self.program_counter = (Chip8::to_decimal(n0, n1, n2) + self.registry[0] as u16) as usize;
-    }
+// Starts a fresh machine running the ROM at path, with its own key bindings
+// and the settings that apply to it, and names the window after it.
+fn launch(
+    path: &Path,
+    config: &Settings,
+    cli: &Settings,
+    romdb: &RomDb,
+    canvas: &mut Canvas<Window>,
+    osd: &mut Osd,
+) -> Result<(Chip8, KeyMap, Settings), String> {
+    let launched = start_rom(path, config, cli, romdb, osd)?;
+    canvas
+        .window_mut()
+        .set_title(&format!("chip8 - {}", osd.rom_name))
+        .unwrap();
+    Ok(launched)
+}
 
-    fn subroutine(&mut self, n0: u8, n1: u8, n2: u8) {
-        self.stack[self.sub_pointer] = self.program_counter;
-        self.sub_pointer += 1;
-        self.jump(n0, n1, n2);
+// A machine set up for the ROM, with the platform, quirks and layout it will
+// run with. Detection, the database and any options stored with the program
+// beat the global settings, but not the ROM's own section or the command
+// line. Detection only ever moves to a newer platform than the configured one,
+// and a hi-res ROM keeps its 64x64 screen on any platform unless a layer sets
+// hires itself.
+fn configure_machine(
+    rom: &Rom,
+    names: &[String],
+    config: &Settings,
+    cli: &Settings,
+    romdb: &RomDb,
+) -> (Chip8, Detection, Option<RomInfo>) {
+    let mut chip8 = Chip8::new();
+    configure(&mut chip8, config);
+    let detection = detect::scan(&rom.data, chip8.layout.start);
+    if detection.platform > chip8.platform {
+        chip8.set_platform(detection.platform);
     }
-
-    fn return_subroutine(&mut self) {
-        self.sub_pointer -= 1;
-        self.program_counter = self.stack[self.sub_pointer];
-        self.stack[self.sub_pointer] = 0;
+    let info = chip8.load(rom.data.clone(), romdb);
+    configure(&mut chip8, &rom.options);
+    if let Some(section) = config.section(names) {
+        configure(&mut chip8, section);
     }
-
-    fn set(&mut self, x: u8, y: u8) {
-        self.registry[x as usize] = self.registry[y as usize];
+    configure(&mut chip8, cli);
+    let layers = [
+        Some(config),
+        Some(&rom.options),
+        config.section(names),
+        Some(cli),
+    ];
+    let hires_set = layers
+        .into_iter()
+        .flatten()
+        .filter_map(|settings| settings.layout)
+        .chain(info.as_ref().map(|info| info.layout))
+        .any(|layout| layout.hires.is_some());
+    if detection.hires && !hires_set {
+        chip8.layout.hires = true;
     }
+    (chip8, detection, info)
+}
 
-    fn or(&mut self, x: u8, y: u8) {
-        self.registry[x as usize] |= self.registry[y as usize];
-        self.registry[0xF] = 0;
+fn start_rom(
+    path: &Path,
+    config: &Settings,
+    cli: &Settings,
+    romdb: &RomDb,
+    osd: &mut Osd,
+) -> Result<(Chip8, KeyMap, Settings), String> {
+    let rom = source::read(path)?;
+    let rom_name = rom_name(path);
+    let names = rom_keys(path, &rom);
+    let settings = config.for_rom(&names, &rom.options, cli);
+
+    let (mut chip8, detection, info) = configure_machine(&rom, &names, config, cli, romdb);
+    if detection.hires && chip8.layout.hires {
+        osd.message("Detected hi-res program");
     }
-
-    fn and(&mut self, x: u8, y: u8) {
-        self.registry[x as usize] &= self.registry[y as usize];
-        self.registry[0xF] = 0;
+    if detection.platform > Platform::Chip8 && chip8.platform == detection.platform {
+        let name = detection.platform.name();
+        match detection.platform.runs() {
+            true => osd.message(format!("Detected {} program", name)),
+            false => osd.message(format!(
+                "{} program: not every instruction is supported",
+                name
+            )),
+        }
     }
-
-    fn xor(&mut self, x: u8, y: u8) {
-        self.registry[x as usize] ^= self.registry[y as usize];
-        self.registry[0xF] = 0;
+    chip8.font = font::load(settings.font, settings.font_file.as_deref())?;
+    chip8.symbols = rom.symbols.clone();
+    if let Some(path) = &settings.symbols {
+        chip8.symbols.load(path)?;
     }
-
-    fn add(&mut self, x: u8, y: u8) {
-        let n = self.registry[x as usize] as u16 + self.registry[y as usize] as u16;
-        self.registry[x as usize] = (n & 0xFF) as u8;
-        self.registry[0xF] = (n > 255) as u8;
+    if let Some(path) = settings.cheat_file() {
+        chip8.cheats = Cheats::load(&path, &romdb::sha1(&rom.data), &chip8.symbols)?;
     }
-
-    fn subtract(&mut self, x: u8, y: u8) {
-        let n = self.registry[x as usize] as i16 - self.registry[y as usize] as i16;
-        self.registry[x as usize] = (n & 0xFF) as u8;
-        self.registry[0xF] = (n >= 0) as u8;
+    chip8.power_on()?;
+    if settings.wait_key_press() {
+        chip8.wait_mode = WaitMode::Press;
     }
-
-    fn subtract_rev(&mut self, x: u8, y: u8) {
-        let n = self.registry[y as usize] as i16 - self.registry[x as usize] as i16;
-        self.registry[x as usize] = (n & 0xFF) as u8;
-        self.registry[0xF] = (n >= 0) as u8;
+    let title = match &info {
+        Some(info) => info.display_title(),
+        None => rom_name.clone(),
+    };
+
+    osd.rom_name = title.clone();
+    if rom.format == source::Format::Binary {
+        osd.message(format!("Loaded {}", title));
+    } else {
+        osd.message(format!("Loaded {} from {}", title, rom.format.name()));
     }
-
-    fn shift_left(&mut self, x: u8, y: u8) {
-        let r = self.registry[y as usize];
-        self.registry[x as usize] = (r << 1) & 0xFF;
-        self.registry[0xF] = (r & 0b10000000) >> 7;
+    if let Some(info) = &info {
+        if !info.keys.is_empty() {
+            osd.message(info.key_hints());
+        }
     }
-
-    fn shift_right(&mut self, x: u8, y: u8) {
-        let r = self.registry[y as usize];
-        self.registry[x as usize] = (r >> 1) & 0xFF;
-        self.registry[0xF] = r & 0b00000001;
+    if !chip8.cheats.is_empty() {
+        osd.message(format!("{} cheats on (F10 toggles)", chip8.cheats.len()));
     }
 
-    fn skip_if_keydown(&mut self, x: u8) {
-        if self.key[self.registry[x as usize] as usize] == true {
-            self.step_counter();
-        }
-    }
+    let keymap = load_keymap(&settings, &rom_name, osd);
+    Ok((chip8, keymap, settings))
+}
 
-    fn skip_if_keyup(&mut self, x: u8) {
-        if self.key[self.registry[x as usize] as usize] == false {
-            self.step_counter();
+// Default bindings with the configured keys and then the --keymap file on top.
+fn load_keymap(settings: &Settings, rom_name: &str, osd: &mut Osd) -> KeyMap {
+    let mut keymap = KeyMap::default();
+    keymap.apply(&settings.keys).unwrap();
+    if let Some(path) = &settings.keymap {
+        if let Err(e) = keymap.load(path, rom_name) {
+            eprintln!("error: {}", e);
+            osd.message(e);
+            return KeyMap::default();
         }
     }
+    keymap
+}
 
-    fn wait_keydown(&mut self, x: u8) {
-        for i in 0..16 {
-            if self.key[i] && !self.wait_key_down {
-                self.wait_key_down = true;
-            }
-
-            if !self.key[i] && self.wait_key_down {
-                self.registry[x as usize] = i as u8;
-                self.step_counter();
-                self.wait_key_down = false;
-                break;
-            }
+// What `chip8 info` shows: the ROM's format, database entry and the
+// platform its reachable instructions need.
+fn print_info(path: &Path, romdb: &RomDb) -> Result<(), String> {
+    let rom = source::read(path)?;
+    println!("File:      {}", path.display());
+    println!("Format:    {}, {} bytes", rom.format.name(), rom.data.len());
+    println!("SHA-1:     {}", romdb::sha1(&rom.data));
+    match romdb.lookup(&rom.data) {
+        Some(info) => {
+            let platform = info.platform.map_or("no platform", |p| p.name());
+            println!("Database:  {}, {}", info.display_title(), platform);
         }
+        None => println!("Database:  not listed"),
     }
 
-    fn random(&mut self, x: u8, n0: u8, n1: u8) {
-        self.registry[x as usize] =
-            self.rng.random_range(0u8..254u8) & Chip8::to_decimal(0, n0, n1) as u8;
+    let detection = detect::scan(&rom.data, Chip8::new().layout.start);
+    let mut summary = format!("{} reachable instructions", detection.reachable);
+    if detection.computed_jumps > 0 {
+        summary += &format!(", {} computed jumps not followed", detection.computed_jumps);
     }
+    let hires = if detection.hires { ", hi-res" } else { "" };
+    println!(
+        "Detected:  {}{} ({})",
+        detection.platform.name(),
+        hires,
+        summary
+    );
+    if !detection.platform.runs() {
+        println!(
+            "  not every {} instruction is supported yet",
+            detection.platform.name()
+        );
+    }
+    if detection.reasons.is_empty() {
+        println!("  only original CHIP-8 instructions found");
+    }
+    let newer = detection
+        .reasons
+        .iter()
+        .filter(|reason| reason.4 > Platform::Hires);
+    if newer.count() == 1 {
+        println!("  a single newer instruction is not enough to switch platforms");
+    }
+    for (address, text, mnemonic, feature, platform) in &detection.reasons {
+        println!(
+            "  0x{:03X}  {}  {:<16} {} ({})",
+            address,
+            text,
+            mnemonic,
+            feature,
+            platform.name()
+        );
+    }
+    println!("The ROM database, a config file or --platform take precedence when running.");
+    Ok(())
+}
 
-    fn draw(&mut self, x: u8, y: u8, n: u8) {
-        let px = self.registry[x as usize];
-        let py = self.registry[y as usize];
-
-        self.registry[0xF] = 0;
-
-        for oy in 0..n {
-            let idx = oy as usize + self.i;
-            let mut bit_row = self.memory[idx];
-            for ox in (0..8).rev() {
-                let pixel = bit_row & 0b1;
-                bit_row >>= 1;
-
-                let dx = (px + ox) as usize % 64;
-                let dy = (py + oy) as usize % 32;
-
-                if pixel == 1 {
-                    if self.pixel_map[dx][dy] == 1 {
-                        self.registry[0xF] = 1;
-                    }
-                    self.pixel_map[dx][dy] ^= 1;
-                }
-            }
+// Prints what the linter finds, and whether nothing worse than a note was
+// found.
+fn lint_rom(path: &Path, config: &Settings, cli: &Settings, romdb: &RomDb) -> Result<bool, String> {
+    let rom = source::read(path)?;
+    let (chip8, ..) = configure_machine(&rom, &rom_keys(path, &rom), config, cli, romdb);
+    let start = chip8.layout.start;
+    let report = lint::check(&rom.data, &chip8.layout);
+    for finding in &report.findings {
+        let opcode = lint_opcode(&rom.data, start, finding.address);
+        println!(
+            "{}  {:<7}  {}",
+            rom.symbols.describe(finding.address),
+            finding.severity.name(),
+            finding.message
+        );
+        if let Some((text, opcode)) = opcode {
+            println!("    {}  {}", text, rom.symbols.mnemonic(&opcode));
         }
     }
+    let count = |severity| {
+        report
+            .findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count(lint::Severity::Error), count(lint::Severity::Warning));
+    println!(
+        "{}: {} errors, {} warnings, {} notes ({} reachable instructions, detected {})",
+        path.display(),
+        errors,
+        warnings,
+        count(lint::Severity::Info),
+        report.reachable,
+        report.platform.name()
+    );
+    Ok(errors + warnings == 0)
+}
+
+fn lint_opcode(rom: &[u8], start: usize, address: usize) -> Option<(String, Opcode)> {
+    let offset = address.checked_sub(start)?;
+    let raw = RawOpCode {
+        v0: *rom.get(offset)?,
+        v1: *rom.get(offset + 1)?,
+    };
+    Some((raw.as_string(), Chip8::decode(raw)))
+}
 
-    fn step_counter(&mut self) {
-        self.program_counter += 2;
+fn rom_name(path: &Path) -> String {
+    if path == Path::new("-") {
+        return "stdin".to_string();
     }
+    path.file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
 
-    fn execute(&mut self, allow_display: bool) {
-        let opcode = Chip8::decode(self.fetch());
-        match opcode {
-            Opcode::Clear => {
-                if allow_display {
-                    self.pixel_map = [[0; 32]; 64];
-                    self.step_counter();
-                }
-            }
-            Opcode::Return => {
-                self.return_subroutine();
-                self.step_counter();
-            }
-            Opcode::Random { x, n0, n1 } => {
-                self.random(x, n0, n1);
-                self.step_counter();
-            }
-            Opcode::NormalRegistry { x, n0, n1 } => {
-                self.set_normal_registry(x, n0, n1);
-                self.step_counter();
-            }
-            Opcode::IndexRegistry { n0, n1, n2 } => {
-                self.set_index_registry(n0, n1, n2);
-                self.step_counter();
-            }
-            Opcode::AddRegistry { x, n0, n1 } => {
-                self.add_registry(x, n0, n1);
-                self.step_counter();
-            }
-            Opcode::SaveToMemory { x } => {
-                self.save_to_memory(x);
-                self.step_counter();
-            }
-            Opcode::LoadFromMemory { x } => {
-                self.load_from_memory(x);
-                self.step_counter();
-            }
-            Opcode::SetSprite { x } => {
-                self.set_sprite(x);
-                self.step_counter();
-            }
-            Opcode::AddVxToI { x } => {
-                self.add_vx_to_i(x);
-                self.step_counter();
-            }
-            Opcode::SetTimer { x } => {
-                self.set_timer(x);
-                self.step_counter();
-            }
-            Opcode::SaveTimer { x } => {
-                self.save_timer(x);
-                self.step_counter();
-            }
-            Opcode::SetSoundTimer { x } => {
-                self.set_sound_timer(x);
-                self.step_counter();
-            }
-            Opcode::SaveDigits { x } => {
-                self.save_digits(x);
-                self.step_counter();
-            }
-            Opcode::SkipIfEqualXN { x, n0, n1 } => {
-                self.skip_if_equal_xn(x, n0, n1);
-                self.step_counter();
-            }
-            Opcode::SkipIfNotEqualXN { x, n0, n1 } => {
-                self.skip_if_not_equal_xn(x, n0, n1);
-                self.step_counter();
-            }
-            Opcode::SkipIfEqualXY { x, y } => {
-                self.skip_if_equal_xy(x, y);
-                self.step_counter();
-            }
-            Opcode::SkipIfNotEqualXY { x, y } => {
-                self.skip_if_not_equal_xy(x, y);
-                self.step_counter();
-            }
-            Opcode::Jump { n0, n1, n2 } => {
-                self.jump(n0, n1, n2);
-            }
-            Opcode::JumpOffset { n0, n1, n2 } => {
-                self.jump_offset(n0, n1, n2);
-            }
-            Opcode::Subroutine { n0, n1, n2 } => {
-                self.subroutine(n0, n1, n2);
-            }
-            Opcode::Set { x, y } => {
-                self.set(x, y);
-                self.step_counter();
-            }
-            Opcode::Or { x, y } => {
-                self.or(x, y);
-                self.step_counter();
-            }
-            Opcode::And { x, y } => {
-                self.and(x, y);
-                self.step_counter();
-            }
-            Opcode::Xor { x, y } => {
-                self.xor(x, y);
-                self.step_counter();
-            }
-            Opcode::Add { x, y } => {
-                self.add(x, y);
-                self.step_counter();
-            }
-            Opcode::Subtract { x, y } => {
-                self.subtract(x, y);
-                self.step_counter();
-            }
-            Opcode::SubtractRev { x, y } => {
-                self.subtract_rev(x, y);
-                self.step_counter();
-            }
-            Opcode::ShiftRight { x, y } => {
-                self.shift_right(x, y);
-                self.step_counter();
-            }
-            Opcode::ShiftLeft { x, y } => {
-                self.shift_left(x, y);
-                self.step_counter();
-            }
-            Opcode::SkipIfKeyDown { x } => {
-                self.skip_if_keydown(x);
-                self.step_counter();
-            }
-            Opcode::SkipIfKeyUp { x } => {
-                self.skip_if_keyup(x);
-                self.step_counter();
-            }
-            Opcode::WaitKeyDown { x } => {
-                self.wait_keydown(x);
-            }
-            Opcode::Draw { x, y, n } => {
-                if allow_display {
-                    self.draw(x, y, n);
-                    self.step_counter();
-                }
-            }
-            Opcode::None { raw } => {
-                unimplemented!("opcode {} not implemented", raw.as_string())
-            }
-        }
+// Names a per-ROM config section may use: the file name without extension,
+// or the SHA-1 of the file.
+fn rom_keys(path: &Path, rom: &Rom) -> [String; 2] {
+    [rom_name(path), romdb::sha1(&rom.data)]
+}
 
-        if allow_display {
-            self.delay_timer -= if self.delay_timer > 0 { 1 } else { 0 };
-            self.sound_timer -= if self.sound_timer > 0 { 1 } else { 0 };
-        }
-    }
+// A ROM file, or "-" for standard input, as opposed to a directory.
+fn is_rom(path: &Path) -> bool {
+    path == Path::new("-") || path.is_file()
+}
 
-    fn input_handle(&mut self, event: &Event) {
-        match event {
-            Event::KeyDown { keycode, .. } => match keycode {
-                Some(Keycode::_1) => self.key[0x1] = true,
-                Some(Keycode::_2) => self.key[0x2] = true,
-                Some(Keycode::_3) => self.key[0x3] = true,
-                Some(Keycode::_4) => self.key[0xC] = true,
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(group(ArgGroup::new("remote").args(["gdb", "dap", "scripts"]).multiple(true)))]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-                Some(Keycode::Q) => self.key[0x4] = true,
-                Some(Keycode::W) => self.key[0x5] = true,
-                Some(Keycode::E) => self.key[0x6] = true,
-                Some(Keycode::R) => self.key[0xD] = true,
+    /// ROM to run (binary, hex text, Octo source or cartridge; - reads stdin), or a directory to pick one from
+    path: Option<PathBuf>,
 
-                Some(Keycode::A) => self.key[0x7] = true,
-                Some(Keycode::S) => self.key[0x8] = true,
-                Some(Keycode::D) => self.key[0x9] = true,
-                Some(Keycode::F) => self.key[0xE] = true,
+    /// Configuration file to use instead of chip8/config.toml in the user's config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-                Some(Keycode::Z) => self.key[0xA] = true,
-                Some(Keycode::X) => self.key[0x0] = true,
-                Some(Keycode::C) => self.key[0xB] = true,
-                Some(Keycode::V) => self.key[0xF] = true,
+    /// Print the configuration in effect, for the ROM if one is given, and exit
+    #[arg(long)]
+    print_config: bool,
 
-                _ => {}
-            },
+    /// Key binding file mapping host keys to chip8 keys
+    #[arg(long)]
+    keymap: Option<PathBuf>,
 
-            Event::KeyUp { keycode, .. } => match keycode {
-                Some(Keycode::_1) => self.key[0x1] = false,
-                Some(Keycode::_2) => self.key[0x2] = false,
-                Some(Keycode::_3) => self.key[0x3] = false,
-                Some(Keycode::_4) => self.key[0xC] = false,
+    /// ROM database file adding to or replacing the built-in entries
+    #[arg(long)]
+    romdb: Option<PathBuf>,
 
-                Some(Keycode::Q) => self.key[0x4] = false,
-                Some(Keycode::W) => self.key[0x5] = false,
-                Some(Keycode::E) => self.key[0x6] = false,
-                Some(Keycode::R) => self.key[0xD] = false,
+    /// Window pixels per chip8 pixel [default: 15]
+    #[arg(long)]
+    scale: Option<u32>,
 
-                Some(Keycode::A) => self.key[0x7] = false,
-                Some(Keycode::S) => self.key[0x8] = false,
-                Some(Keycode::D) => self.key[0x9] = false,
-                Some(Keycode::F) => self.key[0xE] = false,
+    /// Frames per second, which also sets the timer rate [default: 60]
+    #[arg(long)]
+    frame_rate: Option<f32>,
+
+    /// Speed multiplier while the fast-forward key (Tab) is held [default: 4]
+    #[arg(long)]
+    fast_forward: Option<f32>,
+
+    /// Platform whose quirks to emulate
+    #[arg(long, global = true, value_enum)]
+    platform: Option<Platform>,
+
+    /// Address programs are loaded and started at, like 0x600 for the ETI-660
+    #[arg(long, global = true, value_parser = parse_number)]
+    start: Option<usize>,
+
+    /// Address of the hex font
+    #[arg(long, global = true, value_parser = parse_number)]
+    font_address: Option<usize>,
+
+    /// Size of memory in bytes, from 0x1000 to 0x10000
+    #[arg(long, global = true, value_parser = parse_number)]
+    memory: Option<usize>,
+
+    /// Use the 64x64 screen of hi-res CHIP-8
+    #[arg(long, global = true)]
+    hires: bool,
+
+    /// Built-in font for Fx29 digits
+    #[arg(long, value_enum)]
+    font: Option<FontPreset>,
+
+    /// Font binary: 80 bytes of 4x5 digits, optionally followed by 8x10 ones
+    #[arg(long)]
+    font_file: Option<PathBuf>,
+
+    /// Symbol file naming addresses, for breakpoints, traces and reports
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Cheat file with values to freeze, by ROM, instead of chip8/cheats.toml in the user's config directory
+    #[arg(long)]
+    cheats: Option<PathBuf>,
+
+    /// Instructions per frame; unlimited unless set here or by the ROM database
+    #[arg(long)]
+    ipf: Option<u32>,
+
+    /// Finish Fx0A as soon as a key is pressed instead of when it is released
+    #[arg(long)]
+    wait_key_press: bool,
+
+    /// Reload the ROM whenever its file changes on disk
+    #[arg(long)]
+    watch: bool,
+
+    /// Keep registers, I, the stack, PC and timers when --watch reloads the ROM
+    #[arg(long, requires = "watch")]
+    keep_registers: bool,
+
+    /// Pause before running the instruction at an address, optionally only
+    /// when a condition holds, like "0x2A4 if V3 == 0x10 && I > 0x300"
+    #[arg(long = "break", value_parser = Breakpoint::parse, conflicts_with = "headless")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// Pause after an instruction reads or writes memory in a range, like
+    /// 0x300-0x30F:w; :r, :w or :rw, both by default
+    #[arg(long = "watchpoint", value_parser = Watchpoint::parse, conflicts_with = "headless")]
+    watchpoints: Vec<Watchpoint>,
+
+    /// Write every instruction run, with the registers after it, to a file
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Serve the GDB remote protocol on this localhost port; 0 picks a free one
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// Serve the Debug Adapter Protocol for editors on this localhost port; 0 picks a free one
+    #[arg(long)]
+    dap: Option<u16>,
+
+    /// Run without a window, driven by the --gdb or --dap client or the scripts
+    #[arg(long, requires = "remote")]
+    headless: bool,
+
+    /// Rhai script with hooks for frame, instruction, memory-write and key
+    /// events; can be given more than once
+    #[arg(long = "script")]
+    scripts: Vec<PathBuf>,
+
+    /// Directory scripts may read and write files and save screenshots in;
+    /// without it they have no file access
+    #[arg(long, requires = "scripts")]
+    script_dir: Option<PathBuf>,
+
+    /// Count instructions by address, kind and call stack and print a report at exit
+    #[arg(long)]
+    profile: bool,
+
+    /// Also write the profile to a file: JSON for .json, otherwise collapsed
+    /// stacks for flamegraph tools
+    #[arg(long)]
+    profile_output: Option<PathBuf>,
+
+    /// Track which ROM bytes run as code, are read as data or are written, and
+    /// save an annotated disassembly at exit
+    #[arg(long)]
+    coverage: Option<PathBuf>,
+
+    /// Save a GIF heatmap of memory use at exit
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+}
 
-                Some(Keycode::Z) => self.key[0xA] = false,
-                Some(Keycode::X) => self.key[0x0] = false,
-                Some(Keycode::C) => self.key[0xB] = false,
-                Some(Keycode::V) => self.key[0xF] = false,
+#[derive(Subcommand)]
+enum Command {
+    /// Show a ROM's format, database entry and the platform its instructions need
+    Info { rom: PathBuf },
+    /// Check a ROM for code that behaves differently between interpreters or may crash;
+    /// exits with 1 if there are warnings or errors
+    Lint { rom: PathBuf },
+}
 
-                _ => {}
-            },
+impl Cli {
+    fn layout(&self) -> Option<LayoutOverrides> {
+        let layout = LayoutOverrides {
+            start: self.start,
+            font: self.font_address,
+            memory: self.memory,
+            hires: self.hires.then_some(true),
+        };
+        let any = self.start.is_some() || self.font_address.is_some() || self.memory.is_some();
+        (any || self.hires).then_some(layout)
+    }
 
-            _ => {}
+    // The command line as the topmost configuration layer.
+    fn settings(&self) -> Settings {
+        let settings = Settings {
+            scale: self.scale,
+            frame_rate: self.frame_rate,
+            fast_forward: self.fast_forward,
+            wait_key_press: self.wait_key_press.then_some(true),
+            platform: self.platform,
+            ipf: self.ipf,
+            romdb: self.romdb.clone(),
+            keymap: self.keymap.clone(),
+            font: self.font,
+            font_file: self.font_file.clone(),
+            symbols: self.symbols.clone(),
+            cheats: self.cheats.clone(),
+            layout: self.layout(),
+            ..Settings::default()
+        };
+        if let Err(e) = settings.check_values() {
+            eprintln!("error: {}", e);
+            process::exit(1);
         }
+        settings
     }
+}
+
+// Applies whatever a configuration layer sets; the rest is left alone.
+fn configure(chip8: &mut Chip8, settings: &Settings) {
+    if let Some(platform) = settings.platform {
+        chip8.set_platform(platform);
+    }
+    if let Some(quirks) = &settings.quirks {
+        quirks.apply(&mut chip8.quirks);
+    }
+    if let Some(layout) = &settings.layout {
+        layout.apply(&mut chip8.layout);
+    }
+    if settings.ipf.is_some() {
+        chip8.ipf = settings.ipf;
+    }
+    if let Some(palette) = settings.palette() {
+        chip8.palette = palette;
+    }
+}
+
+fn input_handle(chip8: &mut Chip8, event: &Event, keymap: &KeyMap, held: &mut HeldKeys) {
+    if let Some((hosts, pressed)) = keymap::event_hosts(event) {
+        host_input(chip8, &hosts, pressed, keymap, held);
+    }
+}
 
-    fn display(&self, canvas: &mut Canvas<Window>) {
-        canvas.set_draw_color(Color::RGB(16, 16, 64));
-        canvas.clear();
-        canvas.set_draw_color(Color::RGB(224, 224, 128));
+fn host_input(
+    chip8: &mut Chip8,
+    hosts: &[HostKey],
+    pressed: bool,
+    keymap: &KeyMap,
+    held: &mut HeldKeys,
+) {
+    for (key, down) in held.update(hosts, pressed, keymap) {
+        chip8.keypad.set(key, down);
+    }
+}
 
-        let mut pixel = Point::new(0, 0);
+fn panel_input(chip8: &mut Chip8, keys: &[(usize, bool)], keymap: &KeyMap, held: &mut HeldKeys) {
+    for &(key, pressed) in keys {
+        let down = held.update_panel(key, pressed, keymap);
+        chip8.keypad.set(key, down);
+    }
+}
 
-        for x in 0..64usize {
-            for y in 0..32usize {
-                if self.pixel_map[x][y] == 1 {
-                    pixel.x = x as i32;
-                    pixel.y = y as i32;
-                    canvas.draw_point(pixel).unwrap();
-                }
+fn display(chip8: &Chip8, canvas: &mut Canvas<Window>) {
+    let [(br, bg, bb), (fr, fg, fb)] = chip8.palette;
+    canvas.set_draw_color(Color::RGB(br, bg, bb));
+    canvas.clear();
+    canvas.set_draw_color(Color::RGB(fr, fg, fb));
+
+    // Hi-res rows are half as tall, as they were on the VIP's TV picture.
+    let height = chip8.screen_height();
+    let (scale_x, scale_y) = canvas.scale();
+    canvas
+        .set_scale(scale_x, scale_y * 32.0 / height as f32)
+        .unwrap();
+
+    let mut pixel = Point::new(0, 0);
+
+    for x in 0..64usize {
+        for y in 0..height {
+            if chip8.pixel_map[x][y] == 1 {
+                pixel.x = x as i32;
+                pixel.y = y as i32;
+                canvas.draw_point(pixel).unwrap();
             }
         }
-
-        canvas.present();
     }
+
+    canvas.set_scale(scale_x, scale_y).unwrap();
 }
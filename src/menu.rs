@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl3::{event::Event, keyboard::Keycode, pixels::Color, render::Canvas, video::Window};
+
+use crate::osd::draw_text;
+use crate::romdb::RomDb;
+
+const EXTENSIONS: [&str; 6] = ["ch8", "sc8", "xo8", "8o", "hex", "gif"];
+const VISIBLE: usize = 18;
+
+pub struct Menu {
+    dir: PathBuf,
+    entries: Vec<(PathBuf, String)>,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn open(dir: &Path, romdb: &RomDb) -> Menu {
+        let mut entries: Vec<(PathBuf, String)> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+            })
+            .map(|path| {
+                let label = fs::read(&path)
+                    .ok()
+                    .and_then(|data| romdb.lookup(&data).map(|info| info.display_title()))
+                    .unwrap_or_else(|| crate::rom_name(&path));
+                (path, label)
+            })
+            .collect();
+        entries.sort_by_key(|(_, label)| label.to_lowercase());
+
+        Menu {
+            dir: dir.to_path_buf(),
+            entries,
+            selected: 0,
+        }
+    }
+
+    fn up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    fn down(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    fn launch(&self) -> Option<PathBuf> {
+        self.entries
+            .get(self.selected)
+            .map(|(path, _)| path.clone())
+    }
+
+    // Arrow keys and Enter; returns the ROM to launch.
+    pub fn handle(&mut self, event: &Event) -> Option<PathBuf> {
+        match event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Up),
+                ..
+            } => self.up(),
+            Event::KeyDown {
+                keycode: Some(Keycode::Down),
+                ..
+            } => self.down(),
+            Event::KeyDown {
+                keycode: Some(Keycode::Return),
+                repeat: false,
+                ..
+            } => return self.launch(),
+            _ => {}
+        }
+        None
+    }
+
+    // Chip8 keys from the keyboard, gamepads or on-screen keypad:
+    // 5 and 8 move, 6 launches.
+    pub fn key(&mut self, key: usize) -> Option<PathBuf> {
+        match key {
+            0x5 => self.up(),
+            0x8 => self.down(),
+            0x6 => return self.launch(),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>) {
+        canvas.set_draw_color(Color::RGB(16, 16, 64));
+        canvas.clear();
+        let (scale_x, scale_y) = canvas.scale();
+        canvas.set_scale(3.0, 3.0).unwrap();
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+
+        draw_text(canvas, 2, 2, &self.dir.display().to_string());
+
+        if self.entries.is_empty() {
+            draw_text(canvas, 2, 14, "NO ROMS FOUND");
+        }
+
+        let first = self.selected.saturating_sub(VISIBLE - 1);
+        for (n, (_, label)) in self.entries.iter().enumerate().skip(first).take(VISIBLE) {
+            let y = 14 + (n - first) as i32 * 8;
+            if n == self.selected {
+                canvas.set_draw_color(Color::RGB(224, 224, 128));
+                draw_text(canvas, 2, y, &format!("> {}", label));
+                canvas.set_draw_color(Color::RGB(255, 255, 255));
+            } else {
+                draw_text(canvas, 2, y, &format!("  {}", label));
+            }
+        }
+
+        canvas.set_scale(scale_x, scale_y).unwrap();
+    }
+}
@@ -0,0 +1,746 @@
+use std::collections::{BTreeMap, HashMap};
+
+pub struct Program {
+    pub data: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+    // Source line of the statement that put each instruction or byte there.
+    pub lines: BTreeMap<usize, usize>,
+}
+
+// Assembler for the Octo language: labels, constants, aliases, macros,
+// :calc, :next, :unpack, every chip8 and SCHIP statement, if/then,
+// if/begin/else/end and loop/while/again. :stringmode, :assert and the
+// XO-CHIP statements are not supported.
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(n, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |token| (token, n + 1))
+        })
+        .collect();
+
+    let mut assembler = Assembler {
+        tokens,
+        position: 0,
+        memory: vec![0; 4096],
+        here: START + 2,
+        end: START + 2,
+        labels: HashMap::new(),
+        lines: BTreeMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: Vec::new(),
+        flow: Vec::new(),
+    };
+
+    // Programs start wherever `: main` is, so the first word jumps there.
+    assembler
+        .fixups
+        .push((START, "main".to_string(), Patch::Address(0x1000), 0));
+    while assembler.position < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+const START: usize = 0x200;
+// Macros expanded in all, to stop ones that expand themselves forever.
+const EXPANSIONS: usize = 100_000;
+
+// How a label's address goes into the code once it is known.
+enum Patch {
+    // The low 12 bits of an instruction, with these high bits.
+    Address(u16),
+    // `v0 := n << 4 | high nibble` and `v1 := low byte`, for :unpack n.
+    Unpack(u16),
+}
+
+struct Macro<'a> {
+    parameters: Vec<&'a str>,
+    body: Vec<(&'a str, usize)>,
+}
+
+enum Flow {
+    Begin(usize),
+    Else(usize),
+    Loop(usize, Vec<usize>),
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(&'a str, usize)>,
+    position: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<String, usize>,
+    lines: BTreeMap<usize, usize>,
+    constants: HashMap<String, i32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<&'a str, Macro<'a>>,
+    expansions: usize,
+    // Address of the code, the label it needs, how it goes in and the line.
+    fixups: Vec<(usize, String, Patch, usize)>,
+    flow: Vec<Flow>,
+}
+
+impl<'a> Assembler<'a> {
+    fn line(&self) -> usize {
+        let n = self.position.min(self.tokens.len()).saturating_sub(1);
+        self.tokens.get(n).map_or(0, |(_, line)| *line)
+    }
+
+    fn error(&self, message: impl Into<String>) -> String {
+        format!("line {}: {}", self.line(), message.into())
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self.tokens.get(self.position).map(|(token, _)| *token);
+        self.position += 1;
+        token.ok_or_else(|| self.error("unexpected end of program"))
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(self.error(format!("expected `{}`, found `{}`", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, value: u8) -> Result<(), String> {
+        if self.here >= self.memory.len() {
+            return Err(self.error("program does not fit in memory"));
+        }
+        let line = self.line();
+        self.memory[self.here] = value;
+        self.lines.entry(self.here).or_insert(line);
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn word(&mut self, value: u16) -> Result<(), String> {
+        self.byte((value >> 8) as u8)?;
+        self.byte(value as u8)
+    }
+
+    fn patch(&mut self, address: usize, target: usize) {
+        let op = (self.memory[address] as u16) << 8 & 0xF000 | target as u16 & 0xFFF;
+        self.memory[address] = (op >> 8) as u8;
+        self.memory[address + 1] = op as u8;
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(token) {
+            return Some(*register);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn expect_register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register(token)
+            .ok_or_else(|| self.error(format!("expected a register, found `{}`", token)))
+    }
+
+    fn number(&self, token: &str) -> Option<i32> {
+        if let Some(value) = self.constants.get(token) {
+            return Some(*value);
+        }
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i32::from_str_radix(binary, 2).ok()?
+        } else {
+            digits.parse().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    fn expect_byte(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.number(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(value) => Err(self.error(format!("{} does not fit in a byte", value))),
+            None => Err(self.error(format!("expected a number, found `{}`", token))),
+        }
+    }
+
+    fn expect_nibble(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        match self.number(token) {
+            Some(value) if (0..16).contains(&value) => Ok(value as u16),
+            _ => Err(self.error(format!("expected a number from 0 to 15, found `{}`", token))),
+        }
+    }
+
+    // An instruction with a 12-bit address, which may name a later label.
+    fn address_op(&mut self, high: u16) -> Result<(), String> {
+        let token = self.next()?;
+        let address = match self.number(token) {
+            Some(value) if (0..0x1000).contains(&value) => value as u16,
+            Some(value) => return Err(self.error(format!("address {} is out of range", value))),
+            None => {
+                let line = self.line();
+                self.fixups
+                    .push((self.here, token.to_string(), Patch::Address(high), line));
+                0
+            }
+        };
+        self.word(high | address)
+    }
+
+    // The skips used when the condition is false and when it is true.
+    fn condition(&mut self) -> Result<(u16, u16), String> {
+        let x = self.expect_register()? as u16;
+        let operator = self.next()?;
+        match operator {
+            "key" => return Ok((0xE0A1 | x << 8, 0xE09E | x << 8)),
+            "-key" => return Ok((0xE09E | x << 8, 0xE0A1 | x << 8)),
+            "==" | "!=" => {}
+            _ => {
+                return Err(self.error(format!("unsupported comparison `{}`", operator)));
+            }
+        }
+
+        let token = self.next()?;
+        let (equal, not_equal) = match self.register(token) {
+            Some(y) => (
+                0x5000 | x << 8 | (y as u16) << 4,
+                0x9000 | x << 8 | (y as u16) << 4,
+            ),
+            None => {
+                self.position -= 1;
+                let n = self.expect_byte()? as u16;
+                (0x3000 | x << 8 | n, 0x4000 | x << 8 | n)
+            }
+        };
+        Ok(if operator == "==" {
+            (not_equal, equal)
+        } else {
+            (equal, not_equal)
+        })
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.to_string(), self.here).is_some() {
+                    return Err(self.error(format!("label `{}` is defined twice", name)));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self
+                    .number(value)
+                    .or_else(|| self.labels.get(value).map(|address| *address as i32))
+                    .ok_or_else(|| self.error(format!("expected a value, found `{}`", value)))?;
+                self.constants.insert(name.to_string(), value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.expect_register()?;
+                self.aliases.insert(name.to_string(), register);
+            }
+            ":org" => {
+                let token = self.next()?;
+                match self.number(token) {
+                    Some(address) if (START as i32..0x1000).contains(&address) => {
+                        self.here = address as usize;
+                    }
+                    _ => return Err(self.error(format!("bad :org address `{}`", token))),
+                }
+            }
+            ":byte" => {
+                let value = self.expect_byte()?;
+                self.byte(value)?;
+            }
+            ":call" => self.address_op(0x2000)?,
+            // Debugger annotations with no effect on the program.
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.word(0x00E0)?,
+            "return" | ";" => self.word(0x00EE)?,
+            "exit" => self.word(0x00FD)?,
+            "lores" => self.word(0x00FE)?,
+            "hires" => self.word(0x00FF)?,
+            "scroll-right" => self.word(0x00FB)?,
+            "scroll-left" => self.word(0x00FC)?,
+            "scroll-down" => {
+                let n = self.expect_nibble()?;
+                self.word(0x00C0 | n)?;
+            }
+            "bcd" | "save" | "load" | "saveflags" | "loadflags" => {
+                let x = self.expect_register()? as u16;
+                let low = match token {
+                    "bcd" => 0x33,
+                    "save" => 0x55,
+                    "load" => 0x65,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.word(0xF000 | x << 8 | low)?;
+            }
+            "sprite" => {
+                let x = self.expect_register()? as u16;
+                let y = self.expect_register()? as u16;
+                let n = self.expect_nibble()?;
+                self.word(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => self.address_op(0x1000)?,
+            "jump0" => self.address_op(0xB000)?,
+            "native" => self.address_op(0x0000)?,
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.expect_register()? as u16;
+                let low = if token == "delay" { 0x15 } else { 0x18 };
+                self.word(0xF000 | x << 8 | low)?;
+            }
+            "i" => match self.next()? {
+                ":=" => match self.next()? {
+                    "hex" => {
+                        let x = self.expect_register()? as u16;
+                        self.word(0xF029 | x << 8)?;
+                    }
+                    "bighex" => {
+                        let x = self.expect_register()? as u16;
+                        self.word(0xF030 | x << 8)?;
+                    }
+                    _ => {
+                        self.position -= 1;
+                        self.address_op(0xA000)?;
+                    }
+                },
+                "+=" => {
+                    let x = self.expect_register()? as u16;
+                    self.word(0xF01E | x << 8)?;
+                }
+                other => return Err(self.error(format!("unexpected `{}` after i", other))),
+            },
+            "if" => {
+                let (when_false, when_true) = self.condition()?;
+                match self.next()? {
+                    "then" => self.word(when_false)?,
+                    "begin" => {
+                        self.word(when_true)?;
+                        self.flow.push(Flow::Begin(self.here));
+                        self.word(0x1000)?;
+                    }
+                    other => {
+                        return Err(self.error(format!("expected then or begin, found `{}`", other)))
+                    }
+                }
+            }
+            "else" => {
+                let Some(Flow::Begin(jump)) = self.flow.pop() else {
+                    return Err(self.error("else without if ... begin"));
+                };
+                self.flow.push(Flow::Else(self.here));
+                self.word(0x1000)?;
+                self.patch(jump, self.here);
+            }
+            "end" => match self.flow.pop() {
+                Some(Flow::Begin(jump)) | Some(Flow::Else(jump)) => self.patch(jump, self.here),
+                _ => return Err(self.error("end without if ... begin")),
+            },
+            "loop" => self.flow.push(Flow::Loop(self.here, Vec::new())),
+            "while" => {
+                let (_, when_true) = self.condition()?;
+                self.word(when_true)?;
+                let here = self.here;
+                match self
+                    .flow
+                    .iter_mut()
+                    .rev()
+                    .find(|f| matches!(f, Flow::Loop(..)))
+                {
+                    Some(Flow::Loop(_, exits)) => exits.push(here),
+                    _ => return Err(self.error("while outside of loop ... again")),
+                }
+                self.word(0x1000)?;
+            }
+            "again" => {
+                let Some(Flow::Loop(start, exits)) = self.flow.pop() else {
+                    return Err(self.error("again without loop"));
+                };
+                self.word(0x1000 | start as u16)?;
+                for exit in exits {
+                    self.patch(exit, self.here);
+                }
+            }
+            ":next" => {
+                // The label is on the second byte of the next instruction,
+                // for code that rewrites its own operands.
+                let name = self.next()?;
+                if self
+                    .labels
+                    .insert(name.to_string(), self.here + 1)
+                    .is_some()
+                {
+                    return Err(self.error(format!("label `{}` is defined twice", name)));
+                }
+            }
+            ":unpack" => {
+                let n = self.expect_nibble()?;
+                let token = self.next()?;
+                let address = match self.number(token) {
+                    Some(value) if (0..0x1000).contains(&value) => value as u16,
+                    Some(value) => {
+                        return Err(self.error(format!("address {} is out of range", value)))
+                    }
+                    None => {
+                        let line = self.line();
+                        self.fixups
+                            .push((self.here, token.to_string(), Patch::Unpack(n), line));
+                        0
+                    }
+                };
+                self.word(0x6000 | n << 4 | address >> 8)?;
+                self.word(0x6100 | address & 0xFF)?;
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut parameters = Vec::new();
+                loop {
+                    match self.next()? {
+                        "{" => break,
+                        parameter => parameters.push(parameter),
+                    }
+                }
+                let body = self.block()?;
+                self.macros.insert(name, Macro { parameters, body });
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let expression: Vec<&str> = self.block()?.iter().map(|(token, _)| *token).collect();
+                let value = self.calc(&expression)?;
+                self.constants
+                    .insert(name.to_string(), value.floor() as i32);
+            }
+            ":stringmode" | ":assert" => {
+                return Err(self.error(format!("{} is not supported", token)));
+            }
+            _ => {
+                if self.macros.contains_key(token) {
+                    return self.expand(token);
+                }
+                if let Some(x) = self.register(token) {
+                    return self.register_statement(x as u16);
+                }
+                if self.number(token).is_some() {
+                    self.position -= 1;
+                    let value = self.expect_byte()?;
+                    return self.byte(value);
+                }
+                if token.starts_with(':') || token.contains(":=") {
+                    return Err(self.error(format!("unknown statement `{}`", token)));
+                }
+                // Any other name calls the subroutine with that label.
+                self.position -= 1;
+                self.address_op(0x2000)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The tokens up to the `}` closing a `{` just read, which may nest.
+    fn block(&mut self) -> Result<Vec<(&'a str, usize)>, String> {
+        let start = self.position;
+        let mut depth = 1;
+        loop {
+            match self.next()? {
+                "{" => depth += 1,
+                "}" if depth == 1 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(self.tokens[start..self.position - 1].to_vec())
+    }
+
+    // Replaces a macro and its arguments with its body, arguments put in
+    // for the parameters, to be assembled next.
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > EXPANSIONS {
+            return Err(self.error(format!("macro `{}` expands without end", name)));
+        }
+        let line = self.line();
+        let count = self.macros[name].parameters.len();
+        let mut arguments = Vec::new();
+        for _ in 0..count {
+            arguments.push(self.next()?);
+        }
+        let definition = &self.macros[name];
+        let body: Vec<(&'a str, usize)> = definition
+            .body
+            .iter()
+            .map(|(token, _)| {
+                let argument = definition.parameters.iter().position(|p| p == token);
+                (argument.map_or(*token, |n| arguments[n]), line)
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    // Evaluates a :calc expression. As in Octo, there is no precedence:
+    // operators apply right to left unless parenthesised.
+    fn calc(&self, tokens: &[&str]) -> Result<f64, String> {
+        match self.expression(tokens)? {
+            (value, []) => Ok(value),
+            (_, rest) => Err(self.error(format!("unexpected `{}` in :calc", rest[0]))),
+        }
+    }
+
+    fn expression<'t>(&self, tokens: &'t [&'t str]) -> Result<(f64, &'t [&'t str]), String> {
+        let (left, rest) = self.term(tokens)?;
+        let Some((operator, rest)) = rest.split_first() else {
+            return Ok((left, rest));
+        };
+        if *operator == ")" {
+            return Ok((left, &tokens[tokens.len() - rest.len() - 1..]));
+        }
+        let (right, rest) = self.expression(rest)?;
+        let (a, b) = (left, right);
+        let value = match *operator {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" => a / b,
+            "%" => a % b,
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "&" => (a as i64 & b as i64) as f64,
+            "|" => (a as i64 | b as i64) as f64,
+            "^" => (a as i64 ^ b as i64) as f64,
+            "<<" | ">>" => {
+                let shifted = u32::try_from(b as i64).ok().and_then(|n| match *operator {
+                    "<<" => (a as i64).checked_shl(n),
+                    _ => (a as i64).checked_shr(n),
+                });
+                shifted.ok_or_else(|| self.error("shift out of range in :calc"))? as f64
+            }
+            "<" => (a < b) as i32 as f64,
+            "<=" => (a <= b) as i32 as f64,
+            ">" => (a > b) as i32 as f64,
+            ">=" => (a >= b) as i32 as f64,
+            "==" => (a == b) as i32 as f64,
+            "!=" => (a != b) as i32 as f64,
+            _ => return Err(self.error(format!("unknown operator `{}` in :calc", operator))),
+        };
+        Ok((value, rest))
+    }
+
+    fn term<'t>(&self, tokens: &'t [&'t str]) -> Result<(f64, &'t [&'t str]), String> {
+        let Some((token, rest)) = tokens.split_first() else {
+            return Err(self.error("unfinished :calc expression"));
+        };
+        let unary = |f: fn(f64) -> f64| -> Result<(f64, &'t [&'t str]), String> {
+            let (value, rest) = self.term(rest)?;
+            Ok((f(value), rest))
+        };
+        match *token {
+            "(" => match self.expression(rest)? {
+                (value, [")", rest @ ..]) => Ok((value, rest)),
+                _ => Err(self.error("missing `)` in :calc")),
+            },
+            "-" => unary(|a| -a),
+            "~" => unary(|a| !(a as i64) as f64),
+            "!" => unary(|a| (a == 0.0) as i32 as f64),
+            "abs" => unary(f64::abs),
+            "sqrt" => unary(f64::sqrt),
+            "sin" => unary(f64::sin),
+            "cos" => unary(f64::cos),
+            "floor" => unary(f64::floor),
+            "ceil" => unary(f64::ceil),
+            "HERE" => Ok((self.here as f64, rest)),
+            "PI" => Ok((std::f64::consts::PI, rest)),
+            "E" => Ok((std::f64::consts::E, rest)),
+            _ => self
+                .number(token)
+                .or_else(|| self.labels.get(*token).map(|address| *address as i32))
+                .map(|value| (value as f64, rest))
+                .ok_or_else(|| self.error(format!("unknown value `{}` in :calc", token))),
+        }
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let token = self.next()?;
+        if let Some(y) = self.register(token) {
+            let low = match operator {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(self.error(format!("unknown operator `{}`", operator))),
+            };
+            return self.word(0x8000 | x << 8 | (y as u16) << 4 | low);
+        }
+
+        match (operator, token) {
+            (":=", "key") => self.word(0xF00A | x << 8),
+            (":=", "delay") => self.word(0xF007 | x << 8),
+            (":=", "random") => {
+                let n = self.expect_byte()? as u16;
+                self.word(0xC000 | x << 8 | n)
+            }
+            (":=" | "+=" | "-=", _) => {
+                self.position -= 1;
+                let n = self.expect_byte()?;
+                match operator {
+                    ":=" => self.word(0x6000 | x << 8 | n as u16),
+                    "+=" => self.word(0x7000 | x << 8 | n as u16),
+                    _ => self.word(0x7000 | x << 8 | n.wrapping_neg() as u16),
+                }
+            }
+            _ => Err(self.error(format!(
+                "`{}` needs a register, found `{}`",
+                operator, token
+            ))),
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, String> {
+        if let Some(flow) = self.flow.last() {
+            let open = match flow {
+                Flow::Loop(..) => "loop without again",
+                _ => "if ... begin without end",
+            };
+            return Err(open.to_string());
+        }
+
+        for (address, name, patch, line) in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&name) {
+                Some(target) => *target as u16,
+                None if name == "main" => return Err("no `: main` label".to_string()),
+                None => return Err(format!("line {}: undefined label `{}`", line, name)),
+            };
+            let words = match patch {
+                Patch::Address(high) => vec![high | target],
+                Patch::Unpack(n) => vec![0x6000 | n << 4 | target >> 8, 0x6100 | target & 0xFF],
+            };
+            for (n, word) in words.into_iter().enumerate() {
+                self.memory[address + 2 * n..address + 2 * n + 2]
+                    .copy_from_slice(&word.to_be_bytes());
+            }
+        }
+        Ok(Program {
+            data: self.memory[START..self.end].to_vec(),
+            labels: self.labels,
+            lines: self.lines,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(source: &str) -> Vec<u8> {
+        // Past the jump to main.
+        assemble(source).unwrap().data[2..].to_vec()
+    }
+
+    #[test]
+    fn statements_and_labels() {
+        let source =
+            ": main\n  v0 := 5\n  i := sprite\n  sprite v0 v1 3\n: sprite 0xF0 0x90 0xF0\n";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.data,
+            [0x12, 0x02, 0x60, 0x05, 0xA2, 0x08, 0xD0, 0x13, 0xF0, 0x90, 0xF0]
+        );
+        assert_eq!(program.labels["sprite"], 0x208);
+        assert_eq!(program.lines[&0x204], 3);
+    }
+
+    #[test]
+    fn macros_put_in_their_arguments() {
+        let source = ":macro set reg value { reg := value }\n: main set v3 7 set v4 0x10";
+        assert_eq!(code(source), [0x63, 0x07, 0x64, 0x10]);
+    }
+
+    #[test]
+    fn macros_can_use_other_macros() {
+        let source = ":macro one r { r += 1 }\n:macro two r { one r one r }\n: main two v2";
+        assert_eq!(code(source), [0x72, 0x01, 0x72, 0x01]);
+    }
+
+    #[test]
+    fn macros_that_expand_forever_are_errors() {
+        let error = assemble(":macro forever { forever }\n: main forever")
+            .err()
+            .unwrap();
+        assert!(error.contains("expands without end"), "{}", error);
+    }
+
+    #[test]
+    fn calc_applies_operators_right_to_left() {
+        let source = ":calc a { 2 * 3 + 4 }\n:calc b { ( 2 * 3 ) + 4 }\n: main v0 := a v1 := b";
+        assert_eq!(code(source), [0x60, 0x0E, 0x61, 0x0A]);
+    }
+
+    #[test]
+    fn calc_reads_constants_labels_and_here() {
+        let source = ":const base 0x10\n: main\n:calc x { base + HERE - main }\nv0 := x";
+        assert_eq!(code(source), [0x60, 0x10]);
+    }
+
+    #[test]
+    fn calc_shifts_out_of_range_are_errors() {
+        assert_eq!(code(":calc x { 1 << 4 }\n: main v0 := x"), [0x60, 0x10]);
+        for shift in ["1 << 64", "1 >> 64", "1 << -1"] {
+            let source = format!(":calc x {{ {} }}\n: main", shift);
+            let error = assemble(&source).err().unwrap();
+            assert_eq!(error, "line 1: shift out of range in :calc");
+        }
+    }
+
+    #[test]
+    fn next_labels_the_operand_of_the_next_instruction() {
+        let program = assemble(": main\n:next target v0 := 1\n i := target").unwrap();
+        assert_eq!(program.labels["target"], 0x203);
+        assert_eq!(program.data[4..], [0xA2, 0x03]);
+    }
+
+    #[test]
+    fn unpack_loads_an_address_into_v0_and_v1() {
+        let source = ": main :unpack 0xA data\n: data 1 2";
+        assert_eq!(code(source), [0x60, 0xA2, 0x61, 0x06, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn undefined_labels_are_errors() {
+        let error = assemble(": main\n  jump nowhere").err().unwrap();
+        assert_eq!(error, "line 2: undefined label `nowhere`");
+    }
+}
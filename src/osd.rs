@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use sdl3::{pixels::Color, rect::Point, render::Canvas, video::Window};
+
+use chip8::FONT;
+
+const SCALE: f32 = 3.0;
+const GLYPH_WIDTH: i32 = 5;
+const LINE_HEIGHT: i32 = 7;
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+pub struct Osd {
+    pub show_fps: bool,
+    pub show_ips: bool,
+    pub rom_name: String,
+    pub status: Option<String>,
+    // Text placed by scripts, at positions in the display's own units.
+    pub overlay: Vec<(i32, i32, String)>,
+    messages: VecDeque<(String, Instant)>,
+    counter: RateCounter,
+    fps: u32,
+    ips: u32,
+}
+
+impl Osd {
+    pub fn new(rom_name: String) -> Osd {
+        Osd {
+            show_fps: false,
+            show_ips: false,
+            rom_name,
+            status: None,
+            overlay: Vec::new(),
+            messages: VecDeque::new(),
+            counter: RateCounter::new(),
+            fps: 0,
+            ips: 0,
+        }
+    }
+
+    pub fn message(&mut self, text: impl Into<String>) {
+        self.messages.push_back((text.into(), Instant::now()));
+        while self.messages.len() > 4 {
+            self.messages.pop_front();
+        }
+    }
+
+    pub fn count_instruction(&mut self) {
+        self.counter.instructions += 1;
+    }
+
+    pub fn count_frame(&mut self) {
+        self.counter.frames += 1;
+
+        if let Some((fps, ips)) = self.counter.sample() {
+            self.fps = fps;
+            self.ips = ips;
+        }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) {
+        self.messages
+            .retain(|(_, time)| time.elapsed() < MESSAGE_TIME);
+
+        let (scale_x, scale_y) = canvas.scale();
+        canvas.set_scale(SCALE, SCALE).unwrap();
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+
+        for (x, y, text) in &self.overlay {
+            draw_text(canvas, *x, *y, text);
+        }
+
+        let mut y = 1;
+        if self.show_fps || self.show_ips {
+            draw_text(canvas, 1, y, &self.rom_name);
+            y += LINE_HEIGHT;
+        }
+        if self.show_fps {
+            draw_text(canvas, 1, y, &format!("FPS {}", self.fps));
+            y += LINE_HEIGHT;
+        }
+        if self.show_ips {
+            draw_text(canvas, 1, y, &format!("IPS {}", self.ips));
+        }
+
+        let (width, height) = canvas.output_size().unwrap();
+        if let Some(status) = &self.status {
+            let x = (width as f32 / SCALE) as i32 - text_width(status) - 1;
+            draw_text(canvas, x, 1, status);
+        }
+
+        let mut y = (height as f32 / SCALE) as i32 - LINE_HEIGHT;
+        for (text, _) in self.messages.iter().rev() {
+            draw_text(canvas, 1, y, text);
+            y -= LINE_HEIGHT;
+        }
+
+        canvas.set_scale(scale_x, scale_y).unwrap();
+    }
+}
+
+struct RateCounter {
+    since: Instant,
+    frames: u32,
+    instructions: u32,
+}
+
+impl RateCounter {
+    fn new() -> RateCounter {
+        RateCounter {
+            since: Instant::now(),
+            frames: 0,
+            instructions: 0,
+        }
+    }
+
+    fn sample(&mut self) -> Option<(u32, u32)> {
+        let elapsed = self.since.elapsed().as_secs_f32();
+        if elapsed < 1.0 {
+            return None;
+        }
+
+        let rates = (
+            (self.frames as f32 / elapsed).round() as u32,
+            (self.instructions as f32 / elapsed).round() as u32,
+        );
+        *self = RateCounter::new();
+        Some(rates)
+    }
+}
+
+pub fn draw_text(canvas: &mut Canvas<Window>, x: i32, y: i32, text: &str) {
+    let mut pixel = Point::new(0, 0);
+
+    for (n, c) in text.chars().enumerate() {
+        let glyph = glyph(c);
+        let gx = x + n as i32 * GLYPH_WIDTH;
+
+        for (oy, row) in glyph.iter().enumerate() {
+            for ox in 0..4 {
+                if row & (0x80 >> ox) != 0 {
+                    pixel.x = gx + ox;
+                    pixel.y = y + oy as i32;
+                    canvas.draw_point(pixel).unwrap();
+                }
+            }
+        }
+    }
+}
+
+pub fn text_width(text: &str) -> i32 {
+    text.chars().count() as i32 * GLYPH_WIDTH
+}
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+
+    if let Some(digit) = c.to_digit(16) {
+        let start = digit as usize * 5;
+        let mut rows = [0; 5];
+        rows.copy_from_slice(&FONT[start..start + 5]);
+        return rows;
+    }
+
+    match c {
+        'G' => [0xF0, 0x80, 0xB0, 0x90, 0xF0],
+        'H' => [0x90, 0x90, 0xF0, 0x90, 0x90],
+        'I' => [0xE0, 0x40, 0x40, 0x40, 0xE0],
+        'J' => [0x10, 0x10, 0x10, 0x90, 0x60],
+        'K' => [0x90, 0xA0, 0xC0, 0xA0, 0x90],
+        'L' => [0x80, 0x80, 0x80, 0x80, 0xF0],
+        'M' => [0x90, 0xF0, 0xF0, 0x90, 0x90],
+        'N' => [0x90, 0xD0, 0xB0, 0x90, 0x90],
+        'O' => [0x60, 0x90, 0x90, 0x90, 0x60],
+        'P' => [0xE0, 0x90, 0xE0, 0x80, 0x80],
+        'Q' => [0x60, 0x90, 0x90, 0xB0, 0x70],
+        'R' => [0xE0, 0x90, 0xE0, 0xA0, 0x90],
+        'S' => [0x70, 0x80, 0x60, 0x10, 0xE0],
+        'T' => [0xE0, 0x40, 0x40, 0x40, 0x40],
+        'U' => [0x90, 0x90, 0x90, 0x90, 0x60],
+        'V' => [0x90, 0x90, 0x90, 0x60, 0x60],
+        'W' => [0x90, 0x90, 0xF0, 0xF0, 0x90],
+        'X' => [0x90, 0x90, 0x60, 0x90, 0x90],
+        'Y' => [0xA0, 0xA0, 0x40, 0x40, 0x40],
+        'Z' => [0xF0, 0x10, 0x60, 0x80, 0xF0],
+        '%' => [0x90, 0x10, 0x20, 0x40, 0x90],
+        ':' => [0x00, 0x40, 0x00, 0x40, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x40],
+        ',' => [0x00, 0x00, 0x00, 0x40, 0x80],
+        '-' => [0x00, 0x00, 0xE0, 0x00, 0x00],
+        '+' => [0x00, 0x40, 0xE0, 0x40, 0x00],
+        '=' => [0x00, 0xE0, 0x00, 0xE0, 0x00],
+        '/' => [0x10, 0x20, 0x40, 0x80, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0xF0],
+        '!' => [0x40, 0x40, 0x40, 0x00, 0x40],
+        '?' => [0xE0, 0x10, 0x60, 0x00, 0x40],
+        '(' => [0x20, 0x40, 0x40, 0x40, 0x20],
+        ')' => [0x40, 0x20, 0x20, 0x20, 0x40],
+        '[' => [0x60, 0x40, 0x40, 0x40, 0x60],
+        ']' => [0x60, 0x20, 0x20, 0x20, 0x60],
+        '<' => [0x20, 0x40, 0x80, 0x40, 0x20],
+        '>' => [0x80, 0x40, 0x20, 0x40, 0x80],
+        '\'' => [0x40, 0x40, 0x00, 0x00, 0x00],
+        '#' => [0x50, 0xF0, 0x50, 0xF0, 0x50],
+        '*' => [0x00, 0xA0, 0x40, 0xA0, 0x00],
+        _ => [0x00; 5],
+    }
+}
@@ -0,0 +1,161 @@
+use sdl3::{
+    event::Event, mouse::MouseButton, pixels::Color, rect::Rect, render::Canvas, sys, video::Window,
+};
+
+use crate::keymap::{KeyMap, LAYOUT};
+use crate::osd::draw_text;
+
+pub const WIDTH: u32 = 240;
+
+const SCALE: f32 = 2.0;
+const CELL: i32 = 30;
+const GLOW_FRAMES: u8 = 10;
+
+// Mouse presses use this pointer id, fingers use their own.
+const MOUSE: u64 = u64::MAX;
+
+pub struct Panel {
+    pub visible: bool,
+    // Size of the game area to the left of the panel, in window pixels.
+    game_size: (u32, u32),
+    pointers: Vec<(u64, usize)>,
+    glow: [u8; 16],
+}
+
+impl Panel {
+    pub fn new(game_size: (u32, u32)) -> Panel {
+        Panel {
+            visible: false,
+            game_size,
+            pointers: Vec::new(),
+            glow: [0; 16],
+        }
+    }
+
+    pub fn toggle(&mut self, canvas: &mut Canvas<Window>) -> Vec<(usize, bool)> {
+        self.visible = !self.visible;
+        let (width, height) = self.game_size;
+        let width = width + if self.visible { WIDTH } else { 0 };
+        canvas.window_mut().set_size(width, height).unwrap();
+
+        self.pointers
+            .drain(..)
+            .map(|(_, key)| (key, false))
+            .collect()
+    }
+
+    // Top left corner of the keypad, in panel pixels.
+    fn origin(&self) -> (i32, i32) {
+        let (width, height) = self.game_size;
+        let left = (width as f32 / SCALE) as i32;
+        let top = ((height as f32 / SCALE) as i32 - CELL * 4) / 2;
+        (left, top)
+    }
+
+    fn key_at(&self, x: f32, y: f32) -> Option<usize> {
+        let (left, top) = self.origin();
+        let cx = (x / SCALE) as i32 - left;
+        let cy = (y / SCALE) as i32 - top;
+        if cx < 0 || cy < 0 || cx >= CELL * 4 || cy >= CELL * 4 {
+            return None;
+        }
+        Some(LAYOUT[(cy / CELL * 4 + cx / CELL) as usize])
+    }
+
+    // Returns chip8 keys pressed (true) or released (false) by the mouse or touch.
+    pub fn handle(&mut self, event: &Event, canvas: &Canvas<Window>) -> Vec<(usize, bool)> {
+        if !self.visible {
+            return Vec::new();
+        }
+
+        let (width, height) = canvas.window().size();
+        let (pointer, position, pressed) = match *event {
+            // Touches also arrive as synthetic mouse events, which are skipped
+            // so several fingers can hold different keys at once.
+            Event::MouseButtonDown {
+                which,
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } if which != sys::touch::SDL_TOUCH_MOUSEID => (MOUSE, (x, y), true),
+            Event::MouseButtonUp {
+                which,
+                mouse_btn: MouseButton::Left,
+                ..
+            } if which != sys::touch::SDL_TOUCH_MOUSEID => (MOUSE, (0.0, 0.0), false),
+            Event::FingerDown {
+                finger_id, x, y, ..
+            } => (finger_id, (x * width as f32, y * height as f32), true),
+            Event::FingerUp { finger_id, .. } => (finger_id, (0.0, 0.0), false),
+            _ => return Vec::new(),
+        };
+
+        let mut inputs = Vec::new();
+        if let Some(n) = self.pointers.iter().position(|(p, _)| *p == pointer) {
+            let (_, key) = self.pointers.remove(n);
+            inputs.push((key, false));
+        }
+        if pressed {
+            if let Some(key) = self.key_at(position.0, position.1) {
+                self.pointers.push((pointer, key));
+                inputs.push((key, true));
+            }
+        }
+        inputs
+    }
+
+    pub fn draw(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        polled: &[bool; 16],
+        keys: &[bool; 16],
+        keymap: &KeyMap,
+    ) {
+        for (glow, polled) in self.glow.iter_mut().zip(polled) {
+            *glow = if *polled {
+                GLOW_FRAMES
+            } else {
+                glow.saturating_sub(1)
+            };
+        }
+
+        let (scale_x, scale_y) = canvas.scale();
+        canvas.set_scale(SCALE, SCALE).unwrap();
+
+        let (left, top) = self.origin();
+        for (n, key) in LAYOUT.iter().enumerate() {
+            let x = left + (n % 4) as i32 * CELL;
+            let y = top + (n / 4) as i32 * CELL;
+            let cell = Rect::new(x + 1, y + 1, CELL as u32 - 2, CELL as u32 - 2);
+
+            let fill = if keys[*key] {
+                Color::RGB(224, 224, 128)
+            } else {
+                Color::RGB(32, 32, 96)
+            };
+            canvas.set_draw_color(fill);
+            canvas.fill_rect(cell).unwrap();
+
+            if self.glow[*key] > 0 {
+                canvas.set_draw_color(Color::RGB(255, 96, 64));
+                canvas.draw_rect(cell.into()).unwrap();
+            }
+
+            let text = if keys[*key] {
+                Color::RGB(16, 16, 64)
+            } else {
+                Color::RGB(255, 255, 255)
+            };
+            canvas.set_draw_color(text);
+            draw_text(canvas, x + 3, y + 3, &format!("{:X}", key));
+
+            if let Some(host) = keymap.bindings(*key).first() {
+                let name: String = host.name().chars().take(5).collect();
+                draw_text(canvas, x + 3, y + CELL - 8, &name);
+            }
+        }
+
+        canvas.set_scale(scale_x, scale_y).unwrap();
+    }
+}
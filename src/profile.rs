@@ -0,0 +1,282 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{Chip8, Opcode, RawOpCode};
+
+const TOP: usize = 20;
+
+// Counts what a run executes, by address, by kind of instruction and by
+// call stack, and how long it spends waiting.
+pub struct Profiler {
+    instructions: u64,
+    addresses: Vec<u64>,
+    opcodes: BTreeMap<&'static str, u64>,
+    calls: BTreeMap<usize, u64>,
+    // Taken backward jumps by (target, jump address).
+    loops: BTreeMap<(usize, usize), u64>,
+    // Instructions by the subroutine targets leading to them.
+    stacks: HashMap<Vec<usize>, u64>,
+    stack: Vec<usize>,
+    // When the current instruction first failed to finish, and whether it
+    // is waiting for a key rather than the display.
+    blocked: Option<(Instant, bool)>,
+    key_wait: Duration,
+    display_wait: Duration,
+}
+
+#[derive(Serialize)]
+struct Report {
+    instructions: u64,
+    addresses: Vec<AddressCount>,
+    opcodes: BTreeMap<&'static str, u64>,
+    calls: Vec<AddressCount>,
+    loops: Vec<Loop>,
+    key_wait_seconds: f64,
+    display_wait_seconds: f64,
+}
+
+#[derive(Serialize)]
+struct AddressCount {
+    address: usize,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct Loop {
+    start: usize,
+    end: usize,
+    iterations: u64,
+    instructions: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            instructions: 0,
+            addresses: vec![0; 0x10000],
+            opcodes: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            stacks: HashMap::new(),
+            stack: Vec::new(),
+            blocked: None,
+            key_wait: Duration::ZERO,
+            display_wait: Duration::ZERO,
+        }
+    }
+
+    // Called after every attempt to run the instruction at pc; retired is
+    // false while it waits for a key or the display.
+    pub fn record(&mut self, chip8: &Chip8, pc: usize, retired: bool) {
+        let opcode = decode(chip8, pc);
+        if !retired {
+            if self.blocked.is_none() {
+                let key = matches!(opcode, Opcode::WaitKeyDown { .. });
+                self.blocked = Some((Instant::now(), key));
+            }
+            return;
+        }
+        if let Some((since, key)) = self.blocked.take() {
+            let wait = if key {
+                &mut self.key_wait
+            } else {
+                &mut self.display_wait
+            };
+            *wait += since.elapsed();
+        }
+
+        self.instructions += 1;
+        if let Some(count) = self.addresses.get_mut(pc) {
+            *count += 1;
+        }
+        *self.opcodes.entry(opcode.name()).or_default() += 1;
+        match opcode {
+            Opcode::Subroutine { n0, n1, n2 } => {
+                *self
+                    .calls
+                    .entry(Chip8::to_decimal(n0, n1, n2) as usize)
+                    .or_default() += 1;
+            }
+            Opcode::Jump { n0, n1, n2 } if Chip8::to_decimal(n0, n1, n2) as usize <= pc => {
+                let target = Chip8::to_decimal(n0, n1, n2) as usize;
+                *self.loops.entry((target, pc)).or_default() += 1;
+            }
+            _ => {}
+        }
+
+        // Charged to the stack the instruction ran in, before any call or
+        // return it made.
+        match self.stacks.get_mut(&self.stack[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+        self.sync_stack(chip8);
+    }
+
+    // Rebuilds the subroutine targets from the call instructions on the
+    // machine's stack whenever its depth changes.
+    fn sync_stack(&mut self, chip8: &Chip8) {
+        let depth = chip8.sub_pointer.min(chip8.stack.len());
+        if self.stack.len() == depth {
+            return;
+        }
+        self.stack = chip8.stack[..depth]
+            .iter()
+            .map(|call| match decode(chip8, *call) {
+                Opcode::Subroutine { n0, n1, n2 } => Chip8::to_decimal(n0, n1, n2) as usize,
+                _ => *call,
+            })
+            .collect();
+    }
+
+    fn report(&self) -> Report {
+        let mut addresses: Vec<AddressCount> = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| AddressCount {
+                address,
+                count: *count,
+            })
+            .collect();
+        addresses.sort_by(|a, b| b.count.cmp(&a.count).then(a.address.cmp(&b.address)));
+
+        let mut calls: Vec<AddressCount> = self
+            .calls
+            .iter()
+            .map(|(address, count)| AddressCount {
+                address: *address,
+                count: *count,
+            })
+            .collect();
+        calls.sort_by_key(|entry| Reverse(entry.count));
+
+        let mut loops: Vec<Loop> = self
+            .loops
+            .iter()
+            .map(|((start, end), iterations)| Loop {
+                start: *start,
+                end: *end,
+                iterations: *iterations,
+                instructions: self.addresses[*start..=*end].iter().sum(),
+            })
+            .collect();
+        loops.sort_by_key(|entry| Reverse(entry.instructions));
+
+        Report {
+            instructions: self.instructions,
+            addresses,
+            opcodes: self.opcodes.clone(),
+            calls,
+            loops,
+            key_wait_seconds: self.key_wait.as_secs_f64(),
+            display_wait_seconds: self.display_wait.as_secs_f64(),
+        }
+    }
+
+    // The report printed at exit, hottest first.
+    pub fn print(&self, chip8: &Chip8) {
+        let report = self.report();
+        let total = report.instructions.max(1) as f64;
+        let percent = |count: u64| count as f64 * 100.0 / total;
+
+        println!("Profile: {} instructions", report.instructions);
+        println!("Hottest addresses:");
+        let symbols = &chip8.symbols;
+        for entry in report.addresses.iter().take(TOP) {
+            let raw = fetch(chip8, entry.address);
+            println!(
+                "  0x{:03X}  {:<20} {}  {:<20} {:>10} {:>6.2}%",
+                entry.address,
+                symbols.name(entry.address).unwrap_or_default(),
+                raw.as_string(),
+                symbols.mnemonic(&Chip8::decode(raw)),
+                entry.count,
+                percent(entry.count)
+            );
+        }
+
+        let mut opcodes: Vec<(&&str, &u64)> = report.opcodes.iter().collect();
+        opcodes.sort_by_key(|(_, count)| Reverse(**count));
+        println!("Instructions by kind:");
+        for (name, count) in opcodes {
+            println!("  {:<16} {:>10} {:>6.2}%", name, count, percent(*count));
+        }
+
+        if !report.calls.is_empty() {
+            println!("Subroutine calls:");
+        }
+        for entry in report.calls.iter().take(TOP) {
+            println!(
+                "  0x{:03X}  {:<20} {:>10}",
+                entry.address,
+                symbols.name(entry.address).unwrap_or_default(),
+                entry.count
+            );
+        }
+
+        if !report.loops.is_empty() {
+            println!("Hot loops:");
+        }
+        for entry in report.loops.iter().take(TOP) {
+            println!(
+                "  0x{:03X}-0x{:03X}  {:>8} iterations {:>10} instructions {:>6.2}%",
+                entry.start,
+                entry.end,
+                entry.iterations,
+                entry.instructions,
+                percent(entry.instructions)
+            );
+        }
+
+        println!("Waiting for a key:     {:.2}s", report.key_wait_seconds);
+        println!("Waiting for a frame:   {:.2}s", report.display_wait_seconds);
+    }
+
+    // JSON when the file name ends in .json, otherwise collapsed stacks
+    // ("main;0x2A4;draw_player 1234" per line, named where symbols are
+    // known) as flamegraph tools read them.
+    pub fn save(&self, chip8: &Chip8, path: &Path) -> Result<(), String> {
+        let text = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::to_string_pretty(&self.report()).map_err(|e| e.to_string())?
+        } else {
+            let mut lines: Vec<String> = self
+                .stacks
+                .iter()
+                .map(|(stack, count)| {
+                    let mut frames = vec!["main".to_string()];
+                    frames.extend(stack.iter().map(|target| {
+                        chip8
+                            .symbols
+                            .name(*target)
+                            .unwrap_or_else(|| format!("0x{:03X}", target))
+                    }));
+                    format!("{} {}", frames.join(";"), count)
+                })
+                .collect();
+            lines.sort();
+            lines.join("\n") + "\n"
+        };
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+fn fetch(chip8: &Chip8, address: usize) -> RawOpCode {
+    let byte = |n| chip8.memory.get(n).copied().unwrap_or(0);
+    RawOpCode {
+        v0: byte(address),
+        v1: byte(address + 1),
+    }
+}
+
+fn decode(chip8: &Chip8, address: usize) -> Opcode {
+    Chip8::decode(fetch(chip8, address))
+}
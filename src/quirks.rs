@@ -0,0 +1,240 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::font;
+
+// In order of age. SCHIP and XO-CHIP each have all the instructions of
+// the platforms before them; the ETI-660 and hi-res CHIP-8 only move
+// things around in memory.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Chip8,
+    Eti660,
+    Hires,
+    Schip,
+    Xochip,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::Eti660 => "ETI-660",
+            Platform::Hires => "hi-res CHIP-8",
+            Platform::Schip => "SCHIP",
+            Platform::Xochip => "XO-CHIP",
+        }
+    }
+
+    // Whether the core has every instruction the platform adds. SCHIP and
+    // XO-CHIP programs fail at the first one it is missing.
+    pub fn runs(&self) -> bool {
+        !matches!(self, Platform::Schip | Platform::Xochip)
+    }
+
+    pub fn layout(&self) -> Layout {
+        let chip8 = Layout {
+            start: 0x200,
+            font: 0x50,
+            memory: 0x1000,
+            hires: false,
+        };
+        match self {
+            Platform::Eti660 => Layout {
+                start: 0x600,
+                ..chip8
+            },
+            Platform::Hires => Layout {
+                hires: true,
+                ..chip8
+            },
+            Platform::Xochip => Layout {
+                memory: 0x10000,
+                ..chip8
+            },
+            Platform::Chip8 | Platform::Schip => chip8,
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 | Platform::Eti660 | Platform::Hires => Quirks {
+                vf_reset: true,
+                memory_increment: true,
+                display_wait: true,
+                clipping: false,
+                shift_vy: true,
+                jump_vx: false,
+            },
+            Platform::Schip => Quirks {
+                vf_reset: false,
+                memory_increment: false,
+                display_wait: false,
+                clipping: true,
+                shift_vy: false,
+                jump_vx: true,
+            },
+            Platform::Xochip => Quirks {
+                vf_reset: false,
+                memory_increment: true,
+                display_wait: false,
+                clipping: false,
+                shift_vy: true,
+                jump_vx: false,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+pub struct Quirks {
+    // 8xy1, 8xy2 and 8xy3 clear VF.
+    pub vf_reset: bool,
+    // Fx55 and Fx65 leave I pointing past the last register.
+    pub memory_increment: bool,
+    // 00E0 and Dxyn wait for the next frame.
+    pub display_wait: bool,
+    // Sprites are cut off at the screen edge instead of wrapping around.
+    pub clipping: bool,
+    // 8xy6 and 8xyE shift VY into VX instead of shifting VX in place.
+    pub shift_vy: bool,
+    // Bnnn jumps to nnn + VX instead of nnn + V0.
+    pub jump_vx: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Platform::Chip8.quirks()
+    }
+}
+
+// Partial quirk settings layered on top of a platform's defaults.
+#[derive(Clone, Copy, Default, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuirkOverrides {
+    pub vf_reset: Option<bool>,
+    pub memory_increment: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub clipping: Option<bool>,
+    pub shift_vy: Option<bool>,
+    pub jump_vx: Option<bool>,
+}
+
+impl QuirkOverrides {
+    // Layers other on top of self.
+    pub fn merge(&mut self, other: &QuirkOverrides) {
+        let fields = [
+            (other.vf_reset, &mut self.vf_reset),
+            (other.memory_increment, &mut self.memory_increment),
+            (other.display_wait, &mut self.display_wait),
+            (other.clipping, &mut self.clipping),
+            (other.shift_vy, &mut self.shift_vy),
+            (other.jump_vx, &mut self.jump_vx),
+        ];
+        for (value, quirk) in fields {
+            if value.is_some() {
+                *quirk = value;
+            }
+        }
+    }
+
+    pub fn apply(&self, quirks: &mut Quirks) {
+        let fields = [
+            (self.vf_reset, &mut quirks.vf_reset),
+            (self.memory_increment, &mut quirks.memory_increment),
+            (self.display_wait, &mut quirks.display_wait),
+            (self.clipping, &mut quirks.clipping),
+            (self.shift_vy, &mut quirks.shift_vy),
+            (self.jump_vx, &mut quirks.jump_vx),
+        ];
+        for (value, quirk) in fields {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+    }
+}
+
+// Where programs and the font go, how much memory there is, and whether
+// the screen is 64x64 instead of 64x32.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Layout {
+    pub start: usize,
+    pub font: usize,
+    pub memory: usize,
+    pub hires: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Platform::Chip8.layout()
+    }
+}
+
+impl Layout {
+    // Hi-res programs begin by jumping to the VIP interpreter's 64x64
+    // patch at 0x260; the emulator has that built in, so they start
+    // straight after it instead. The machine and the linter both start
+    // from here.
+    pub fn entry_point(&self, rom: &[u8]) -> usize {
+        if self.hires && hires_patch(rom, self.start) {
+            0x2C0
+        } else {
+            self.start
+        }
+    }
+}
+
+// Whether a ROM loaded at start opens with the jump into the hi-res patch.
+pub fn hires_patch(rom: &[u8], start: usize) -> bool {
+    start == 0x200 && rom.starts_with(&[0x12, 0x60])
+}
+
+#[derive(Clone, Copy, Default, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutOverrides {
+    pub start: Option<usize>,
+    pub font: Option<usize>,
+    pub memory: Option<usize>,
+    pub hires: Option<bool>,
+}
+
+impl LayoutOverrides {
+    pub fn merge(&mut self, other: &LayoutOverrides) {
+        self.start = other.start.or(self.start);
+        self.font = other.font.or(self.font);
+        self.memory = other.memory.or(self.memory);
+        self.hires = other.hires.or(self.hires);
+    }
+
+    pub fn apply(&self, layout: &mut Layout) {
+        layout.start = self.start.unwrap_or(layout.start);
+        layout.font = self.font.unwrap_or(layout.font);
+        layout.memory = self.memory.unwrap_or(layout.memory);
+        layout.hires = self.hires.unwrap_or(layout.hires);
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(memory) = self.memory {
+            if !(0x1000..=0x10000).contains(&memory) {
+                return Err(format!(
+                    "memory must be from 0x1000 to 0x10000, not {:#X}",
+                    memory
+                ));
+            }
+        }
+        let memory = self.memory.unwrap_or(0x10000);
+        if let Some(start) = self.start {
+            if start >= memory {
+                return Err(format!("start {:#X} is past the end of memory", start));
+            }
+        }
+        if let Some(font) = self.font {
+            if font + font::SMALL_SIZE + font::BIG_SIZE > memory {
+                return Err(format!("font at {:#X} does not fit in memory", font));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,565 @@
+// An executable specification: what each instruction does to the machine,
+// written plainly from the instruction set rather than from the emulator.
+// The properties below run both on random states and instructions under
+// every quirk profile and compare the results.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use proptest::prelude::*;
+
+use crate::keypad::WaitMode;
+use crate::quirks::{Layout, Platform, Quirks};
+use crate::{Chip8, STACK};
+
+// The small glyphs come first, 5 bytes each; the big ones follow, 10 each.
+const SMALL_GLYPHS: usize = 16 * 5;
+
+#[derive(Clone, PartialEq)]
+pub struct State {
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: usize,
+    pub pc: usize,
+    // Return addresses, innermost last.
+    pub stack: Vec<usize>,
+    pub delay: u8,
+    pub sound: u8,
+    // Rows of pixels from the top; only 32 are used outside hi-res.
+    pub screen: [[bool; 64]; 64],
+}
+
+// Memory and the screen are too big to print in full.
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("State")
+            .field("memory", &format_args!("{} bytes", self.memory.len()))
+            .field("v", &format_args!("{:02X?}", self.v))
+            .field("i", &format_args!("{:#X}", self.i))
+            .field("pc", &format_args!("{:#X}", self.pc))
+            .field("stack", &format_args!("{:X?}", self.stack))
+            .field("delay", &self.delay)
+            .field("sound", &self.sound)
+            .finish_non_exhaustive()
+    }
+}
+
+// What an instruction depends on besides the state.
+#[derive(Clone, Copy, Debug)]
+pub struct Machine {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub hires: bool,
+    pub font: usize,
+    // Keys held down, pressed since the last frame.
+    pub keys: u16,
+    // Fx0A finishes when a key goes down rather than when it comes up.
+    pub press: bool,
+    // The instruction runs at the start of a frame: the display may be
+    // used and the timers count down afterwards.
+    pub frame: bool,
+}
+
+// The state after the instruction at PC and whether it ran, or None if
+// the machine cannot run it. Cxnn uses `random` as the random byte.
+pub fn step(before: &State, machine: &Machine, random: u8) -> Option<(State, bool)> {
+    let mut s = before.clone();
+    let len = s.memory.len();
+    let word = u16::from_be_bytes([s.memory[s.pc], s.memory[(s.pc + 1) % len]]);
+    let x = (word >> 8 & 0xF) as usize;
+    let y = (word >> 4 & 0xF) as usize;
+    let n = (word & 0xF) as usize;
+    let nn = (word & 0xFF) as u8;
+    let nnn = (word & 0xFFF) as usize;
+    let quirks = machine.quirks;
+    let pc = s.pc;
+    let next = (pc + 2) % len;
+    let skip = |condition: bool| if condition { (pc + 4) % len } else { next };
+    // Display instructions wait for a frame if the quirk says so.
+    let waits = quirks.display_wait && !machine.frame;
+    let mut ran = true;
+
+    s.pc = match (word >> 12, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) | (0x0, 0x2, 0x3, 0x0) if waits => {
+            ran = false;
+            pc
+        }
+        (0x0, 0x0, 0xE, 0x0) | (0x0, 0x2, 0x3, 0x0) => {
+            s.screen = [[false; 64]; 64];
+            next
+        }
+        (0x0, 0x0, 0xE, 0xE) => s.stack.pop()?,
+        (0x1, ..) => nnn,
+        (0x2, ..) => {
+            if s.stack.len() == STACK {
+                return None;
+            }
+            s.stack.push(next);
+            nnn
+        }
+        (0x3, ..) => skip(s.v[x] == nn),
+        (0x4, ..) => skip(s.v[x] != nn),
+        (0x5, _, _, 0x0) => skip(s.v[x] == s.v[y]),
+        (0x6, ..) => {
+            s.v[x] = nn;
+            next
+        }
+        (0x7, ..) => {
+            s.v[x] = s.v[x].wrapping_add(nn);
+            next
+        }
+        (0x8, _, _, 0x0) => {
+            s.v[x] = s.v[y];
+            next
+        }
+        (0x8, _, _, 0x1..=0x3) => {
+            s.v[x] = match n {
+                0x1 => s.v[x] | s.v[y],
+                0x2 => s.v[x] & s.v[y],
+                _ => s.v[x] ^ s.v[y],
+            };
+            if quirks.vf_reset {
+                s.v[0xF] = 0;
+            }
+            next
+        }
+        (0x8, _, _, 0x4) => {
+            let (sum, carry) = s.v[x].overflowing_add(s.v[y]);
+            set_with_flag(&mut s.v, x, sum, carry);
+            next
+        }
+        (0x8, _, _, 0x5) => {
+            let (difference, borrow) = s.v[x].overflowing_sub(s.v[y]);
+            set_with_flag(&mut s.v, x, difference, !borrow);
+            next
+        }
+        (0x8, _, _, 0x7) => {
+            let (difference, borrow) = s.v[y].overflowing_sub(s.v[x]);
+            set_with_flag(&mut s.v, x, difference, !borrow);
+            next
+        }
+        (0x8, _, _, 0x6 | 0xE) => {
+            let source = if quirks.shift_vy { s.v[y] } else { s.v[x] };
+            if n == 0x6 {
+                set_with_flag(&mut s.v, x, source >> 1, source & 0x01 != 0);
+            } else {
+                set_with_flag(&mut s.v, x, source << 1, source & 0x80 != 0);
+            }
+            next
+        }
+        (0x9, _, _, 0x0) => skip(s.v[x] != s.v[y]),
+        (0xA, ..) => {
+            s.i = nnn;
+            next
+        }
+        (0xB, ..) => {
+            let offset = if quirks.jump_vx { s.v[x] } else { s.v[0] };
+            (nnn + offset as usize) % len
+        }
+        (0xC, ..) => {
+            s.v[x] = random & nn;
+            next
+        }
+        (0xD, ..) if waits => {
+            ran = false;
+            pc
+        }
+        (0xD, ..) => {
+            draw(&mut s, machine, x, y, n);
+            next
+        }
+        (0xE, _, 0x9, 0xE) => skip(machine.keys & 1 << (s.v[x] & 0xF) != 0),
+        (0xE, _, 0xA, 0x1) => skip(machine.keys & 1 << (s.v[x] & 0xF) == 0),
+        (0xF, _, 0x0, 0x7) => {
+            s.v[x] = s.delay;
+            next
+        }
+        // With the keys just pressed and still held, only a press finishes
+        // the wait; otherwise it goes on until one is released.
+        (0xF, _, 0x0, 0xA) if machine.press && machine.keys != 0 => {
+            s.v[x] = machine.keys.trailing_zeros() as u8;
+            next
+        }
+        (0xF, _, 0x0, 0xA) => {
+            ran = false;
+            pc
+        }
+        (0xF, _, 0x1, 0x5) => {
+            s.delay = s.v[x];
+            next
+        }
+        (0xF, _, 0x1, 0x8) => {
+            s.sound = s.v[x];
+            next
+        }
+        (0xF, _, 0x1, 0xE) => {
+            s.i = (s.i + s.v[x] as usize) % 0x10000;
+            next
+        }
+        (0xF, _, 0x2, 0x9) => {
+            s.i = machine.font + (s.v[x] & 0xF) as usize * 5;
+            next
+        }
+        (0xF, _, 0x3, 0x0) => {
+            s.i = machine.font + SMALL_GLYPHS + (s.v[x] & 0xF) as usize * 10;
+            next
+        }
+        (0xF, _, 0x3, 0x3) => {
+            let value = s.v[x];
+            for (offset, digit) in [value / 100, value / 10 % 10, value % 10]
+                .iter()
+                .enumerate()
+            {
+                s.memory[(s.i + offset) % len] = *digit;
+            }
+            next
+        }
+        (0xF, _, 0x5, 0x5) => {
+            for r in 0..=x {
+                s.memory[(s.i + r) % len] = s.v[r];
+            }
+            if quirks.memory_increment {
+                s.i = (s.i + x + 1) % 0x10000;
+            }
+            next
+        }
+        (0xF, _, 0x6, 0x5) => {
+            for r in 0..=x {
+                s.v[r] = s.memory[(s.i + r) % len];
+            }
+            if quirks.memory_increment {
+                s.i = (s.i + x + 1) % 0x10000;
+            }
+            next
+        }
+        _ => return None,
+    };
+
+    if machine.frame {
+        s.delay = s.delay.saturating_sub(1);
+        s.sound = s.sound.saturating_sub(1);
+    }
+    Some((s, ran))
+}
+
+// The flag is written last, so it wins when X is F.
+fn set_with_flag(v: &mut [u8; 16], x: usize, value: u8, flag: bool) {
+    v[x] = value;
+    v[0xF] = flag as u8;
+}
+
+// XORs an 8-pixel-wide sprite of n rows from I onto the screen. With n
+// at 0, SCHIP and XO-CHIP draw a 16x16 one, two bytes a row, and the
+// others draw nothing. The position wraps; pixels past the edge are cut
+// off or wrap as well.
+fn draw(s: &mut State, machine: &Machine, x: usize, y: usize, n: usize) {
+    let height = if machine.hires { 64 } else { 32 };
+    let left = s.v[x] as usize % 64;
+    let top = s.v[y] as usize % height;
+    let (rows, width) = match machine.platform {
+        Platform::Schip | Platform::Xochip if n == 0 => (16, 16),
+        _ => (n, 8),
+    };
+    let mut collision = false;
+    for row in 0..rows {
+        for column in 0..width {
+            let address = s.i + row * width / 8 + column / 8;
+            let byte = s.memory[address % s.memory.len()];
+            if byte & (0x80 >> (column % 8)) == 0 {
+                continue;
+            }
+            let (px, py) = (left + column, top + row);
+            if machine.quirks.clipping && (px >= 64 || py >= height) {
+                continue;
+            }
+            let pixel = &mut s.screen[py % height][px % 64];
+            collision |= *pixel;
+            *pixel = !*pixel;
+        }
+    }
+    s.v[0xF] = collision as u8;
+}
+
+// The emulator keeps the address of each call on its stack rather than
+// the address to return to.
+fn emulator(state: &State, machine: &Machine) -> Chip8 {
+    let len = state.memory.len();
+    let mut chip8 = Chip8::new();
+    chip8.platform = machine.platform;
+    chip8.quirks = machine.quirks;
+    chip8.layout = Layout {
+        start: 0x200,
+        font: machine.font,
+        memory: len,
+        hires: machine.hires,
+    };
+    chip8.memory = state.memory.clone();
+    chip8.registry = state.v;
+    chip8.i = state.i;
+    chip8.program_counter = state.pc;
+    for (n, address) in state.stack.iter().enumerate() {
+        chip8.stack[n] = (address + len - 2) % len;
+    }
+    chip8.sub_pointer = state.stack.len();
+    chip8.delay_timer = state.delay;
+    chip8.sound_timer = state.sound;
+    for (y, row) in state.screen.iter().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            chip8.pixel_map[x][y] = *pixel as u8;
+        }
+    }
+    for key in 0..16 {
+        chip8.keypad.set(key, machine.keys & 1 << key != 0);
+    }
+    chip8.wait_mode = if machine.press {
+        WaitMode::Press
+    } else {
+        WaitMode::Release
+    };
+    chip8
+}
+
+fn state_of(chip8: &Chip8) -> State {
+    let len = chip8.memory.len();
+    let mut screen = [[false; 64]; 64];
+    for (y, row) in screen.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = chip8.pixel_map[x][y] == 1;
+        }
+    }
+    State {
+        memory: chip8.memory.clone(),
+        v: chip8.registry,
+        i: chip8.i,
+        pc: chip8.program_counter,
+        stack: chip8.stack[..chip8.sub_pointer]
+            .iter()
+            .map(|address| (address + 2) % len)
+            .collect(),
+        delay: chip8.delay_timer,
+        sound: chip8.sound_timer,
+        screen,
+    }
+}
+
+// The parts of two states that differ, briefly.
+fn differences(expected: &State, actual: &State) -> Vec<String> {
+    let mut differences = Vec::new();
+    for n in 0..16 {
+        if expected.v[n] != actual.v[n] {
+            differences.push(format!(
+                "V{:X} {:#04X} not {:#04X}",
+                n, actual.v[n], expected.v[n]
+            ));
+        }
+    }
+    let fields = [
+        ("I", expected.i, actual.i),
+        ("PC", expected.pc, actual.pc),
+        ("DT", expected.delay as usize, actual.delay as usize),
+        ("ST", expected.sound as usize, actual.sound as usize),
+    ];
+    for (name, expected, actual) in fields {
+        if expected != actual {
+            differences.push(format!("{} {:#X} not {:#X}", name, actual, expected));
+        }
+    }
+    if expected.stack != actual.stack {
+        differences.push(format!(
+            "stack {:X?} not {:X?}",
+            actual.stack, expected.stack
+        ));
+    }
+    let memory = (0..expected.memory.len()).filter(|n| expected.memory[*n] != actual.memory[*n]);
+    for address in memory.take(4) {
+        differences.push(format!(
+            "[{:#X}] {:#04X} not {:#04X}",
+            address, actual.memory[address], expected.memory[address]
+        ));
+    }
+    let pixels = (0..64 * 64)
+        .filter(|n| expected.screen[n / 64][n % 64] != actual.screen[n / 64][n % 64])
+        .count();
+    if pixels > 0 {
+        differences.push(format!("{} pixels", pixels));
+    }
+    differences
+}
+
+// Each instruction's fixed bits and the bits left to chance, so every one
+// comes up often; any word at all covers the rest.
+const FORMS: [(u16, u16); 37] = [
+    (0x00E0, 0x000),
+    (0x00EE, 0x000),
+    (0x0230, 0x000),
+    (0x1000, 0xFFF),
+    (0x2000, 0xFFF),
+    (0x3000, 0xFFF),
+    (0x4000, 0xFFF),
+    (0x5000, 0xFF0),
+    (0x6000, 0xFFF),
+    (0x7000, 0xFFF),
+    (0x8000, 0xFF0),
+    (0x8001, 0xFF0),
+    (0x8002, 0xFF0),
+    (0x8003, 0xFF0),
+    (0x8004, 0xFF0),
+    (0x8005, 0xFF0),
+    (0x8006, 0xFF0),
+    (0x8007, 0xFF0),
+    (0x800E, 0xFF0),
+    (0x9000, 0xFF0),
+    (0xA000, 0xFFF),
+    (0xB000, 0xFFF),
+    (0xC000, 0xFFF),
+    (0xD000, 0xFFF),
+    (0xD000, 0xFF0),
+    (0xE09E, 0xF00),
+    (0xE0A1, 0xF00),
+    (0xF007, 0xF00),
+    (0xF00A, 0xF00),
+    (0xF015, 0xF00),
+    (0xF018, 0xF00),
+    (0xF01E, 0xF00),
+    (0xF029, 0xF00),
+    (0xF030, 0xF00),
+    (0xF033, 0xF00),
+    (0xF055, 0xF00),
+    (0xF065, 0xF00),
+];
+
+fn opcode() -> impl Strategy<Value = u16> {
+    prop_oneof![
+        4 => (prop::sample::select(&FORMS[..]), any::<u16>())
+            .prop_map(|((bits, free), random)| bits | random & free),
+        1 => any::<u16>(),
+    ]
+}
+
+// Small values too, so registers often match each other and constants.
+fn registers() -> impl Strategy<Value = [u8; 16]> {
+    prop::array::uniform16(prop_oneof![any::<u8>(), 0..4u8])
+}
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    any::<[bool; 6]>().prop_map(
+        |[vf_reset, memory_increment, display_wait, clipping, shift_vy, jump_vx]| Quirks {
+            vf_reset,
+            memory_increment,
+            display_wait,
+            clipping,
+            shift_vy,
+            jump_vx,
+        },
+    )
+}
+
+// 4 KiB or 64 KiB of memory, with random bytes repeated through it.
+fn state_strategy() -> impl Strategy<Value = State> {
+    (prop_oneof![Just(0x1000), Just(0x10000)], any::<u16>())
+        .prop_flat_map(|(len, pc)| {
+            (
+                prop::collection::vec(any::<u8>(), 0x1000),
+                registers(),
+                0..=0xFFFFusize,
+                Just(pc as usize % len),
+                prop::collection::vec(0..len, 0..=STACK),
+                any::<u8>(),
+                any::<u8>(),
+                prop::collection::vec(any::<u64>(), 64),
+                Just(len),
+            )
+        })
+        .prop_map(|(memory, v, i, pc, stack, delay, sound, rows, len)| {
+            let mut screen = [[false; 64]; 64];
+            for (row, bits) in screen.iter_mut().zip(rows) {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = bits & 1 << x != 0;
+                }
+            }
+            State {
+                memory: memory.repeat(len / 0x1000),
+                v,
+                i,
+                pc,
+                stack,
+                delay,
+                sound,
+                screen,
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn instructions_match_the_reference(
+        state in state_strategy(),
+        word in opcode(),
+        random_platform in prop::sample::select(Platform::value_variants()),
+        random_quirks in quirks(),
+        hires in any::<bool>(),
+        font in prop_oneof![Just(0x50), 0..0x200usize],
+        keys in prop_oneof![Just(0), any::<u16>()],
+        press in any::<bool>(),
+        frame in any::<bool>(),
+    ) {
+        let mut before = state;
+        let len = before.memory.len();
+        let [high, low] = word.to_be_bytes();
+        before.memory[before.pc] = high;
+        before.memory[(before.pc + 1) % len] = low;
+
+        let profiles = Platform::value_variants().iter().map(|p| (*p, p.quirks()));
+        for (platform, quirks) in profiles.chain([(random_platform, random_quirks)]) {
+            let machine = Machine { platform, quirks, hires, font, keys, press, frame };
+            let mut chip8 = emulator(&before, &machine);
+            let result = chip8.execute(frame);
+            // Whatever byte the emulator drew, Cxnn must give it ANDed with nn.
+            let random = chip8.registry[(word >> 8 & 0xF) as usize];
+            let actual = state_of(&chip8);
+            match (step(&before, &machine, random), result) {
+                (Some((expected, ran)), Ok(retired)) => {
+                    let differences = differences(&expected, &actual);
+                    prop_assert!(
+                        differences.is_empty(),
+                        "{:04X} at {:#X} with {:?}: {}",
+                        word, before.pc, machine, differences.join(", ")
+                    );
+                    prop_assert_eq!(retired, ran, "{:04X} with {:?}", word, machine);
+                }
+                (None, Err(_)) => {
+                    let differences = differences(&before, &actual);
+                    prop_assert!(
+                        differences.is_empty(),
+                        "{:04X} failed but changed {}",
+                        word, differences.join(", ")
+                    );
+                }
+                (expected, result) => prop_assert!(
+                    false,
+                    "{:04X} with {:?}: the reference {}, the emulator gave {:?}",
+                    word,
+                    machine,
+                    if expected.is_some() { "runs it" } else { "cannot run it" },
+                    result
+                ),
+            }
+        }
+    }
+}
+
+// The reference takes any random byte, so check the emulator draws them all.
+#[test]
+fn random_bytes_cover_every_value() {
+    let mut chip8 = Chip8::new();
+    chip8.power_on().unwrap();
+    chip8.memory[0x200..0x204].copy_from_slice(&[0xC0, 0xFF, 0x12, 0x00]);
+    let mut seen = [false; 256];
+    for _ in 0..8192 {
+        chip8.execute(false).unwrap();
+        seen[chip8.registry[0] as usize] = true;
+        chip8.execute(false).unwrap();
+    }
+    let missing: Vec<usize> = (0..256).filter(|n| !seen[*n]).collect();
+    assert!(missing.is_empty(), "never drew {:X?}", missing);
+}
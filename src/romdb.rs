@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::quirks::{LayoutOverrides, Platform, QuirkOverrides, Quirks};
+
+const BUILTIN: &str = include_str!("romdb.toml");
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomInfo {
+    pub sha1: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub year: Option<u32>,
+    pub platform: Option<Platform>,
+    // Instructions per frame the ROM was written for.
+    pub ipf: Option<u32>,
+    #[serde(default)]
+    pub quirks: QuirkOverrides,
+    #[serde(default)]
+    pub layout: LayoutOverrides,
+    // Background and foreground as "#RRGGBB".
+    pub palette: Option<[String; 2]>,
+    // What each chip8 key does, keyed by its hex digit.
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+}
+
+impl RomInfo {
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.platform.unwrap_or(Platform::Chip8).quirks();
+        self.quirks.apply(&mut quirks);
+        quirks
+    }
+
+    pub fn palette(&self) -> Option<[(u8, u8, u8); 2]> {
+        let [background, foreground] = self.palette.as_ref()?;
+        Some([parse_color(background)?, parse_color(foreground)?])
+    }
+
+    pub fn display_title(&self) -> String {
+        match (&self.author, self.year) {
+            (Some(author), Some(year)) => format!("{} [{}, {}]", self.title, author, year),
+            (Some(author), None) => format!("{} [{}]", self.title, author),
+            (None, Some(year)) => format!("{} [{}]", self.title, year),
+            (None, None) => self.title.clone(),
+        }
+    }
+
+    pub fn key_hints(&self) -> String {
+        self.keys
+            .iter()
+            .map(|(key, action)| format!("{}: {}", key, action))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+}
+
+#[derive(Deserialize)]
+struct RomDbFile {
+    #[serde(default)]
+    rom: Vec<RomInfo>,
+}
+
+pub struct RomDb {
+    roms: BTreeMap<String, RomInfo>,
+}
+
+impl RomDb {
+    pub fn builtin() -> RomDb {
+        let mut db = RomDb {
+            roms: BTreeMap::new(),
+        };
+        db.extend(BUILTIN).unwrap();
+        db
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.extend(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn extend(&mut self, text: &str) -> Result<(), String> {
+        let file: RomDbFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        for rom in file.rom {
+            rom.layout
+                .check()
+                .map_err(|e| format!("{}: layout: {}", rom.title, e))?;
+            if rom.palette.is_some() && rom.palette().is_none() {
+                return Err(format!(
+                    "{}: palette colors must look like #RRGGBB",
+                    rom.title
+                ));
+            }
+            self.roms.insert(rom.sha1.to_lowercase(), rom);
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, data: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&sha1(data))
+    }
+}
+
+pub fn sha1(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+pub fn parse_color(text: &str) -> Option<(u8, u8, u8)> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
@@ -0,0 +1,431 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+
+use crate::Chip8;
+use crate::MemoryAccess;
+
+// Hooks a script may define.
+const HOOKS: [&str; 5] = [
+    "on_start",
+    "on_frame",
+    "on_instruction",
+    "on_write",
+    "on_key",
+];
+
+// A runaway hook is stopped after this many steps rather than hanging the
+// emulator.
+const MAX_OPERATIONS: u64 = 1_000_000;
+// Nor may one use up the emulator's memory or stack.
+const MAX_STRING_SIZE: usize = 1 << 20;
+const MAX_ARRAY_SIZE: usize = 1 << 16;
+const MAX_MAP_SIZE: usize = 1 << 16;
+const MAX_CALL_LEVELS: usize = 64;
+const MAX_EXPR_DEPTH: usize = 64;
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+// The parts of the machine scripts see. They are moved in for the length
+// of a hook and back out after it.
+#[derive(Default)]
+struct Machine {
+    registry: [u8; 16],
+    memory: Vec<u8>,
+    i: usize,
+    program_counter: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    keys: [bool; 16],
+    key_changes: Vec<(usize, bool)>,
+    screenshots: Vec<PathBuf>,
+    texts: BTreeMap<(i64, i64), String>,
+    frame: i64,
+    exit: Option<i32>,
+    // The only directory scripts may touch, if one was granted.
+    dir: Option<PathBuf>,
+}
+
+impl Machine {
+    fn register(&self, x: i64) -> Fallible<usize> {
+        match usize::try_from(x) {
+            Ok(x) if x < 16 => Ok(x),
+            _ => Err(format!("no register V{}", x).into()),
+        }
+    }
+
+    fn address(&self, address: i64) -> Fallible<usize> {
+        match usize::try_from(address) {
+            Ok(address) if address < self.memory.len() => Ok(address),
+            _ => Err(format!("address {:#X} is outside memory", address).into()),
+        }
+    }
+
+    fn key(&self, key: i64) -> Fallible<usize> {
+        match usize::try_from(key) {
+            Ok(key) if key < 16 => Ok(key),
+            _ => Err(format!("no key {:X}", key).into()),
+        }
+    }
+
+    // A file in the granted directory; names may not leave it.
+    fn file(&self, name: &str) -> Fallible<PathBuf> {
+        let Some(dir) = &self.dir else {
+            return Err("scripts have no file access; grant a directory with --script-dir".into());
+        };
+        let path = Path::new(name);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("\"{}\" is outside the script directory", name).into());
+        }
+        Ok(dir.join(path))
+    }
+}
+
+struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    // A hook's `this`, kept between calls.
+    state: Dynamic,
+    hooks: Vec<&'static str>,
+    failed: bool,
+}
+
+// Rhai scripts given with --script, called on machine events. They run
+// sandboxed: no imports, no files outside the --script-dir directory and a
+// limit on how long each hook may take.
+pub struct Scripts {
+    machine: Rc<RefCell<Machine>>,
+    scripts: Vec<Script>,
+    // The keypad as on_key last reported it.
+    keys: [bool; 16],
+}
+
+impl Scripts {
+    pub fn load(paths: &[PathBuf], dir: Option<&Path>) -> Result<Scripts, String> {
+        let machine = Rc::new(RefCell::new(Machine {
+            dir: dir.map(Path::to_path_buf),
+            ..Machine::default()
+        }));
+        let mut scripts = Vec::new();
+        for path in paths {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string());
+            let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+            let source = fs::read_to_string(path).map_err(|e| error(&e))?;
+            let engine = engine(&machine);
+            let ast = engine.compile(&source).map_err(|e| error(&e))?;
+            let mut scope = Scope::new();
+            engine
+                .run_ast_with_scope(&mut scope, &ast)
+                .map_err(|e| error(&e))?;
+            let hooks = HOOKS
+                .into_iter()
+                .filter(|hook| ast.iter_functions().any(|f| f.name == *hook))
+                .collect();
+            scripts.push(Script {
+                name,
+                engine,
+                ast,
+                scope,
+                state: Map::new().into(),
+                hooks,
+                failed: false,
+            });
+        }
+        Ok(Scripts {
+            machine,
+            scripts,
+            keys: [false; 16],
+        })
+    }
+
+    // When a ROM has started.
+    pub fn start(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        self.machine.borrow_mut().frame = 0;
+        self.keys = *chip8.keypad.down();
+        self.call(chip8, "on_start", ())
+    }
+
+    // After each frame, before it is shown.
+    pub fn frame(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let result = self.call(chip8, "on_frame", ());
+        self.machine.borrow_mut().frame += 1;
+        result
+    }
+
+    // After each instruction, with where it was and any memory it wrote.
+    pub fn instruction(&mut self, chip8: &mut Chip8, pc: usize) -> Result<(), String> {
+        let instruction = self.call(chip8, "on_instruction", (pc as i64,));
+        let written = match chip8.access {
+            Some(MemoryAccess {
+                start,
+                len,
+                write: true,
+                ..
+            }) if self.wants("on_write") => (start..start + len).try_for_each(|address| {
+                let value = chip8.memory.get(address).copied().unwrap_or(0);
+                self.call(chip8, "on_write", (address as i64, value as i64))
+            }),
+            _ => Ok(()),
+        };
+        instruction.and(written)
+    }
+
+    // Reports the chip8 keys that went down or up since the last call.
+    pub fn keys(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let mut result = Ok(());
+        for key in 0..16 {
+            let down = chip8.keypad.is_down(key);
+            if down != self.keys[key] {
+                self.keys[key] = down;
+                result = result.and(self.call(chip8, "on_key", (key as i64, down)));
+            }
+        }
+        result
+    }
+
+    // Overlay text by position, in on-screen display units.
+    pub fn texts(&self) -> Vec<(i32, i32, String)> {
+        let machine = self.machine.borrow();
+        machine
+            .texts
+            .iter()
+            .map(|((x, y), text)| (*x as i32, *y as i32, text.clone()))
+            .collect()
+    }
+
+    // The exit code a script asked the emulator to quit with.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.machine.borrow().exit
+    }
+
+    fn wants(&self, hook: &str) -> bool {
+        self.scripts
+            .iter()
+            .any(|script| !script.failed && script.hooks.contains(&hook))
+    }
+
+    // Runs a hook in every script defining it. A script whose hook fails is
+    // not called again; the first failure is returned.
+    fn call(
+        &mut self,
+        chip8: &mut Chip8,
+        hook: &str,
+        args: impl FuncArgs + Clone,
+    ) -> Result<(), String> {
+        if !self.wants(hook) {
+            return Ok(());
+        }
+        self.lend(chip8);
+        let mut result = Ok(());
+        for script in &mut self.scripts {
+            if script.failed || !script.hooks.contains(&hook) {
+                continue;
+            }
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut script.state);
+            let called = script.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut script.scope,
+                &script.ast,
+                hook,
+                args.clone(),
+            );
+            if let Err(e) = called {
+                script.failed = true;
+                let message = format!("{}: {}: {}, script stopped", script.name, hook, e);
+                eprintln!("error: {}", message);
+                if result.is_ok() {
+                    result = Err(message);
+                }
+            }
+        }
+        self.take_back(chip8).and(result)
+    }
+
+    fn lend(&mut self, chip8: &mut Chip8) {
+        let mut machine = self.machine.borrow_mut();
+        machine.registry = chip8.registry;
+        machine.memory = std::mem::take(&mut chip8.memory);
+        machine.i = chip8.i;
+        machine.program_counter = chip8.program_counter;
+        machine.delay_timer = chip8.delay_timer;
+        machine.sound_timer = chip8.sound_timer;
+        machine.keys = *chip8.keypad.down();
+    }
+
+    fn take_back(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let mut machine = self.machine.borrow_mut();
+        chip8.registry = machine.registry;
+        chip8.memory = std::mem::take(&mut machine.memory);
+        chip8.i = machine.i;
+        chip8.program_counter = machine.program_counter;
+        chip8.delay_timer = machine.delay_timer;
+        chip8.sound_timer = machine.sound_timer;
+        for (key, down) in machine.key_changes.drain(..) {
+            chip8.keypad.set(key, down);
+        }
+        let screenshots = std::mem::take(&mut machine.screenshots);
+        screenshots
+            .iter()
+            .try_for_each(|path| save_screenshot(chip8, path))
+    }
+}
+
+// An engine with the machine functions and the sandbox's limits.
+fn engine(machine: &Rc<RefCell<Machine>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_ARRAY_SIZE);
+    engine.set_max_map_size(MAX_MAP_SIZE);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+
+    let m = machine.clone();
+    engine.register_fn("reg", move |x: i64| -> Fallible<i64> {
+        let machine = m.borrow();
+        Ok(machine.registry[machine.register(x)?] as i64)
+    });
+    let m = machine.clone();
+    engine.register_fn("set_reg", move |x: i64, value: i64| -> Fallible<()> {
+        let mut machine = m.borrow_mut();
+        let x = machine.register(x)?;
+        machine.registry[x] = value as u8;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("peek", move |address: i64| -> Fallible<i64> {
+        let machine = m.borrow();
+        Ok(machine.memory[machine.address(address)?] as i64)
+    });
+    let m = machine.clone();
+    engine.register_fn("poke", move |address: i64, value: i64| -> Fallible<()> {
+        let mut machine = m.borrow_mut();
+        let address = machine.address(address)?;
+        machine.memory[address] = value as u8;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("index", move || m.borrow().i as i64);
+    let m = machine.clone();
+    engine.register_fn("set_index", move |value: i64| {
+        m.borrow_mut().i = value as usize & 0xFFFF;
+    });
+    let m = machine.clone();
+    engine.register_fn("pc", move || m.borrow().program_counter as i64);
+    let m = machine.clone();
+    engine.register_fn("set_pc", move |value: i64| -> Fallible<()> {
+        let mut machine = m.borrow_mut();
+        machine.program_counter = machine.address(value)?;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("delay_timer", move || m.borrow().delay_timer as i64);
+    let m = machine.clone();
+    engine.register_fn("set_delay_timer", move |value: i64| {
+        m.borrow_mut().delay_timer = value as u8;
+    });
+    let m = machine.clone();
+    engine.register_fn("sound_timer", move || m.borrow().sound_timer as i64);
+    let m = machine.clone();
+    engine.register_fn("set_sound_timer", move |value: i64| {
+        m.borrow_mut().sound_timer = value as u8;
+    });
+    let m = machine.clone();
+    engine.register_fn("frame", move || m.borrow().frame);
+
+    let m = machine.clone();
+    engine.register_fn("key_down", move |key: i64| -> Fallible<bool> {
+        let machine = m.borrow();
+        Ok(machine.keys[machine.key(key)?])
+    });
+    for (name, down) in [("press", true), ("release", false)] {
+        let m = machine.clone();
+        engine.register_fn(name, move |key: i64| -> Fallible<()> {
+            let mut machine = m.borrow_mut();
+            let key = machine.key(key)?;
+            machine.keys[key] = down;
+            machine.key_changes.push((key, down));
+            Ok(())
+        });
+    }
+
+    let m = machine.clone();
+    engine.register_fn("text", move |x: i64, y: i64, text: &str| {
+        let mut machine = m.borrow_mut();
+        if text.is_empty() {
+            machine.texts.remove(&(x, y));
+        } else {
+            machine.texts.insert((x, y), text.to_string());
+        }
+    });
+    let m = machine.clone();
+    engine.register_fn("clear_text", move || m.borrow_mut().texts.clear());
+    let m = machine.clone();
+    engine.register_fn("quit", move |code: i64| {
+        m.borrow_mut().exit = Some(code as i32);
+    });
+
+    let m = machine.clone();
+    engine.register_fn("screenshot", move |name: &str| -> Fallible<()> {
+        let mut machine = m.borrow_mut();
+        let path = machine.file(name)?;
+        machine.screenshots.push(path);
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("read_file", move |name: &str| -> Fallible<String> {
+        let path = m.borrow().file(name)?;
+        fs::read_to_string(&path).map_err(|e| format!("{}: {}", name, e).into())
+    });
+    for (name, append) in [("write_file", false), ("append_file", true)] {
+        let m = machine.clone();
+        engine.register_fn(name, move |file: &str, text: &str| -> Fallible<()> {
+            let path = m.borrow().file(file)?;
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&path)
+                .and_then(|mut out| out.write_all(text.as_bytes()))
+                .map_err(|e| format!("{}: {}", file, e).into())
+        });
+    }
+    engine
+}
+
+// The screen as a GIF in the machine's colors, one pixel per chip8 pixel.
+fn save_screenshot(chip8: &Chip8, path: &Path) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+    let height = chip8.screen_height();
+    let mut pixels = Vec::with_capacity(64 * height);
+    for y in 0..height {
+        for x in 0..64 {
+            pixels.push(chip8.pixel_map[x][y]);
+        }
+    }
+    let palette: Vec<u8> = chip8
+        .palette
+        .iter()
+        .flat_map(|(r, g, b)| [*r, *g, *b])
+        .collect();
+    let file = File::create(path).map_err(|e| error(&e))?;
+    let mut encoder =
+        gif::Encoder::new(file, 64, height as u16, &palette).map_err(|e| error(&e))?;
+    let frame = gif::Frame::from_indexed_pixels(64, height as u16, pixels, None);
+    encoder.write_frame(&frame).map_err(|e| error(&e))
+}
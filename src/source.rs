@@ -0,0 +1,263 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::config::Settings;
+use crate::octo;
+use crate::quirks::QuirkOverrides;
+use crate::symbols::Symbols;
+
+// How a ROM was stored; recognised from the file's contents.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Binary,
+    Hex,
+    OctoSource,
+    OctoCart,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Binary => "binary",
+            Format::Hex => "hex text",
+            Format::OctoSource => "Octo source",
+            Format::OctoCart => "Octo cartridge",
+        }
+    }
+}
+
+pub struct Rom {
+    pub data: Vec<u8>,
+    pub format: Format,
+    // Settings stored with the program, such as a cartridge's colors.
+    pub options: Settings,
+    // Labels and lines when the program was assembled from source.
+    pub symbols: Symbols,
+}
+
+// Reads a ROM from a file, or from standard input when path is "-".
+pub fn read(path: &Path) -> Result<Rom, String> {
+    let data = if path == Path::new("-") {
+        stdin()?.to_vec()
+    } else {
+        fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?
+    };
+    decode(data, path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Standard input can only be read once, so it is kept for resets.
+fn stdin() -> Result<&'static [u8], String> {
+    static STDIN: OnceLock<Vec<u8>> = OnceLock::new();
+    if let Some(data) = STDIN.get() {
+        return Ok(data);
+    }
+    let mut data = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut data)
+        .map_err(|e| format!("stdin: {}", e))?;
+    Ok(STDIN.get_or_init(|| data))
+}
+
+fn decode(data: Vec<u8>, path: &Path) -> Result<Rom, String> {
+    if data.starts_with(b"GIF8") {
+        return read_cart(&data, path);
+    }
+
+    let rom = |data, format| Rom {
+        data,
+        format,
+        options: Settings::default(),
+        symbols: Symbols::default(),
+    };
+    let Ok(text) = std::str::from_utf8(&data) else {
+        return Ok(rom(data, Format::Binary));
+    };
+    if path.extension().is_some_and(|ext| ext == "8o") || text.contains(": main") {
+        let program = octo::assemble(text)?;
+        return Ok(Rom {
+            symbols: symbols(&program, path),
+            ..rom(program.data, Format::OctoSource)
+        });
+    }
+    match parse_hex(text) {
+        Some(bytes) => Ok(rom(bytes, Format::Hex)),
+        None => Ok(rom(data, Format::Binary)),
+    }
+}
+
+// Bytes written out as hex, like "00E0 A22A 600C", with optional 0x
+// prefixes, commas and # comments. Anything else is not hex text.
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for line in text.lines() {
+        let code = line.split('#').next().unwrap_or_default();
+        for token in code.split(|c: char| c.is_whitespace() || c == ',') {
+            let digits = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            for n in (0..digits.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(&digits[n..n + 2], 16).ok()?);
+            }
+        }
+    }
+    (!bytes.is_empty()).then_some(bytes)
+}
+
+#[derive(Deserialize)]
+struct Cart {
+    program: String,
+    #[serde(default)]
+    options: CartOptions,
+}
+
+// The subset of Octo's options this emulator has an equivalent for.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CartOptions {
+    tickrate: Option<u32>,
+    fill_color: Option<String>,
+    background_color: Option<String>,
+    shift_quirks: Option<bool>,
+    load_store_quirks: Option<bool>,
+    clip_quirks: Option<bool>,
+    jump_quirks: Option<bool>,
+    v_blank_quirks: Option<bool>,
+    logic_quirks: Option<bool>,
+}
+
+impl CartOptions {
+    // Octo's quirk flags name the departures from the original interpreter,
+    // so some of them are the inverse of ours.
+    fn settings(&self) -> Settings {
+        let quirks = QuirkOverrides {
+            vf_reset: self.logic_quirks,
+            memory_increment: self.load_store_quirks.map(|on| !on),
+            display_wait: self.v_blank_quirks,
+            clipping: self.clip_quirks,
+            shift_vy: self.shift_quirks.map(|on| !on),
+            jump_vx: self.jump_quirks,
+        };
+        let palette = match (&self.background_color, &self.fill_color) {
+            (Some(background), Some(fill)) => Some([background.clone(), fill.clone()]),
+            _ => None,
+        };
+        let settings = Settings {
+            ipf: self.tickrate.filter(|ipf| *ipf > 0),
+            palette,
+            quirks: Some(quirks),
+            ..Settings::default()
+        };
+        // Colors Octo accepts but #RRGGBB does not describe are left out.
+        match settings.palette() {
+            Some(_) => settings,
+            None => Settings {
+                palette: None,
+                ..settings
+            },
+        }
+    }
+}
+
+// Octo cartridges carry their payload in the low nibble of each pixel's
+// palette index, two pixels per byte with the high nibble first, running
+// on from frame to frame. The payload is a 32-bit big-endian length and
+// then that many bytes of JSON holding the program source and options.
+fn read_cart(data: &[u8], path: &Path) -> Result<Rom, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(data).map_err(|e| e.to_string())?;
+
+    let mut nibbles = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+        nibbles.extend(frame.buffer.iter().map(|index| index & 0xF));
+    }
+    let payload: Vec<u8> = nibbles
+        .chunks_exact(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect();
+
+    let not_a_cart = || "GIF is not an Octo cartridge".to_string();
+    let length = payload
+        .get(..4)
+        .map(|n| u32::from_be_bytes([n[0], n[1], n[2], n[3]]) as usize)
+        .ok_or_else(not_a_cart)?;
+    let json = payload
+        .get(4..)
+        .and_then(|rest| rest.get(..length))
+        .ok_or_else(not_a_cart)?;
+    let cart: Cart = serde_json::from_slice(json).map_err(|_| not_a_cart())?;
+
+    let program = octo::assemble(&cart.program)?;
+    Ok(Rom {
+        symbols: symbols(&program, path),
+        data: program.data,
+        format: Format::OctoCart,
+        options: cart.options.settings(),
+    })
+}
+
+// Source lines are given against the file the program came from.
+fn symbols(program: &octo::Program, path: &Path) -> Symbols {
+    let file = match path.file_name() {
+        Some(name) if path != Path::new("-") => name.to_string_lossy().into_owned(),
+        _ => "stdin".to_string(),
+    };
+    Symbols::from_octo(&program.labels, &program.lines, &file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cartridge as Octo saves one, with the payload in the pixels.
+    fn cart(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        let pixels: Vec<u8> = payload.iter().flat_map(|b| [b >> 4, b & 0xF]).collect();
+        let palette: Vec<u8> = (0..16u8).flat_map(|n| [n * 16, n * 16, n * 16]).collect();
+
+        let mut data = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut data, pixels.len() as u16, 1, &palette).unwrap();
+        let frame = gif::Frame::from_indexed_pixels(pixels.len() as u16, 1, pixels, None);
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        data
+    }
+
+    #[test]
+    fn carts_hold_a_program_and_its_options() {
+        let json = r##"{"program": ": main\n  v0 := 1", "options": {"tickrate": 20,
+            "fillColor": "#FF0000", "backgroundColor": "#000000", "shiftQuirks": true}}"##;
+        let rom = decode(cart(json), Path::new("game.gif")).unwrap();
+        assert!(rom.format == Format::OctoCart);
+        assert_eq!(rom.data, [0x12, 0x02, 0x60, 0x01]);
+        assert_eq!(rom.options.ipf, Some(20));
+        assert!(rom.options.palette().is_some());
+        assert_eq!(rom.options.quirks.unwrap().shift_vy, Some(false));
+    }
+
+    #[test]
+    fn other_gifs_are_not_carts() {
+        let error = decode(cart("not json"), Path::new("picture.gif"))
+            .err()
+            .unwrap();
+        assert_eq!(error, "GIF is not an Octo cartridge");
+    }
+
+    #[test]
+    fn text_is_hex_or_octo_source() {
+        let hex = decode(b"00E0 0xA22A # clear\n".to_vec(), Path::new("a.hex")).unwrap();
+        assert_eq!(hex.data, [0x00, 0xE0, 0xA2, 0x2A]);
+        let source = decode(b": main\n  clear".to_vec(), Path::new("a.txt")).unwrap();
+        assert!(source.format == Format::OctoSource);
+        assert_eq!(source.data, [0x12, 0x02, 0x00, 0xE0]);
+    }
+}
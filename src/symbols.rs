@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::{parse_number, Opcode};
+
+// Names and source lines for addresses, from the Octo assembler or a
+// symbol file.
+#[derive(Clone, Default)]
+pub struct Symbols {
+    names: BTreeMap<usize, String>,
+    addresses: HashMap<String, usize>,
+    // Like "game.8o:12".
+    lines: BTreeMap<usize, String>,
+}
+
+impl Symbols {
+    // Labels and source lines from an assembled Octo file.
+    pub fn from_octo(
+        labels: &HashMap<String, usize>,
+        lines: &BTreeMap<usize, usize>,
+        file: &str,
+    ) -> Symbols {
+        let mut symbols = Symbols::default();
+        for (name, address) in labels {
+            symbols.insert(*address, name);
+        }
+        for (address, line) in lines {
+            symbols.lines.insert(*address, format!("{}:{}", file, line));
+        }
+        symbols
+    }
+
+    // A symbol file has an address and then a name or a source location
+    // on each line, with # comments:
+    //
+    //   0x2A4 draw_player
+    //   0x2A4 game.8o:12
+    //
+    // Its entries are added to any the ROM already has.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (n, line) in text.lines().enumerate() {
+            let error = |message: String| format!("{}:{}: {}", path.display(), n + 1, message);
+            let code = line.split('#').next().unwrap_or_default();
+            let mut words = code.split_whitespace();
+            let Some(address) = words.next() else {
+                continue;
+            };
+            let address = parse_number(address).map_err(error)?;
+            let (Some(name), None) = (words.next(), words.next()) else {
+                return Err(error("expected an address and a name".to_string()));
+            };
+            let is_line = name.rsplit_once(':').is_some_and(|(_, line)| {
+                !line.is_empty() && line.chars().all(|c| c.is_ascii_digit())
+            });
+            if is_line {
+                self.lines.insert(address, name.to_string());
+            } else {
+                self.insert(address, name);
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, address: usize, name: &str) {
+        self.names.insert(address, name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.addresses.get(name).copied()
+    }
+
+    // A number, or a name this table knows with an optional offset, like
+    // "draw_player+4".
+    pub fn resolve(&self, text: &str) -> Option<usize> {
+        if let Ok(address) = parse_number(text) {
+            return Some(address);
+        }
+        if let Some(address) = self.address(text) {
+            return Some(address);
+        }
+        let (name, offset) = text.rsplit_once('+')?;
+        Some(self.address(name)? + parse_number(offset).ok()?)
+    }
+
+    // The nearest name at or before address, like "draw_player+4".
+    pub fn name(&self, address: usize) -> Option<String> {
+        let (start, name) = self.names.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => name.clone(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+
+    // The name given to exactly this address.
+    pub fn label(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    pub fn line(&self, address: usize) -> Option<&str> {
+        self.lines.get(&address).map(String::as_str)
+    }
+
+    // The file and line the instruction at address came from.
+    pub fn location(&self, address: usize) -> Option<(&str, usize)> {
+        split_line(self.lines.get(&address)?)
+    }
+
+    // The first instruction on a source line, or on the next line that has
+    // one, with the line it is on. The file may be a longer path ending in
+    // the name the symbols know.
+    pub fn line_address(&self, file: &Path, line: usize) -> Option<(usize, usize)> {
+        self.lines
+            .iter()
+            .filter_map(|(address, text)| Some((*address, split_line(text)?)))
+            .filter(|(_, (name, at))| *at >= line && file.ends_with(name))
+            .map(|(address, (_, at))| (address, at))
+            .min_by_key(|(address, at)| (*at, *address))
+    }
+
+    // An address with whatever is known about it, like
+    // "0x2A4 (draw_player, game.8o:12)".
+    pub fn describe(&self, address: usize) -> String {
+        let known: Vec<String> = self
+            .name(address)
+            .into_iter()
+            .chain(self.line(address).map(str::to_string))
+            .collect();
+        if known.is_empty() {
+            format!("0x{:03X}", address)
+        } else {
+            format!("0x{:03X} ({})", address, known.join(", "))
+        }
+    }
+
+    // The opcode's assembly with its address operand named when a label
+    // starts there, like "CALL draw_player".
+    pub fn mnemonic(&self, opcode: &Opcode) -> String {
+        let mnemonic = opcode.mnemonic();
+        match opcode
+            .target()
+            .and_then(|target| Some((target, self.label(target)?)))
+        {
+            Some((target, name)) => mnemonic.replace(&format!("#{:03X}", target), name),
+            None => mnemonic,
+        }
+    }
+}
+
+// "game.8o:12" as its file and line.
+fn split_line(text: &str) -> Option<(&str, usize)> {
+    let (file, line) = text.rsplit_once(':')?;
+    Some((file, line.parse().ok()?))
+}
@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const INTERVAL: Duration = Duration::from_millis(250);
+
+// Polls a file's size and modification time. A change is only reported
+// once the file has stayed the same for a whole interval, so a ROM that
+// is still being written is not loaded half-way.
+pub struct Watcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    pending: Option<(SystemTime, u64)>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> Watcher {
+        Watcher {
+            path: path.to_path_buf(),
+            stamp: stamp(path),
+            pending: None,
+            last_check: Instant::now(),
+        }
+    }
+
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        // Missing while an editor or build replaces it; wait for it to return.
+        let Some(current) = stamp(&self.path) else {
+            return false;
+        };
+        if Some(current) == self.stamp {
+            self.pending = None;
+            return false;
+        }
+        if self.pending != Some(current) {
+            self.pending = Some(current);
+            return false;
+        }
+
+        self.stamp = Some(current);
+        self.pending = None;
+        true
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,280 @@
+// Drives `chip8 --headless --dap 0` over a socket the way an editor would.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/2-ibm-logo.ch8");
+
+// Assembles to 1202 600C 220A 6103 1208 7001 00EE from 0x200.
+const PROGRAM: &str = ": main
+  v0 := 12
+  add_one
+  v1 := 3
+  loop again
+: add_one
+  v0 += 1
+  return
+";
+
+struct Client {
+    process: Child,
+    stream: BufReader<TcpStream>,
+    seq: u64,
+    events: VecDeque<Value>,
+}
+
+impl Client {
+    fn start(args: &[&str]) -> Client {
+        let mut process = Command::new(env!("CARGO_BIN_EXE_chip8"))
+            .args(["--headless", "--dap", "0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(process.stdout.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let address = line.trim().rsplit(' ').next().unwrap().to_string();
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            process,
+            stream: BufReader::new(stream),
+            seq: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn read(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stream.read_line(&mut line).unwrap();
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.stream.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    // Sends a request and returns its response, keeping events for later.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let body = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(
+            self.stream.get_mut(),
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        loop {
+            let message = self.read();
+            if message["type"] == "response" && message["request_seq"] == self.seq {
+                return message;
+            }
+            self.events.push_back(message);
+        }
+    }
+
+    fn body(&mut self, command: &str, arguments: Value) -> Value {
+        let response = self.request(command, arguments);
+        assert_eq!(response["success"], true, "{}", response);
+        response["body"].clone()
+    }
+
+    fn event(&mut self, name: &str) -> Value {
+        loop {
+            let message = match self.events.pop_front() {
+                Some(message) => message,
+                None => self.read(),
+            };
+            if message["event"] == name {
+                return message["body"].clone();
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) {
+        assert_eq!(self.event("stopped")["reason"], reason);
+    }
+
+    fn frames(&mut self) -> Vec<Value> {
+        let body = self.body("stackTrace", json!({ "threadId": 1 }));
+        body["stackFrames"].as_array().unwrap().clone()
+    }
+
+    fn register(&mut self, name: &str) -> String {
+        let body = self.body("variables", json!({ "variablesReference": 1 }));
+        let variables = body["variables"].as_array().unwrap();
+        let variable = variables.iter().find(|v| v["name"] == name).unwrap();
+        variable["value"].as_str().unwrap().to_string()
+    }
+
+    // Launches an Octo program written to a scratch file.
+    fn launch(&mut self, name: &str, breakpoints: &[u64]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("chip8-dap-{}-{}.8o", name, std::process::id()));
+        fs::write(&path, PROGRAM).unwrap();
+        self.body("initialize", json!({ "adapterID": "chip8" }));
+        self.body("launch", json!({ "program": path, "stopOnEntry": true }));
+        self.event("initialized");
+        let lines: Vec<Value> = breakpoints
+            .iter()
+            .map(|line| json!({ "line": line }))
+            .collect();
+        let body = self.body(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": lines }),
+        );
+        for breakpoint in body["breakpoints"].as_array().unwrap() {
+            assert_eq!(breakpoint["verified"], true);
+        }
+        self.body("configurationDone", json!({}));
+        self.stopped("entry");
+        path
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[test]
+fn stops_at_source_breakpoints_and_shows_state() {
+    let mut client = Client::start(&[]);
+    let path = client.launch("breakpoints", &[7]);
+
+    // The jump to main comes first.
+    let frames = client.frames();
+    assert_eq!(frames[0]["instructionPointerReference"], "0x200");
+    let body = client.body(
+        "setInstructionBreakpoints",
+        json!({ "breakpoints": [{ "instructionReference": "add_one", "offset": 2 }] }),
+    );
+    assert_eq!(body["breakpoints"][0]["line"], 8);
+
+    client.body("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    let frames = client.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["name"], "add_one");
+    assert_eq!(frames[0]["line"], 7);
+    assert_eq!(frames[0]["source"]["path"], json!(path));
+    assert_eq!(frames[1]["name"], "main+2");
+    assert_eq!(frames[1]["line"], 3);
+    assert_eq!(client.register("V0"), "0x0C (12)");
+    let stack = client.body("variables", json!({ "variablesReference": 2 }));
+    let call = stack["variables"][0]["value"].as_str().unwrap();
+    assert!(call.starts_with("0x204 (main+2, "), "{}", call);
+
+    client.body("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    assert!(client.register("PC").starts_with("0x20C (add_one+2, "));
+    assert_eq!(client.register("V0"), "0x0D (13)");
+
+    client.body("stepOut", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.frames()[0]["line"], 4);
+    assert_eq!(client.register("V0"), "0x0D (13)");
+
+    client.body("disconnect", json!({}));
+    assert!(client.process.wait().unwrap().success());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn steps_over_calls() {
+    let mut client = Client::start(&[]);
+    let path = client.launch("steps", &[]);
+
+    client.body("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.frames()[0]["line"], 2);
+    client.body("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.frames()[0]["line"], 3);
+    client.body("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.frames()[0]["line"], 4);
+    assert_eq!(client.register("V0"), "0x0D (13)");
+    client.body("stepIn", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.register("V1"), "0x03 (3)");
+
+    let body = client.body(
+        "disassemble",
+        json!({ "memoryReference": "0x200", "instructionCount": 3 }),
+    );
+    assert_eq!(body["instructions"][2]["instruction"], "CALL add_one");
+    assert_eq!(body["instructions"][2]["line"], 3);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn attaches_to_a_running_rom() {
+    let mut client = Client::start(&[ROM]);
+    client.body("initialize", json!({ "adapterID": "chip8" }));
+    client.body("attach", json!({ "stopOnEntry": true }));
+    client.event("initialized");
+    client.body("configurationDone", json!({}));
+    client.stopped("entry");
+    let body = client.body("evaluate", json!({ "expression": "[0x201]" }));
+    assert_eq!(body["result"], "0xE0 (224)");
+
+    let body = client.body(
+        "readMemory",
+        json!({ "memoryReference": "0x200", "count": 4 }),
+    );
+    assert_eq!(body["data"], "AOCiKg==");
+    client.body(
+        "writeMemory",
+        json!({ "memoryReference": "0x300", "data": "q80=" }),
+    );
+    let body = client.body(
+        "readMemory",
+        json!({ "memoryReference": "0x2FF", "offset": 1, "count": 2 }),
+    );
+    assert_eq!(body["data"], "q80=");
+
+    client.body("continue", json!({ "threadId": 1 }));
+    client.body("pause", json!({ "threadId": 1 }));
+    client.stopped("pause");
+
+    // Detaching from a program the client did not launch leaves it running.
+    client.body("disconnect", json!({}));
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(client.process.try_wait().unwrap().is_none());
+}
+
+#[test]
+fn attach_needs_a_running_rom() {
+    let mut client = Client::start(&[]);
+    let response = client.request("attach", json!({}));
+    assert_eq!(response["success"], false);
+}
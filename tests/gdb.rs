@@ -0,0 +1,227 @@
+// Drives `chip8 --headless --gdb 0` over a socket the way a GDB client would.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/2-ibm-logo.ch8");
+
+struct Client {
+    process: Child,
+    stream: TcpStream,
+}
+
+impl Client {
+    fn start() -> Client {
+        Client::start_with(&[])
+    }
+
+    fn start_with(args: &[&str]) -> Client {
+        let mut process = Command::new(env!("CARGO_BIN_EXE_chip8"))
+            .args(["--headless", "--gdb", "0"])
+            .args(args)
+            .arg(ROM)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(process.stdout.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let address = line.trim().rsplit(' ').next().unwrap().to_string();
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client { process, stream }
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+        assert_eq!(self.byte(), b'+');
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn reply(&mut self) -> String {
+        while self.byte() != b'$' {}
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.byte(), self.byte()];
+        let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", sum)
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn ask(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+
+    fn pc(&mut self) -> String {
+        self.ask("p11")
+    }
+
+    // A monitor command and its output, both hex encoded on the wire.
+    fn monitor(&mut self, command: &str) -> String {
+        let hex: String = command.bytes().map(|b| format!("{:02x}", b)).collect();
+        let reply = self.ask(&format!("qRcmd,{}", hex));
+        let bytes: Vec<u8> = (0..reply.len())
+            .step_by(2)
+            .map(|n| u8::from_str_radix(&reply[n..n + 2], 16).unwrap())
+            .collect();
+        String::from_utf8(bytes).unwrap()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[test]
+fn stops_on_attach_with_registers_at_reset() {
+    let mut client = Client::start();
+    assert_eq!(client.ask("?"), "S05");
+    let registers = client.ask("g");
+    // V0-VF, I and PC (little-endian), SP, DT and ST.
+    assert_eq!(
+        registers,
+        format!("{}{}{}{}", "00".repeat(16), "0000", "0002", "000000")
+    );
+    assert!(client.ask("qSupported").contains("qXfer:features:read+"));
+    assert!(client
+        .ask("qXfer:features:read:target.xml:0,fff")
+        .contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+}
+
+#[test]
+fn reads_and_writes_memory_and_registers() {
+    let mut client = Client::start();
+    assert_eq!(client.ask("m200,4"), "00e0a22a");
+    assert_eq!(client.ask("M300,2:abcd"), "OK");
+    assert_eq!(client.ask("m300,2"), "abcd");
+    assert_eq!(client.ask("P10=0003"), "OK");
+    assert_eq!(client.ask("p10"), "0003");
+    assert_eq!(client.ask("P3=7f"), "OK");
+    assert_eq!(client.ask("p3"), "7f");
+    assert_eq!(client.ask("mffff,2"), "E01");
+}
+
+#[test]
+fn steps_and_continues_to_breakpoints() {
+    let mut client = Client::start();
+    assert_eq!(client.ask("s"), "S05");
+    assert_eq!(client.pc(), "0202");
+
+    assert_eq!(client.ask("Z0,208,2"), "OK");
+    client.send("c");
+    assert_eq!(client.reply(), "S05");
+    assert_eq!(client.pc(), "0802");
+    assert_eq!(client.ask("p0"), "0c");
+    assert_eq!(client.ask("p1"), "08");
+
+    // The draw at the breakpoint runs when stepped.
+    assert_eq!(client.ask("s"), "S05");
+    assert_eq!(client.pc(), "0a02");
+
+    assert_eq!(client.ask("z0,208,2"), "OK");
+    assert_eq!(client.ask("Z0,20c,2"), "OK");
+    client.send("c");
+    assert_eq!(client.reply(), "S05");
+    assert_eq!(client.pc(), "0c02");
+}
+
+#[test]
+fn steps_are_traced_like_any_other_instruction() {
+    let path = std::env::temp_dir().join(format!("chip8-gdb-trace-{}.txt", std::process::id()));
+    let mut client = Client::start_with(&["--trace", path.to_str().unwrap()]);
+    // The program may have run a little before the client attached.
+    let mut stepped = Vec::new();
+    for _ in 0..2 {
+        // Registers are little-endian on the wire.
+        let pc = client.pc();
+        let pc = u16::from_str_radix(&format!("{}{}", &pc[2..], &pc[..2]), 16).unwrap();
+        stepped.push(format!("0x{:03X}", pc));
+        assert_eq!(client.ask("s"), "S05");
+    }
+    client.send("k");
+    assert!(client.process.wait().unwrap().success());
+    let trace = std::fs::read_to_string(&path).unwrap();
+    let addresses: Vec<String> = trace.lines().map(|line| line[..5].to_string()).collect();
+    assert!(
+        addresses.ends_with(&stepped),
+        "{:?} after {:?}",
+        stepped,
+        addresses
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn kill_ends_the_emulator() {
+    let mut client = Client::start();
+    client.send("k");
+    assert!(client.process.wait().unwrap().success());
+}
+
+#[test]
+fn monitor_commands_find_and_freeze_values() {
+    let path = std::env::temp_dir().join(format!("chip8-cheats-{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut client = Client::start_with(&["--cheats", path.to_str().unwrap()]);
+    assert!(client.monitor("search").starts_with("searching 4096 bytes"));
+    assert_eq!(client.ask("M300,1:05"), "OK");
+    assert_eq!(client.monitor("search changed"), "1 candidates:\n0x300=5\n");
+    assert_eq!(client.ask("M300,1:03"), "OK");
+    assert_eq!(client.monitor("search increased"), "0 candidates\n");
+
+    assert_eq!(client.monitor("freeze lives 0x300=9"), "froze 0x300 at 9\n");
+    assert_eq!(client.monitor("freeze V1=66"), "froze V1 at 66\n");
+    // The draws wait for frames, which put the values back.
+    assert_eq!(client.ask("Z0,228,2"), "OK");
+    client.send("c");
+    assert_eq!(client.reply(), "S05");
+    assert_eq!(client.ask("m300,1"), "09");
+    assert_eq!(client.ask("p1"), "42");
+    assert!(client.monitor("cheats save").starts_with("saved 2 cheats"));
+    assert!(client.monitor("unknown").contains("search increased"));
+    drop(client);
+
+    let mut client = Client::start_with(&["--cheats", path.to_str().unwrap()]);
+    assert_eq!(
+        client.monitor("cheats"),
+        "cheats on:\nV1  V1=66\nlives  0x300=9\n"
+    );
+    assert_eq!(client.monitor("unfreeze lives"), "removed lives\n");
+    assert_eq!(client.monitor("cheats off"), "cheats off\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn faults_stop_the_target() {
+    let mut client = Client::start();
+    assert_eq!(client.ask("M200,2:0123"), "OK");
+    assert_eq!(client.ask("s"), "S04");
+    assert_eq!(client.pc(), "0002");
+    client.send("c");
+    assert_eq!(client.reply(), "S04");
+    assert_eq!(client.pc(), "0002");
+}
@@ -0,0 +1,142 @@
+// Runs `chip8 lint` on small ROMs written as hex.
+
+use std::fs;
+use std::process::{Command, Output};
+
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/2-ibm-logo.ch8");
+
+fn lint(name: &str, hex: &str) -> Output {
+    lint_with(name, hex, &[])
+}
+
+fn lint_with(name: &str, hex: &str, args: &[&str]) -> Output {
+    let path = std::env::temp_dir().join(format!("chip8-lint-{}-{}.hex", name, std::process::id()));
+    fs::write(&path, hex).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .arg("lint")
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(path).unwrap();
+    output
+}
+
+fn lint_file(path: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .args(["lint", path])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn clean_rom_passes() {
+    let output = lint_file(ROM);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+    assert!(
+        stdout.contains("0 errors, 0 warnings, 0 notes"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn reports_portability_problems() {
+    let output = lint(
+        "problems",
+        "6A3C 6B00 A218 DAB1  # a sprite at x 60
+         8016                 # shift V1 into V0
+         A200 F055 F165       # store over 0x200, then use I
+         A300 DBB4            # draw from past the end
+         0123 1216 FF00",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    for expected in [
+        "0x206  warning  sprite at (60, 0) crosses the right edge",
+        "0x208  warning  shifts V0 on SCHIP but V1 on CHIP-8",
+        "0x20C  warning  writes into the instruction at 0x200",
+        "0x20E  warning  uses I as left by the load or store at 0x20C",
+        "0x212  warning  reads 0x300-0x303, past the end of the ROM at 0x219",
+        "0x214  error    machine code call to 0x123",
+        "1 errors, 5 warnings, 2 notes",
+    ] {
+        assert!(stdout.contains(expected), "{} not in\n{}", expected, stdout);
+    }
+}
+
+#[test]
+fn blank_sprite_columns_may_cross_edges() {
+    let output = lint("blank", "6A3C 6B00 A20A DAB1 1208 F000");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+}
+
+#[test]
+fn reports_deep_and_recursive_calls() {
+    // Each instruction calls the next, 17 deep.
+    let calls: Vec<String> = (1..=17)
+        .map(|n| format!("{:04X}", 0x2200 + 2 * n))
+        .collect();
+    let output = lint("deep", &format!("{} 00EE", calls.join(" ")));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("0x200  error    calls nest 17 deep"),
+        "{}",
+        stdout
+    );
+
+    let output = lint("recursive", "6001 2200");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("0x202  warning  recursive call to 0x200"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn uses_the_configured_start_address() {
+    // I := 0x604, store V0 there, loop: a write over the jump when loaded
+    // at 0x600, past the end of the ROM when loaded at 0x200.
+    let rom = "A604 F055 1602";
+    let output = lint_with("start", rom, &["--start", "0x600"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("0x602  warning  writes into the instruction at 0x604"),
+        "{}",
+        stdout
+    );
+
+    let output = lint_with("eti660", rom, &["--platform", "eti660"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("writes into the instruction"), "{}", stdout);
+
+    let output = lint("default", rom);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        !stdout.contains("writes into the instruction"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn hires_roms_start_after_the_patch_on_any_platform() {
+    // The jump into the hi-res patch, then code at 0x2C0 that draws on row
+    // 40 and uses one SCHIP instruction, Fx30.
+    let rom = format!(
+        "1260 {} 6000 6128 A2CC D011 F030 12CA FF00",
+        "0000 ".repeat(95)
+    );
+    let output = lint("hires", &rom);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("0 errors, 0 warnings"), "{}", stdout);
+    assert!(stdout.contains("detected CHIP-8"), "{}", stdout);
+
+    let output = lint_with("hires-schip", &rom, &["--platform", "schip"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+}
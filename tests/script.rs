@@ -0,0 +1,154 @@
+// Runs `chip8 --headless --script ...` the way a bot test would.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/2-ibm-logo.ch8");
+
+// A scratch directory for one test's script, ROM and output.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chip8-script-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .arg("--headless")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn script_reads_the_machine_and_saves_files() {
+    let dir = scratch("files");
+    let script = dir.join("bot.rhai");
+    fs::write(
+        &script,
+        r#"
+fn on_start() { this.instructions = 0; }
+fn on_instruction(pc) { this.instructions += 1; }
+fn on_frame() {
+    if frame() == 30 {
+        screenshot("ibm.gif");
+        write_file("result.txt", `${index()} ${this.instructions > 0}`);
+        quit(if peek(0x201) == 0xE0 { 3 } else { 4 });
+    }
+}
+"#,
+    )
+    .unwrap();
+    let output = run(&[
+        "--script",
+        script.to_str().unwrap(),
+        "--script-dir",
+        dir.to_str().unwrap(),
+        ROM,
+    ]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    // The last sprite drawn leaves I at 0x275.
+    assert_eq!(
+        fs::read_to_string(dir.join("result.txt")).unwrap(),
+        "629 true"
+    );
+    assert!(fs::read(dir.join("ibm.gif"))
+        .unwrap()
+        .starts_with(b"GIF89a"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn script_presses_keys_and_sees_writes() {
+    let dir = scratch("keys");
+    // V0 := 5, I := 0x300, wait for a key into V0, store V0 at I, loop.
+    let rom = dir.join("keys.hex");
+    fs::write(&rom, "6005 A300 F00A F055 1208").unwrap();
+    let script = dir.join("keys.rhai");
+    fs::write(
+        &script,
+        r#"
+fn on_start() { this.keys = 0; }
+fn on_frame() {
+    if frame() == 2 { press(7); }
+    if frame() == 4 { release(7); }
+}
+fn on_key(key, down) { this.keys += 1; }
+fn on_write(address, value) {
+    if address == 0x300 { quit(value + 16 * this.keys); }
+}
+"#,
+    )
+    .unwrap();
+    let output = run(&["--script", script.to_str().unwrap(), rom.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(7 + 16 * 2), "{:?}", output);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn scripts_have_no_file_access_unless_granted() {
+    let dir = scratch("sandbox");
+    let script = dir.join("snoop.rhai");
+    fs::write(&script, r#"print(read_file("snoop.rhai"));"#).unwrap();
+    let output = run(&["--script", script.to_str().unwrap(), ROM]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no file access"));
+
+    fs::write(&script, r#"write_file("../escape.txt", "");"#).unwrap();
+    let output = run(&[
+        "--script",
+        script.to_str().unwrap(),
+        "--script-dir",
+        dir.to_str().unwrap(),
+        ROM,
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("outside the script directory"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn key_edges_end_with_the_frame() {
+    let dir = scratch("edges");
+    // Waits 10 frames on the delay timer, then for a key into V0, stores
+    // V0 at 0x300 and stops. Key 3 went down and up long before the wait.
+    let rom = dir.join("edges.hex");
+    fs::write(&rom, "6A0A FA15 FB07 3B00 1204 A300 F00A F055 1210").unwrap();
+    let script = dir.join("edges.rhai");
+    fs::write(
+        &script,
+        r#"
+fn on_frame() {
+    if frame() == 1 { press(3); }
+    if frame() == 2 { release(3); }
+    if frame() == 20 { press(7); }
+    if frame() == 21 { release(7); }
+    if frame() == 120 { quit(99); }
+}
+fn on_write(address, value) {
+    if address == 0x300 { quit(value); }
+}
+"#,
+    )
+    .unwrap();
+    let output = run(&["--script", script.to_str().unwrap(), rom.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(7), "{:?}", output);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn scripts_cannot_use_up_memory() {
+    let dir = scratch("limits");
+    let script = dir.join("greedy.rhai");
+    fs::write(&script, r#"let s = "x"; loop { s += s; }"#).unwrap();
+    let output = run(&["--script", script.to_str().unwrap(), ROM]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("string"),
+        "{:?}",
+        output
+    );
+    fs::remove_dir_all(dir).unwrap();
+}